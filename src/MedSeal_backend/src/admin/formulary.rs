@@ -0,0 +1,441 @@
+use ic_cdk::api::caller;
+use serde::{Deserialize, Serialize};
+use crate::shared::types::*;
+use crate::shared::storage as storage;
+use crate::shared::utils as utils;
+use crate::shared::dosage;
+use crate::shared::auth::require_admin_permission;
+use crate::doctor::medicines::clean_name_list;
+
+/// Rows accepted per import call and returned per export page; larger
+/// catalogs are sent in chunks.
+const MAX_BATCH_ROWS: usize = 500;
+
+/// Full seven-character WHO ATC code, e.g. "N02BE01".
+pub fn is_valid_atc_code(code: &str) -> bool {
+    let chars: Vec<char> = code.chars().collect();
+    chars.len() == 7
+        && chars[0].is_ascii_uppercase()
+        && chars[1..3].iter().all(|c| c.is_ascii_digit())
+        && chars[3..5].iter().all(|c| c.is_ascii_uppercase())
+        && chars[5..7].iter().all(|c| c.is_ascii_digit())
+}
+
+fn validate_formulary_request(request: &FormularyMedicineRequest) -> Result<()> {
+    if request.generic_name.trim().is_empty() {
+        return Err("Generic name is required".to_string());
+    }
+
+    if !is_valid_atc_code(request.atc_code.trim()) {
+        return Err("ATC code must look like N02BE01".to_string());
+    }
+
+    if clean_name_list(request.active_ingredients.clone()).is_empty() {
+        return Err("At least one active ingredient is required".to_string());
+    }
+
+    if request.side_effects.trim().is_empty() {
+        return Err("Side effects information is required".to_string());
+    }
+
+    dosage::validate_dosage(&request.dosage)
+}
+
+fn requested_code(request: &FormularyMedicineRequest) -> Option<String> {
+    request.external_code
+        .as_deref()
+        .map(str::trim)
+        .filter(|code| !code.is_empty())
+        .map(str::to_string)
+}
+
+fn build_formulary_medicine(id: String, external_code: String, request: FormularyMedicineRequest, created_at: u64, created_by: String, is_active: bool) -> Medicine {
+    let generic_name = request.generic_name.trim().to_string();
+    Medicine {
+        id,
+        name: generic_name.clone(),
+        dosage: dosage::render_strength(&request.dosage),
+        frequency: dosage::render_frequency(&request.dosage.frequency),
+        duration: dosage::render_duration(request.dosage.duration_days),
+        side_effects: request.side_effects,
+        guide_text: request.guide_text.clone(),
+        guide_source: request.guide_source,
+        description: request.guide_text,
+        created_at,
+        created_by,
+        // Formulary entries belong to the platform, not to a doctor
+        doctor_id: String::new(),
+        is_active,
+        active_ingredients: clean_name_list(request.active_ingredients),
+        dosage_spec: Some(request.dosage),
+        formulary: Some(FormularyDetails {
+            generic_name,
+            brand_names: clean_name_list(request.brand_names),
+            atc_code: request.atc_code.trim().to_string(),
+            external_code: Some(external_code),
+        }),
+        current_revision: None, // assigned by storage
+    }
+}
+
+fn external_code_of(medicine: &Medicine) -> String {
+    medicine.formulary
+        .as_ref()
+        .and_then(|details| details.external_code.clone())
+        .unwrap_or_else(|| medicine.id.clone())
+}
+
+fn create_formulary_entry(request: FormularyMedicineRequest, created_by: String, is_active: bool) -> Result<Medicine> {
+    validate_formulary_request(&request)?;
+
+    let medicine_id = utils::generate_medicine_id()?;
+    let external_code = requested_code(&request).unwrap_or_else(|| medicine_id.clone());
+    if storage::get_medicine_id_by_external_code(&external_code).is_some() {
+        return Err(format!("External code {} is already in use", external_code));
+    }
+
+    let medicine = build_formulary_medicine(
+        medicine_id,
+        external_code.clone(),
+        request,
+        utils::get_current_timestamp(),
+        created_by,
+        is_active,
+    );
+    let medicine = storage::store_medicine(medicine);
+    storage::set_external_code(&external_code, &medicine.id);
+    Ok(medicine)
+}
+
+fn update_formulary_entry(medicine_id: &str, request: FormularyMedicineRequest, is_active: Option<bool>) -> Result<Medicine> {
+    validate_formulary_request(&request)?;
+
+    let existing = storage::get_medicine(medicine_id)
+        .filter(|medicine| medicine.formulary.is_some())
+        .ok_or("Formulary medicine not found".to_string())?;
+
+    let previous_code = external_code_of(&existing);
+    let external_code = requested_code(&request).unwrap_or_else(|| previous_code.clone());
+    if let Some(owner) = storage::get_medicine_id_by_external_code(&external_code) {
+        if owner != existing.id {
+            return Err(format!("External code {} is already in use", external_code));
+        }
+    }
+
+    let medicine = build_formulary_medicine(
+        existing.id,
+        external_code.clone(),
+        request,
+        existing.created_at,
+        existing.created_by,
+        is_active.unwrap_or(existing.is_active),
+    );
+    let medicine = storage::update_medicine_in_storage(medicine_id, medicine)
+        .ok_or("Formulary medicine not found".to_string())?;
+    if external_code != previous_code {
+        storage::remove_external_code(&previous_code);
+    }
+    storage::set_external_code(&external_code, medicine_id);
+    Ok(medicine)
+}
+
+#[ic_cdk::update]
+pub fn add_formulary_medicine(request: FormularyMedicineRequest) -> Result<Medicine> {
+    let admin = require_admin_permission(AdminPermission::ManageSystem)?;
+
+    let medicine = create_formulary_entry(request, admin.id, true)?;
+
+    storage::append_audit_entry(
+        caller().to_string(),
+        "add_formulary_medicine",
+        medicine.id.clone(),
+        medicine.name.clone(),
+        medicine.created_at,
+    );
+    Ok(medicine)
+}
+
+#[ic_cdk::update]
+pub fn update_formulary_medicine(medicine_id: String, request: FormularyMedicineRequest) -> Result<Medicine> {
+    require_admin_permission(AdminPermission::ManageSystem)?;
+
+    let medicine = update_formulary_entry(&medicine_id, request, None)?;
+
+    storage::append_audit_entry(
+        caller().to_string(),
+        "update_formulary_medicine",
+        medicine_id,
+        medicine.name.clone(),
+        utils::get_current_timestamp(),
+    );
+    Ok(medicine)
+}
+
+/// Withdrawn entries stay readable for existing prescriptions but can no
+/// longer be prescribed.
+#[ic_cdk::update]
+pub fn set_formulary_medicine_active(medicine_id: String, is_active: bool) -> Result<Medicine> {
+    require_admin_permission(AdminPermission::ManageSystem)?;
+
+    let mut medicine = storage::get_medicine(&medicine_id)
+        .filter(|medicine| medicine.formulary.is_some())
+        .ok_or("Formulary medicine not found".to_string())?;
+
+    medicine.is_active = is_active;
+    let medicine = storage::update_medicine_in_storage(&medicine_id, medicine)
+        .ok_or("Formulary medicine not found".to_string())?;
+
+    storage::append_audit_entry(
+        caller().to_string(),
+        "set_formulary_medicine_active",
+        medicine_id,
+        format!("is_active={}", is_active),
+        utils::get_current_timestamp(),
+    );
+    Ok(medicine)
+}
+
+// Bulk import/export
+
+/// One formulary entry as a flat row, shared by the CSV and JSON formats.
+/// List fields are `;`-separated.
+#[derive(Serialize, Deserialize)]
+struct FormularyRow {
+    external_code: String,
+    generic_name: String,
+    #[serde(default)]
+    brand_names: String,
+    atc_code: String,
+    active_ingredients: String,
+    strength: f64,
+    unit: String,
+    form: String,
+    route: String,
+    times_per_period: u32,
+    period: String,
+    #[serde(default)]
+    as_needed: bool,
+    duration_days: u32,
+    side_effects: String,
+    #[serde(default)]
+    guide_text: String,
+    #[serde(default)]
+    guide_source: String,
+    #[serde(default = "default_active")]
+    is_active: bool,
+}
+
+fn default_active() -> bool {
+    true
+}
+
+fn split_list(text: &str) -> Vec<String> {
+    text.split(';').map(|item| item.trim().to_string()).collect()
+}
+
+fn row_to_request(row: FormularyRow) -> Result<(FormularyMedicineRequest, bool)> {
+    let unit = dosage::parse_unit(&row.unit)
+        .ok_or(format!("Unknown strength unit '{}'", row.unit))?;
+    let period = dosage::parse_period(&row.period)
+        .ok_or(format!("Unknown frequency period '{}'", row.period))?;
+
+    let request = FormularyMedicineRequest {
+        external_code: Some(row.external_code),
+        generic_name: row.generic_name,
+        brand_names: split_list(&row.brand_names),
+        atc_code: row.atc_code,
+        active_ingredients: split_list(&row.active_ingredients),
+        dosage: DosageSpec {
+            strength: row.strength,
+            unit,
+            form: dosage::parse_form(&row.form),
+            route: dosage::parse_route(&row.route),
+            frequency: DoseFrequency {
+                times: row.times_per_period,
+                period,
+                as_needed: row.as_needed,
+            },
+            duration_days: row.duration_days,
+        },
+        side_effects: row.side_effects,
+        guide_text: row.guide_text,
+        guide_source: row.guide_source,
+    };
+    Ok((request, row.is_active))
+}
+
+fn medicine_to_row(medicine: &Medicine) -> Option<FormularyRow> {
+    let details = medicine.formulary.as_ref()?;
+    let spec = medicine.dosage_spec.as_ref()?;
+
+    Some(FormularyRow {
+        external_code: external_code_of(medicine),
+        generic_name: details.generic_name.clone(),
+        brand_names: details.brand_names.join(";"),
+        atc_code: details.atc_code.clone(),
+        active_ingredients: medicine.active_ingredients.join(";"),
+        strength: spec.strength,
+        unit: dosage::unit_label(&spec.unit).to_string(),
+        form: dosage::form_label(&spec.form),
+        route: dosage::route_label(&spec.route),
+        times_per_period: spec.frequency.times,
+        period: dosage::period_label(&spec.frequency.period).to_string(),
+        as_needed: spec.frequency.as_needed,
+        duration_days: spec.duration_days,
+        side_effects: medicine.side_effects.clone(),
+        guide_text: medicine.guide_text.clone(),
+        guide_source: medicine.guide_source.clone(),
+        is_active: medicine.is_active,
+    })
+}
+
+// Rows that fail to parse are kept as errors so they show up in the report
+fn parse_rows(request: &FormularyImportRequest) -> Result<Vec<std::result::Result<FormularyRow, String>>> {
+    match request.format {
+        FormularyFormat::Csv => {
+            let mut reader = csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .from_reader(request.data.as_bytes());
+            Ok(reader
+                .deserialize::<FormularyRow>()
+                .map(|row| row.map_err(|e| format!("Invalid CSV row: {}", e)))
+                .collect())
+        }
+        FormularyFormat::Json => {
+            let values: Vec<serde_json::Value> = serde_json::from_str(&request.data)
+                .map_err(|e| format!("JSON batch must be an array of rows: {}", e))?;
+            Ok(values
+                .into_iter()
+                .map(|value| serde_json::from_value(value).map_err(|e| format!("Invalid JSON row: {}", e)))
+                .collect())
+        }
+    }
+}
+
+fn import_row(row: FormularyRow, admin_id: &str) -> Result<(Medicine, ImportRowOutcome)> {
+    if row.external_code.trim().is_empty() {
+        return Err("External code is required".to_string());
+    }
+
+    let (request, is_active) = row_to_request(row)?;
+    let code = requested_code(&request).unwrap_or_default();
+
+    // Unmapped entries export under their medicine id
+    let existing = storage::get_medicine_id_by_external_code(&code).or_else(|| {
+        storage::get_medicine(&code)
+            .filter(|medicine| medicine.formulary.is_some())
+            .map(|medicine| medicine.id)
+    });
+
+    match existing {
+        Some(medicine_id) => {
+            update_formulary_entry(&medicine_id, request, Some(is_active))
+                .map(|medicine| (medicine, ImportRowOutcome::Updated))
+        }
+        None => {
+            create_formulary_entry(request, admin_id.to_string(), is_active)
+                .map(|medicine| (medicine, ImportRowOutcome::Created))
+        }
+    }
+}
+
+/// Upsert one chunk of formulary rows by external code. Every row is
+/// validated and reported on its own; a bad row doesn't stop the others.
+#[ic_cdk::update]
+pub fn import_formulary(request: FormularyImportRequest) -> Result<FormularyImportReport> {
+    let admin = require_admin_permission(AdminPermission::ManageSystem)?;
+
+    let rows = parse_rows(&request)?;
+    if rows.len() > MAX_BATCH_ROWS {
+        return Err(format!("At most {} rows per chunk; split the import", MAX_BATCH_ROWS));
+    }
+
+    let mut report = FormularyImportReport { created: 0, updated: 0, rejected: 0, rows: Vec::new() };
+    let mut seen_codes: Vec<String> = Vec::new();
+
+    for (index, parsed) in rows.into_iter().enumerate() {
+        let mut result = ImportRowResult {
+            row: index as u32 + 1,
+            external_code: None,
+            medicine_id: None,
+            outcome: ImportRowOutcome::Rejected,
+            message: None,
+        };
+
+        let outcome = parsed.and_then(|row| {
+            let code = row.external_code.trim().to_string();
+            result.external_code = Some(code.clone());
+            if seen_codes.contains(&code) {
+                return Err("External code appears more than once in this chunk".to_string());
+            }
+            seen_codes.push(code);
+            import_row(row, &admin.id)
+        });
+
+        match outcome {
+            Ok((medicine, ImportRowOutcome::Created)) => {
+                report.created += 1;
+                result.medicine_id = Some(medicine.id);
+                result.outcome = ImportRowOutcome::Created;
+            }
+            Ok((medicine, outcome)) => {
+                report.updated += 1;
+                result.medicine_id = Some(medicine.id);
+                result.outcome = outcome;
+            }
+            Err(message) => {
+                report.rejected += 1;
+                result.message = Some(message);
+            }
+        }
+        report.rows.push(result);
+    }
+
+    storage::append_audit_entry(
+        caller().to_string(),
+        "import_formulary",
+        "formulary".to_string(),
+        format!("{} created, {} updated, {} rejected", report.created, report.updated, report.rejected),
+        utils::get_current_timestamp(),
+    );
+    Ok(report)
+}
+
+/// One page of the formulary in the import format, so an export can be
+/// edited and fed back to `import_formulary` chunk by chunk.
+#[ic_cdk::query]
+pub fn export_formulary(format: FormularyFormat, offset: u64, limit: u64) -> Result<FormularyExportChunk> {
+    require_admin_permission(AdminPermission::ManageSystem)?;
+
+    let medicines = storage::get_formulary_medicines();
+    let total = medicines.len() as u64;
+    let limit = limit.clamp(1, MAX_BATCH_ROWS as u64);
+
+    let rows: Vec<FormularyRow> = medicines
+        .iter()
+        .skip(offset as usize)
+        .take(limit as usize)
+        .filter_map(medicine_to_row)
+        .collect();
+
+    let data = match format {
+        FormularyFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(Vec::new());
+            for row in &rows {
+                writer.serialize(row).map_err(|e| format!("Failed to write CSV: {}", e))?;
+            }
+            let bytes = writer.into_inner().map_err(|e| format!("Failed to write CSV: {}", e))?;
+            String::from_utf8(bytes).map_err(|e| format!("Failed to write CSV: {}", e))?
+        }
+        FormularyFormat::Json => {
+            serde_json::to_string(&rows).map_err(|e| format!("Failed to write JSON: {}", e))?
+        }
+    };
+
+    let next = offset.saturating_add(limit);
+    Ok(FormularyExportChunk {
+        data,
+        total,
+        next_offset: if next < total { Some(next) } else { None },
+    })
+}
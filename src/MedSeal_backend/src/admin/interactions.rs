@@ -0,0 +1,126 @@
+use ic_cdk::api::caller;
+use crate::shared::types::*;
+use crate::shared::storage as storage;
+use crate::shared::utils as utils;
+use crate::shared::auth::require_admin_permission;
+
+/// Upsert a batch of interaction pairs. The whole batch is validated before
+/// anything is written.
+#[ic_cdk::update]
+pub fn load_drug_interactions(entries: Vec<DrugInteractionInput>) -> Result<u64> {
+    let admin = require_admin_permission(AdminPermission::ManageSystem)?;
+
+    for (index, entry) in entries.iter().enumerate() {
+        let a = storage::normalize_ingredient(&entry.ingredient_a);
+        let b = storage::normalize_ingredient(&entry.ingredient_b);
+        if a.is_empty() || b.is_empty() {
+            return Err(format!("Entry {}: both ingredients are required", index));
+        }
+        if a == b {
+            return Err(format!("Entry {}: an ingredient cannot interact with itself", index));
+        }
+        if entry.explanation.trim().is_empty() {
+            return Err(format!("Entry {}: an explanation is required", index));
+        }
+    }
+
+    let now = utils::get_current_timestamp();
+    let count = entries.len() as u64;
+    for entry in entries {
+        storage::store_drug_interaction(DrugInteraction {
+            ingredient_a: storage::normalize_ingredient(&entry.ingredient_a),
+            ingredient_b: storage::normalize_ingredient(&entry.ingredient_b),
+            severity: entry.severity,
+            explanation: entry.explanation,
+            source: entry.source,
+            updated_at: now,
+            updated_by: admin.id.clone(),
+        });
+    }
+
+    storage::append_audit_entry(
+        caller().to_string(),
+        "load_drug_interactions",
+        "drug_interactions".to_string(),
+        format!("Loaded {} interaction(s)", count),
+        now,
+    );
+    Ok(count)
+}
+
+#[ic_cdk::update]
+pub fn remove_drug_interaction(ingredient_a: String, ingredient_b: String) -> Result<String> {
+    require_admin_permission(AdminPermission::ManageSystem)?;
+
+    if !storage::remove_drug_interaction(&ingredient_a, &ingredient_b) {
+        return Err("Interaction not found".to_string());
+    }
+
+    storage::append_audit_entry(
+        caller().to_string(),
+        "remove_drug_interaction",
+        format!("{}|{}", ingredient_a, ingredient_b),
+        String::new(),
+        utils::get_current_timestamp(),
+    );
+    Ok("Interaction removed".to_string())
+}
+
+/// Upsert a batch of condition contraindications, validated as a whole like
+/// interaction pairs.
+#[ic_cdk::update]
+pub fn load_condition_contraindications(entries: Vec<ConditionContraindicationInput>) -> Result<u64> {
+    let admin = require_admin_permission(AdminPermission::ManageSystem)?;
+
+    for (index, entry) in entries.iter().enumerate() {
+        if storage::normalize_ingredient(&entry.condition).is_empty()
+            || storage::normalize_ingredient(&entry.ingredient).is_empty()
+        {
+            return Err(format!("Entry {}: both condition and ingredient are required", index));
+        }
+        if entry.explanation.trim().is_empty() {
+            return Err(format!("Entry {}: an explanation is required", index));
+        }
+    }
+
+    let now = utils::get_current_timestamp();
+    let count = entries.len() as u64;
+    for entry in entries {
+        storage::store_condition_contraindication(ConditionContraindication {
+            condition: storage::normalize_ingredient(&entry.condition),
+            ingredient: storage::normalize_ingredient(&entry.ingredient),
+            severity: entry.severity,
+            explanation: entry.explanation,
+            source: entry.source,
+            updated_at: now,
+            updated_by: admin.id.clone(),
+        });
+    }
+
+    storage::append_audit_entry(
+        caller().to_string(),
+        "load_condition_contraindications",
+        "condition_contraindications".to_string(),
+        format!("Loaded {} contraindication(s)", count),
+        now,
+    );
+    Ok(count)
+}
+
+#[ic_cdk::update]
+pub fn remove_condition_contraindication(condition: String, ingredient: String) -> Result<String> {
+    require_admin_permission(AdminPermission::ManageSystem)?;
+
+    if !storage::remove_condition_contraindication(&condition, &ingredient) {
+        return Err("Contraindication not found".to_string());
+    }
+
+    storage::append_audit_entry(
+        caller().to_string(),
+        "remove_condition_contraindication",
+        format!("{}|{}", condition, ingredient),
+        String::new(),
+        utils::get_current_timestamp(),
+    );
+    Ok("Contraindication removed".to_string())
+}
@@ -1,2 +1,5 @@
 pub mod users;
-pub mod verification;
\ No newline at end of file
+pub mod verification;
+pub mod settings;
+pub mod interactions;
+pub mod formulary;
\ No newline at end of file
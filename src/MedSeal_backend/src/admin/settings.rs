@@ -0,0 +1,67 @@
+use crate::shared::types::*;
+use crate::shared::storage as storage;
+use crate::shared::utils as utils;
+use crate::shared::auth::require_admin_permission;
+use crate::shared::ledger;
+use crate::doctor::prescriptions::MAX_PRESCRIPTION_VALIDITY_DAYS;
+use candid::Principal;
+use ic_cdk::api::caller;
+
+#[ic_cdk::query]
+pub fn get_system_settings() -> SystemSettings {
+    storage::get_system_settings()
+}
+
+// Pool escrows and NGO funds are balances on the configured ledger, so
+// switching ledgers while any of them holds tokens would strand the funds
+async fn ensure_ledger_drained(ledger_id: Principal) -> Result<()> {
+    let pools = storage::get_all_contribution_pools();
+    let mut ngo_ids: Vec<String> = pools.iter().map(|pool| pool.ngo_id.clone()).collect();
+    ngo_ids.sort();
+    ngo_ids.dedup();
+
+    let accounts = pools
+        .iter()
+        .map(|pool| (format!("pool {}", pool.id), ledger::pool_account(&pool.id)))
+        .chain(ngo_ids.iter().map(|ngo_id| (format!("the fund of NGO {}", ngo_id), ledger::ngo_fund_account(ngo_id))));
+    for (holder, account) in accounts {
+        let balance = ledger::balance_of(ledger_id, account).await?;
+        if balance > 0u64 {
+            return Err(format!(
+                "The ledger cannot be changed while {} still holds {} tokens on it",
+                holder, balance
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[ic_cdk::update]
+pub async fn update_system_settings(settings: SystemSettings) -> Result<SystemSettings> {
+    require_admin_permission(AdminPermission::ManageSystem)?;
+
+    if settings.prescription_validity_days == 0 {
+        return Err("Prescription validity must be at least one day".to_string());
+    }
+    if settings.prescription_validity_days > MAX_PRESCRIPTION_VALIDITY_DAYS {
+        return Err(format!(
+            "Prescription validity cannot exceed {} days",
+            MAX_PRESCRIPTION_VALIDITY_DAYS
+        ));
+    }
+
+    let current_ledger = storage::get_system_settings().ledger_canister_id;
+    if let Some(current_ledger) = current_ledger.filter(|ledger_id| settings.ledger_canister_id != Some(*ledger_id)) {
+        ensure_ledger_drained(current_ledger).await?;
+    }
+
+    storage::set_system_settings(settings.clone());
+    storage::append_audit_entry(
+        caller().to_string(),
+        "update_system_settings",
+        "system_settings".to_string(),
+        format!("{:?}", settings),
+        utils::get_current_timestamp(),
+    );
+    Ok(settings)
+}
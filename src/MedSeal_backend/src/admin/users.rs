@@ -1,38 +1,148 @@
 use ic_cdk::api::caller;
 use crate::shared::types::*;
 use crate::shared::storage as storage;
-use candid::{CandidType, Deserialize};
+use crate::shared::utils as utils;
+use crate::shared::auth::{redact_user, require_admin_permission, verify_admin};
+use candid::{CandidType, Deserialize, Principal};
 
-// Helper function to verify admin role
-fn verify_admin() -> Result<User> {
-    let caller_principal = caller().to_string();
-    
-    match storage::get_user_by_principal(&caller_principal) {
-        Some(user) => {
-            match user.role {
-                UserRole::Admin => Ok(user),
-                _ => Err("Admin access required".to_string()),
-            }
-        },
-        None => Err("User not found".to_string()),
+#[ic_cdk::update]
+pub fn create_admin(request: CreateAdminRequest) -> Result<User> {
+    let creator = require_admin_permission(AdminPermission::ManageUsers)?;
+
+    // Admins can only hand out permissions they hold themselves
+    let creator_permissions = storage::get_admin_permissions(&creator.id)
+        .map(|entry| entry.permissions)
+        .unwrap_or_default();
+    if let Some(missing) = request.permissions.iter().find(|p| !creator_permissions.contains(p)) {
+        return Err(format!("Cannot grant permission you do not hold: {:?}", missing));
+    }
+
+    let user_principal = Principal::from_text(&request.user_principal)
+        .map_err(|_| "Invalid user principal".to_string())?
+        .to_string();
+
+    if storage::principal_has_account(&user_principal) {
+        return Err("Principal already has an account".to_string());
+    }
+
+    if !utils::validate_email(&request.email) {
+        return Err("Invalid email address".to_string());
+    }
+
+    let now = utils::get_current_timestamp();
+    let admin_id = utils::generate_user_id()?;
+    let admin = User {
+        id: admin_id.clone(),
+        name: request.name,
+        email: request.email,
+        role: UserRole::Admin,
+        license_number: String::new(),
+        user_principal,
+        created_at: now,
+        verification_status: VerificationStatus::Approved,
+        verification_request: None,
+        last_active: None,
+        total_prescriptions: 0,
+        total_medicines: 0,
+        health_profile: None,
+    };
+
+    storage::store_user(admin.clone());
+    storage::append_audit_entry(
+        caller().to_string(),
+        "create_admin",
+        admin_id.clone(),
+        format!("Granted {:?}", request.permissions),
+        now,
+    );
+    storage::store_admin_permissions(AdminPermissions {
+        admin_id,
+        permissions: request.permissions,
+        updated_at: now,
+        updated_by: Some(creator.id),
+    });
+    storage::set_admin_exists(true);
+    Ok(admin)
+}
+
+#[ic_cdk::update]
+pub fn set_admin_permissions(admin_id: String, permissions: Vec<AdminPermission>) -> Result<AdminPermissions> {
+    let caller_admin = require_admin_permission(AdminPermission::ManageUsers)?;
+
+    let target = storage::get_user(&admin_id)
+        .ok_or("User not found".to_string())?;
+
+    if !matches!(target.role, UserRole::Admin) {
+        return Err("User is not an admin".to_string());
+    }
+
+    // Prevent an admin from locking themselves out of admin management
+    if target.id == caller_admin.id && !permissions.contains(&AdminPermission::ManageUsers) {
+        return Err("You cannot remove ManageUsers from yourself".to_string());
+    }
+
+    let caller_permissions = storage::get_admin_permissions(&caller_admin.id)
+        .map(|entry| entry.permissions)
+        .unwrap_or_default();
+    if let Some(missing) = permissions.iter().find(|p| !caller_permissions.contains(p)) {
+        return Err(format!("Cannot grant permission you do not hold: {:?}", missing));
     }
+
+    let entry = AdminPermissions {
+        admin_id: target.id,
+        permissions,
+        updated_at: utils::get_current_timestamp(),
+        updated_by: Some(caller_admin.id),
+    };
+
+    storage::store_admin_permissions(entry.clone());
+    storage::append_audit_entry(
+        caller().to_string(),
+        "set_admin_permissions",
+        entry.admin_id.clone(),
+        format!("Set {:?}", entry.permissions),
+        entry.updated_at,
+    );
+    Ok(entry)
+}
+
+#[ic_cdk::query]
+pub fn get_audit_log() -> Result<Vec<AuditEntry>> {
+    require_admin_permission(AdminPermission::ManageSystem)?;
+    Ok(storage::get_audit_log())
+}
+
+#[ic_cdk::query]
+pub fn get_admin_permissions(admin_id: String) -> Result<AdminPermissions> {
+    require_admin_permission(AdminPermission::ManageUsers)?;
+
+    storage::get_admin_permissions(&admin_id)
+        .ok_or("No permissions recorded for this admin".to_string())
+}
+
+#[ic_cdk::query]
+pub fn get_my_admin_permissions() -> Result<AdminPermissions> {
+    let admin = verify_admin()?;
+
+    storage::get_admin_permissions(&admin.id)
+        .ok_or("No permissions recorded for this admin".to_string())
 }
 
 #[ic_cdk::query]
 pub fn get_all_doctors() -> Result<Vec<User>> {
-    verify_admin()?;
-    Ok(storage::get_all_doctors())
+    require_admin_permission(AdminPermission::ManageUsers)?;
+    Ok(storage::get_all_doctors().into_iter().map(redact_user).collect())
 }
 
 #[ic_cdk::query]
 pub fn get_all_patients() -> Result<Vec<User>> {
-    verify_admin()?;
-    Ok(storage::get_all_patients())
+    require_admin_permission(AdminPermission::ManageUsers)?;
+    Ok(storage::get_all_patients().into_iter().map(redact_user).collect())
 }
 
 #[ic_cdk::query]
 pub fn get_user_stats(user_id: String) -> Result<UserStats> {
-    verify_admin()?;
+    require_admin_permission(AdminPermission::ViewAnalytics)?;
     
     let user = storage::get_user(&user_id)
         .ok_or("User not found".to_string())?;
@@ -55,7 +165,7 @@ pub fn get_user_stats(user_id: String) -> Result<UserStats> {
 
 #[ic_cdk::query]
 pub fn get_system_overview() -> Result<SystemOverview> {
-    verify_admin()?;
+    require_admin_permission(AdminPermission::ViewAnalytics)?;
     
     let all_doctors = storage::get_all_doctors();
     let all_patients = storage::get_all_patients();
@@ -2,20 +2,39 @@ use ic_cdk::api::caller;
 use crate::shared::types::*;
 use crate::shared::storage as storage;
 use crate::shared::utils as utils;
+use crate::shared::auth::{require_admin_permission, verify_admin};
 
-// Helper function to verify admin role
-fn verify_admin() -> Result<User> {
-    let caller_principal = caller().to_string();
-    
-    match storage::get_user_by_principal(&caller_principal) {
-        Some(user) => {
-            match user.role {
-                UserRole::Admin => Ok(user),
-                _ => Err("Admin access required".to_string()),
-            }
-        },
-        None => Err("User not found".to_string()),
+// Verification requests cover doctors, pharmacies and NGOs; the applicant's
+// role decides which review permission applies. Pharmacies hold clinical
+// licenses, so they are reviewed alongside doctors.
+fn review_permission_for(user_id: &str) -> AdminPermission {
+    match storage::get_user(user_id).map(|user| user.role) {
+        Some(UserRole::NGO) => AdminPermission::ReviewNGOs,
+        _ => AdminPermission::ReviewDoctors,
+    }
+}
+
+// Helper function to verify the caller may review a given applicant
+fn verify_reviewer_for(user_id: &str) -> Result<User> {
+    require_admin_permission(review_permission_for(user_id))
+}
+
+// Helper function to list only the requests the calling admin may review
+fn reviewable_requests(requests: Vec<VerificationRequest>) -> Result<Vec<VerificationRequest>> {
+    let admin = verify_admin()?;
+
+    let can_review_doctors = storage::admin_has_permission(&admin.id, &AdminPermission::ReviewDoctors);
+    let can_review_ngos = storage::admin_has_permission(&admin.id, &AdminPermission::ReviewNGOs);
+    if !can_review_doctors && !can_review_ngos {
+        return Err("Admin permission required: ReviewDoctors or ReviewNGOs".to_string());
     }
+
+    Ok(requests.into_iter()
+        .filter(|request| match review_permission_for(&request.doctor_id) {
+            AdminPermission::ReviewNGOs => can_review_ngos,
+            _ => can_review_doctors,
+        })
+        .collect())
 }
 
 // Helper function to verify doctor role
@@ -50,7 +69,7 @@ pub fn admin_process_verification_submission(request: SubmitVerificationRequest)
         }
     }
     
-    let verification_id = utils::generate_id("verify");
+    let verification_id = utils::generate_id("verify")?;
     let verification_request = VerificationRequest {
         id: verification_id.clone(),
         doctor_id: doctor.id.clone(),
@@ -82,31 +101,30 @@ pub fn admin_process_verification_submission(request: SubmitVerificationRequest)
 
 #[ic_cdk::query]
 pub fn get_all_verification_requests() -> Result<Vec<VerificationRequest>> {
-    let _admin = verify_admin()?;
-    Ok(storage::get_all_verification_requests())
+    reviewable_requests(storage::get_all_verification_requests())
 }
 
 #[ic_cdk::query]
 pub fn get_pending_verification_requests() -> Result<Vec<VerificationRequest>> {
-    let _admin = verify_admin()?;
-    Ok(storage::get_pending_verification_requests())
+    reviewable_requests(storage::get_pending_verification_requests())
 }
 
 #[ic_cdk::query]
 pub fn get_verification_request(request_id: String) -> Result<VerificationRequest> {
-    let _admin = verify_admin()?;
+    let request = storage::get_verification_request(&request_id)
+        .ok_or("Verification request not found".to_string())?;
     
-    storage::get_verification_request(&request_id)
-        .ok_or("Verification request not found".to_string())
+    verify_reviewer_for(&request.doctor_id)?;
+    Ok(request)
 }
 
 #[ic_cdk::update]
 pub fn process_verification_request(request: ProcessVerificationRequest) -> Result<String> {
-    let _admin = verify_admin()?;
-    
     let mut verification_request = storage::get_verification_request(&request.verification_id)
         .ok_or("Verification request not found".to_string())?;
     
+    let _admin = verify_reviewer_for(&verification_request.doctor_id)?;
+    
     // Update verification request
     verification_request.status = request.status.clone();
     verification_request.processed_at = Some(utils::get_current_timestamp());
@@ -137,7 +155,7 @@ pub fn process_verification_request(request: ProcessVerificationRequest) -> Resu
 
 #[ic_cdk::query]
 pub fn get_doctor_verification_status(doctor_id: String) -> Result<VerificationStatusInfo> {
-    let _admin = verify_admin()?;
+    let _admin = require_admin_permission(AdminPermission::ReviewDoctors)?;
     
     let user = storage::get_user(&doctor_id)
         .ok_or("Doctor not found".to_string())?;
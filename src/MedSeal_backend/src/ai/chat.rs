@@ -1,5 +1,7 @@
 use crate::shared::types::*;
 use crate::shared::storage::*;
+use crate::shared::dosage;
+use crate::patient::prescriptions::open_prescription_with_contact;
 use ic_llm;
 
 fn to_ic_llm_messages(messages: Vec<ChatMessage>, system_prompt: String) -> Vec<ic_llm::ChatMessage> {
@@ -71,16 +73,8 @@ pub async fn chat_medicine(messages: Vec<ChatMessage>, context: MedicineChatCont
 
 #[ic_cdk::update]
 pub async fn get_prescription_ai_help(prescription_code: String, patient_contact: String, question: String) -> Result<String> {
-    // Get prescription data
-    let prescription = match get_prescription_by_code(&prescription_code) {
-        Some(prescription) => {
-            if prescription.patient_contact != patient_contact {
-                return Err("Invalid prescription code or patient contact".to_string());
-            }
-            prescription
-        },
-        None => return Err("Prescription not found".to_string()),
-    };
+    // Same checks and access log entry as opening the prescription directly
+    let prescription = open_prescription_with_contact(&prescription_code, &patient_contact, "get_prescription_ai_help")?;
 
     // Build prescription context
     let mut prescription_data = format!(
@@ -89,18 +83,26 @@ pub async fn get_prescription_ai_help(prescription_code: String, patient_contact
     );
 
     for med in &prescription.medicines {
-        if let Some(medicine) = get_medicine(&med.medicine_id) {
+        if let Some(medicine) = get_prescribed_medicine(med) {
             prescription_data.push_str(&format!(
                 "- {} ({}), Frequency: {}, Duration: {}\n",
                 medicine.name, medicine.dosage, medicine.frequency, medicine.duration
             ));
             if let Some(custom_dosage) = &med.custom_dosage {
-                prescription_data.push_str(&format!("  Custom Dosage: {}\n", custom_dosage));
+                prescription_data.push_str(&format!("  Custom Dosage: {}\n", dosage::describe_dosage(custom_dosage)));
             }
             if !med.custom_instructions.is_empty() {
                 prescription_data.push_str(&format!("  Instructions: {}\n", med.custom_instructions));
             }
             prescription_data.push_str(&format!("  Side Effects: {}\n", medicine.side_effects));
+            if let Some(overlay) = get_medicine_overlay(&prescription.doctor_id, &medicine.id) {
+                prescription_data.push_str(&format!("  Doctor's Guide: {}\n", overlay.guide_text));
+            }
+        } else {
+            prescription_data.push_str(&format!(
+                "- Medicine {} (details unavailable; it is no longer in the catalog)\n",
+                med.medicine_id
+            ));
         }
     }
 
@@ -0,0 +1,213 @@
+use crate::shared::types::*;
+use crate::shared::storage as storage;
+use crate::shared::search;
+
+/// Every known interaction between the active ingredients of two different
+/// medicines in the list, most severe first. Unknown medicine ids are ignored.
+pub fn find_interactions(medicine_ids: &[String]) -> Vec<InteractionWarning> {
+    let mut medicines: Vec<Medicine> = Vec::new();
+    for id in medicine_ids {
+        if medicines.iter().any(|medicine| &medicine.id == id) {
+            continue;
+        }
+        if let Some(medicine) = storage::get_medicine(id) {
+            medicines.push(medicine);
+        }
+    }
+
+    let mut warnings = Vec::new();
+    for (index, first) in medicines.iter().enumerate() {
+        for second in &medicines[index + 1..] {
+            for ingredient_a in &first.active_ingredients {
+                for ingredient_b in &second.active_ingredients {
+                    if let Some(interaction) = storage::get_drug_interaction(ingredient_a, ingredient_b) {
+                        warnings.push(InteractionWarning {
+                            medicine_a_id: first.id.clone(),
+                            medicine_a_name: first.name.clone(),
+                            medicine_b_id: second.id.clone(),
+                            medicine_b_name: second.name.clone(),
+                            ingredient_a: ingredient_a.clone(),
+                            ingredient_b: ingredient_b.clone(),
+                            severity: interaction.severity,
+                            explanation: interaction.explanation,
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    warnings.sort_by(|a, b| b.severity.cmp(&a.severity));
+    warnings
+}
+
+// An allergy matches an ingredient when every word of the allergy appears in
+// it, so "penicillin" flags "penicillin v potassium"
+fn allergy_matches(substance: &str, ingredient: &str) -> bool {
+    let substance_tokens = search::tokenize(substance);
+    let ingredient_tokens = search::tokenize(ingredient);
+    !substance_tokens.is_empty() && substance_tokens.iter().all(|token| ingredient_tokens.contains(token))
+}
+
+/// Everything in a patient's health profile that argues against the listed
+/// medicines, most severe first. Unknown medicine ids are ignored.
+pub fn find_profile_warnings(profile: &HealthProfile, medicine_ids: &[String]) -> Vec<ProfileWarning> {
+    let mut conditions: Vec<(String, ProfileWarningKind)> = profile.chronic_conditions
+        .iter()
+        .map(|condition| (condition.clone(), ProfileWarningKind::Condition))
+        .collect();
+    match profile.pregnancy_status {
+        PregnancyStatus::Pregnant => conditions.push(("pregnancy".to_string(), ProfileWarningKind::Pregnancy)),
+        PregnancyStatus::Breastfeeding => conditions.push(("breastfeeding".to_string(), ProfileWarningKind::Pregnancy)),
+        PregnancyStatus::NotPregnant | PregnancyStatus::Unknown => {}
+    }
+
+    let mut seen: Vec<&str> = Vec::new();
+    let mut warnings = Vec::new();
+    for id in medicine_ids {
+        if seen.contains(&id.as_str()) {
+            continue;
+        }
+        seen.push(id);
+        let Some(medicine) = storage::get_medicine(id) else { continue };
+
+        let mut push = |ingredient: &str, kind: ProfileWarningKind, matched: &str, severity: InteractionSeverity, message: String| {
+            warnings.push(ProfileWarning {
+                medicine_id: medicine.id.clone(),
+                medicine_name: medicine.name.clone(),
+                ingredient: ingredient.to_string(),
+                kind,
+                matched: matched.to_string(),
+                severity,
+                message,
+            });
+        };
+
+        for ingredient in &medicine.active_ingredients {
+            for allergy in &profile.allergies {
+                if allergy_matches(&allergy.substance, ingredient) {
+                    let reaction = if allergy.reaction.trim().is_empty() {
+                        String::new()
+                    } else {
+                        format!(" ({})", allergy.reaction.trim())
+                    };
+                    push(
+                        ingredient,
+                        ProfileWarningKind::Allergy,
+                        &allergy.substance,
+                        InteractionSeverity::Contraindicated,
+                        format!("Patient is allergic to {}{}", allergy.substance.trim(), reaction),
+                    );
+                }
+            }
+
+            for (condition, kind) in &conditions {
+                if let Some(entry) = storage::get_condition_contraindication(condition, ingredient) {
+                    push(ingredient, kind.clone(), condition, entry.severity, entry.explanation);
+                }
+            }
+
+            for external in &profile.external_medications {
+                for external_ingredient in &external.active_ingredients {
+                    if storage::normalize_ingredient(external_ingredient) == storage::normalize_ingredient(ingredient) {
+                        push(
+                            ingredient,
+                            ProfileWarningKind::ExternalMedication,
+                            &external.name,
+                            InteractionSeverity::Moderate,
+                            format!("Patient already takes {} through {}", ingredient, external.name),
+                        );
+                    } else if let Some(interaction) = storage::get_drug_interaction(ingredient, external_ingredient) {
+                        push(
+                            ingredient,
+                            ProfileWarningKind::ExternalMedication,
+                            &external.name,
+                            interaction.severity,
+                            interaction.explanation,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    warnings.sort_by(|a, b| b.severity.cmp(&a.severity));
+    warnings
+}
+
+/// Stands in for the profile checks when the patient hasn't consented to
+/// share their profile with the prescribing doctor.
+pub fn profile_not_shared_warning() -> ProfileWarning {
+    ProfileWarning {
+        medicine_id: String::new(),
+        medicine_name: String::new(),
+        ingredient: String::new(),
+        kind: ProfileWarningKind::NotShared,
+        matched: String::new(),
+        severity: InteractionSeverity::Moderate,
+        message: "The patient has not shared their health profile with you, so allergies and conditions were not checked".to_string(),
+    }
+}
+
+/// Report interactions for a proposed medicine list before prescribing
+#[ic_cdk::query]
+pub fn check_drug_interactions(medicine_ids: Vec<String>) -> Vec<InteractionWarning> {
+    find_interactions(&medicine_ids)
+}
+
+#[ic_cdk::query]
+pub fn get_drug_interactions() -> Vec<DrugInteraction> {
+    storage::get_all_drug_interactions()
+}
+
+#[ic_cdk::query]
+pub fn get_condition_contraindications() -> Vec<ConditionContraindication> {
+    storage::get_all_condition_contraindications()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allergies_match_ingredients_containing_every_word() {
+        assert!(allergy_matches("penicillin", "Penicillin V Potassium"));
+        assert!(allergy_matches("penicillin potassium", "penicillin v potassium"));
+        assert!(!allergy_matches("amoxicillin clavulanate", "amoxicillin"));
+        assert!(!allergy_matches("  ", "penicillin v potassium"));
+    }
+
+    #[test]
+    fn interactions_are_found_in_either_order() {
+        storage::store_drug_interaction(DrugInteraction {
+            ingredient_a: "Warfarin".to_string(),
+            ingredient_b: "aspirin".to_string(),
+            severity: InteractionSeverity::Major,
+            explanation: "Raises bleeding risk".to_string(),
+            source: "test".to_string(),
+            updated_at: 0,
+            updated_by: "test".to_string(),
+        });
+
+        assert!(storage::get_drug_interaction("warfarin", "Aspirin").is_some());
+        assert!(storage::get_drug_interaction(" aspirin ", "WARFARIN").is_some());
+        assert!(storage::get_drug_interaction("warfarin", "ibuprofen").is_none());
+    }
+
+    #[test]
+    fn severities_sort_with_contraindicated_highest() {
+        let mut severities = vec![
+            InteractionSeverity::Moderate,
+            InteractionSeverity::Contraindicated,
+            InteractionSeverity::Minor,
+            InteractionSeverity::Major,
+        ];
+        severities.sort_by(|a, b| b.cmp(a));
+        assert_eq!(severities, vec![
+            InteractionSeverity::Contraindicated,
+            InteractionSeverity::Major,
+            InteractionSeverity::Moderate,
+            InteractionSeverity::Minor,
+        ]);
+    }
+}
@@ -1,8 +1,43 @@
 use crate::shared::types::*;
 use crate::shared::storage as storage;
 use crate::shared::utils as utils;
+use crate::shared::dosage;
+use crate::shared::search;
 use ic_cdk::api::caller;
 
+// Trim names (ingredients, brands) and drop blanks and case-insensitive duplicates
+pub fn clean_name_list(names: Vec<String>) -> Vec<String> {
+    let mut cleaned: Vec<String> = Vec::new();
+    for name in names {
+        let name = name.trim().to_string();
+        if !name.is_empty()
+            && !cleaned.iter().any(|existing| storage::normalize_ingredient(existing) == storage::normalize_ingredient(&name))
+        {
+            cleaned.push(name);
+        }
+    }
+    cleaned
+}
+
+fn to_listing(medicine: Medicine, viewer_id: Option<&str>) -> MedicineListing {
+    if medicine.formulary.is_some() {
+        let overlay = viewer_id.and_then(|id| storage::get_medicine_overlay(id, &medicine.id));
+        MedicineListing { medicine, source: MedicineSource::Formulary, overlay }
+    } else {
+        MedicineListing { medicine, source: MedicineSource::Private, overlay: None }
+    }
+}
+
+fn verify_doctor() -> Result<User> {
+    let user = storage::get_user_by_principal(&caller().to_string())
+        .ok_or("User not found".to_string())?;
+
+    match user.role {
+        UserRole::Doctor => Ok(user),
+        _ => Err("Only doctors can manage medicine overlays".to_string()),
+    }
+}
+
 #[ic_cdk::update]
 pub fn add_medicine(request: CreateMedicineRequest) -> Result<Medicine> {
     let caller_principal = caller().to_string();
@@ -18,13 +53,15 @@ pub fn add_medicine(request: CreateMedicineRequest) -> Result<Medicine> {
         _ => return Err("Only doctors can add medicines".to_string()),
     }
 
-    let medicine_id = utils::generate_medicine_id();
+    dosage::validate_dosage(&request.dosage)?;
+
+    let medicine_id = utils::generate_medicine_id()?;
     let medicine = Medicine {
         id: medicine_id.clone(),
         name: request.name.clone(),
-        dosage: request.dosage.clone(),
-        frequency: request.frequency.clone(),
-        duration: request.duration.clone(),
+        dosage: dosage::render_strength(&request.dosage),
+        frequency: dosage::render_frequency(&request.dosage.frequency),
+        duration: dosage::render_duration(request.dosage.duration_days),
         side_effects: request.side_effects.clone(),
         guide_text: request.guide_text.clone(),
         guide_source: request.guide_source.clone(),
@@ -33,15 +70,118 @@ pub fn add_medicine(request: CreateMedicineRequest) -> Result<Medicine> {
         created_by: user.id.clone(),
         doctor_id: user.id.clone(),
         is_active: true,
+        active_ingredients: clean_name_list(request.active_ingredients),
+        dosage_spec: Some(request.dosage),
+        formulary: None,
+        current_revision: None, // assigned by storage
     };
 
-    storage::store_medicine(medicine.clone());
-    Ok(medicine)
+    Ok(storage::store_medicine(medicine))
 }
 
+/// Every medicine, tagged as formulary or private. Formulary entries carry
+/// the calling doctor's overlay when they have one.
 #[ic_cdk::query]
-pub fn get_all_medicines() -> Vec<Medicine> {
+pub fn get_all_medicines() -> Vec<MedicineListing> {
+    let viewer = storage::get_user_by_principal(&caller().to_string());
+    let viewer_id = viewer.as_ref().map(|user| user.id.as_str());
+
     storage::get_all_medicines()
+        .into_iter()
+        .map(|medicine| to_listing(medicine, viewer_id))
+        .collect()
+}
+
+const DEFAULT_SEARCH_LIMIT: u64 = 20;
+const MAX_SEARCH_LIMIT: u64 = 100;
+
+/// Ranked search over name, brand names, active ingredients, description and
+/// side effects. Words match by prefix and tolerate small typos; every word
+/// must match. A blank query lists medicines by name.
+#[ic_cdk::query]
+pub fn search_medicines(request: MedicineSearchRequest) -> MedicineSearchResults {
+    let viewer = storage::get_user_by_principal(&caller().to_string());
+    let viewer_id = viewer.as_ref().map(|user| user.id.as_str());
+
+    let mut matches: Vec<(Medicine, u32)> = if search::tokenize(&request.query).is_empty() {
+        let mut medicines = storage::get_all_medicines();
+        medicines.sort_by_key(|medicine| medicine.name.to_lowercase());
+        medicines.into_iter().map(|medicine| (medicine, 0)).collect()
+    } else {
+        search::search_medicine_ids(&request.query)
+            .into_iter()
+            .filter_map(|(id, score)| storage::get_medicine(&id).map(|medicine| (medicine, score)))
+            .collect()
+    };
+
+    if request.active_only {
+        matches.retain(|(medicine, _)| medicine.is_active);
+    }
+
+    let limit = match request.limit {
+        0 => DEFAULT_SEARCH_LIMIT,
+        limit => limit.min(MAX_SEARCH_LIMIT),
+    };
+
+    MedicineSearchResults {
+        total: matches.len() as u64,
+        hits: matches
+            .into_iter()
+            .skip(request.offset as usize)
+            .take(limit as usize)
+            .map(|(medicine, score)| MedicineSearchHit { listing: to_listing(medicine, viewer_id), score })
+            .collect(),
+    }
+}
+
+/// Active formulary medicines, with the calling doctor's overlays applied.
+#[ic_cdk::query]
+pub fn get_formulary() -> Vec<MedicineListing> {
+    let viewer = storage::get_user_by_principal(&caller().to_string());
+    let viewer_id = viewer.as_ref().map(|user| user.id.as_str());
+
+    storage::get_formulary_medicines()
+        .into_iter()
+        .filter(|medicine| medicine.is_active)
+        .map(|medicine| to_listing(medicine, viewer_id))
+        .collect()
+}
+
+/// Attach the calling doctor's own guide text to a formulary medicine.
+#[ic_cdk::update]
+pub fn set_medicine_overlay(medicine_id: String, guide_text: String, guide_source: String) -> Result<MedicineOverlay> {
+    let doctor = verify_doctor()?;
+
+    match storage::get_medicine(&medicine_id) {
+        Some(medicine) if medicine.formulary.is_some() => {}
+        Some(_) => return Err("Overlays only apply to formulary medicines; edit your own medicine instead".to_string()),
+        None => return Err("Medicine not found".to_string()),
+    }
+
+    if guide_text.trim().is_empty() {
+        return Err("Guide text is required".to_string());
+    }
+
+    let overlay = MedicineOverlay {
+        doctor_id: doctor.id,
+        medicine_id,
+        guide_text: guide_text.trim().to_string(),
+        guide_source: guide_source.trim().to_string(),
+        updated_at: utils::get_current_timestamp(),
+    };
+    storage::store_medicine_overlay(overlay.clone());
+    Ok(overlay)
+}
+
+#[ic_cdk::update]
+pub fn remove_medicine_overlay(medicine_id: String) -> Result<String> {
+    let doctor = verify_doctor()?;
+
+    if storage::remove_medicine_overlay(&doctor.id, &medicine_id) {
+        Ok("Overlay removed".to_string())
+    } else {
+        Err("Overlay not found".to_string())
+    }
 }
 
 #[ic_cdk::query]
@@ -55,8 +195,19 @@ pub fn get_doctor_medicines(doctor_id: String) -> Vec<Medicine> {
 }
 
 #[ic_cdk::query]
-pub fn get_doctor_prescriptions(doctor_id: String) -> Vec<Prescription> {
-    storage::get_doctor_prescriptions(&doctor_id)
+pub fn get_doctor_prescriptions(doctor_id: String) -> Result<Vec<Prescription>> {
+    crate::doctor::prescriptions::own_doctor_prescriptions(&doctor_id)
+}
+
+/// Every revision of a medicine, oldest first.
+#[ic_cdk::query]
+pub fn get_medicine_history(medicine_id: String) -> Vec<MedicineRevision> {
+    storage::get_medicine_revisions(&medicine_id)
+}
+
+#[ic_cdk::query]
+pub fn get_medicine_revision(medicine_id: String, revision: u32) -> Option<MedicineRevision> {
+    storage::get_medicine_revision(&medicine_id, revision)
 }
 
 #[ic_cdk::query]
@@ -79,6 +230,8 @@ pub fn update_medicine(medicine_id: String, request: CreateMedicineRequest) -> R
         _ => return Err("Only doctors can update medicines".to_string()),
     }
 
+    dosage::validate_dosage(&request.dosage)?;
+
     // Get existing medicine
     let existing_medicine = match storage::get_medicine(&medicine_id) {
         Some(medicine) => medicine,
@@ -93,9 +246,9 @@ pub fn update_medicine(medicine_id: String, request: CreateMedicineRequest) -> R
     let updated_medicine = Medicine {
         id: existing_medicine.id.clone(),
         name: request.name.clone(),
-        dosage: request.dosage.clone(),
-        frequency: request.frequency.clone(),
-        duration: request.duration.clone(),
+        dosage: dosage::render_strength(&request.dosage),
+        frequency: dosage::render_frequency(&request.dosage.frequency),
+        duration: dosage::render_duration(request.dosage.duration_days),
         side_effects: request.side_effects.clone(),
         guide_text: request.guide_text.clone(),
         guide_source: request.guide_source.clone(),
@@ -104,13 +257,14 @@ pub fn update_medicine(medicine_id: String, request: CreateMedicineRequest) -> R
         created_by: existing_medicine.created_by.clone(),
         doctor_id: existing_medicine.doctor_id.clone(),
         is_active: existing_medicine.is_active,
+        active_ingredients: clean_name_list(request.active_ingredients),
+        dosage_spec: Some(request.dosage),
+        formulary: None,
+        current_revision: existing_medicine.current_revision, // advanced by storage
     };
 
-    if storage::update_medicine_in_storage(&medicine_id, updated_medicine.clone()) {
-        Ok(updated_medicine)
-    } else {
-        Err("Failed to update medicine".to_string())
-    }
+    storage::update_medicine_in_storage(&medicine_id, updated_medicine)
+        .ok_or("Failed to update medicine".to_string())
 }
 
 #[ic_cdk::update]
@@ -142,9 +296,6 @@ pub fn toggle_medicine_status(medicine_id: String) -> Result<Medicine> {
     // Toggle status
     existing_medicine.is_active = !existing_medicine.is_active;
 
-    if storage::update_medicine_in_storage(&medicine_id, existing_medicine.clone()) {
-        Ok(existing_medicine)
-    } else {
-        Err("Failed to toggle medicine status".to_string())
-    }
+    storage::update_medicine_in_storage(&medicine_id, existing_medicine)
+        .ok_or("Failed to toggle medicine status".to_string())
 }
\ No newline at end of file
@@ -1,3 +1,5 @@
 pub mod medicines;
 pub mod prescriptions;
-pub mod verification;
\ No newline at end of file
+pub mod verification;
+pub mod interactions;
+pub mod patients;
@@ -0,0 +1,40 @@
+use ic_cdk::api::caller;
+use crate::shared::types::*;
+use crate::shared::storage as storage;
+use crate::doctor::interactions::find_profile_warnings;
+use crate::patient::profile::{has_profile_access, shared_health_profile};
+
+fn verify_doctor() -> Result<User> {
+    let user = storage::get_user_by_principal(&caller().to_string())
+        .ok_or("User not found".to_string())?;
+
+    match user.role {
+        UserRole::Doctor => Ok(user),
+        _ => Err("Only doctors can view patient health profiles".to_string()),
+    }
+}
+
+/// A patient's health profile, if they have shared it with the caller.
+#[ic_cdk::query]
+pub fn get_patient_health_profile(patient_id: String) -> Result<HealthProfile> {
+    let doctor = verify_doctor()?;
+    shared_health_profile(&patient_id, &doctor.id)
+}
+
+/// Patients who currently share their health profile with the caller.
+#[ic_cdk::query]
+pub fn get_consenting_patients() -> Result<Vec<ProfileConsent>> {
+    let doctor = verify_doctor()?;
+    Ok(storage::get_doctor_profile_consents(&doctor.id)
+        .into_iter()
+        .filter(|consent| has_profile_access(&consent.patient_id, &doctor.id))
+        .collect())
+}
+
+/// Check a proposed medicine list against a patient's shared profile before prescribing.
+#[ic_cdk::query]
+pub fn check_patient_profile(patient_id: String, medicine_ids: Vec<String>) -> Result<Vec<ProfileWarning>> {
+    let doctor = verify_doctor()?;
+    let profile = shared_health_profile(&patient_id, &doctor.id)?;
+    Ok(find_profile_warnings(&profile, &medicine_ids))
+}
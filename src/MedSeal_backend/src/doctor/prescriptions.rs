@@ -2,45 +2,510 @@ use ic_cdk::api::caller;
 use crate::shared::types::*;
 use crate::shared::storage as storage;
 use crate::shared::utils as utils;
+use crate::shared::dosage;
+use crate::doctor::interactions::{find_interactions, find_profile_warnings, profile_not_shared_warning};
+use crate::patient::profile::{has_profile_access, shared_health_profile};
+use std::time::Duration;
+
+const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+const EXPIRY_SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const MAX_REFILLS: u32 = 12;
+const MAX_REFILL_INTERVAL_DAYS: u64 = 365;
+/// Upper bound for `SystemSettings::prescription_validity_days`.
+pub const MAX_PRESCRIPTION_VALIDITY_DAYS: u64 = 3650;
+
+/// Whether a prescription may move from `from` to `to`.
+fn is_allowed_transition(from: &PrescriptionStatus, to: &PrescriptionStatus) -> bool {
+    use PrescriptionStatus::*;
+    matches!(
+        (from, to),
+        (Draft, Issued)
+            | (Draft, Cancelled)
+            | (Issued, PartiallyDispensed)
+            | (Issued, Dispensed)
+            | (Issued, Cancelled)
+            | (Issued, Expired)
+            | (PartiallyDispensed, PartiallyDispensed)
+            | (PartiallyDispensed, Dispensed)
+            | (PartiallyDispensed, Cancelled)
+            | (PartiallyDispensed, Expired)
+    )
+}
+
+// When a prescription issued or refilled at `now` stops being valid
+fn expiry_from(now: u64) -> Result<u64> {
+    storage::get_system_settings()
+        .prescription_validity_days
+        .checked_mul(NANOS_PER_DAY)
+        .and_then(|validity| now.checked_add(validity))
+        .ok_or("Prescription validity period is too long".to_string())
+}
+
+/// Moves a prescription to `next`, rejecting transitions the lifecycle doesn't allow.
+/// Callers persist the prescription afterwards.
+pub fn transition_prescription(prescription: &mut Prescription, next: PrescriptionStatus) -> Result<()> {
+    if !is_allowed_transition(&prescription.status, &next) {
+        return Err(format!(
+            "Cannot move prescription from {:?} to {:?}",
+            prescription.status, next
+        ));
+    }
+
+    let now = utils::get_current_timestamp();
+    if matches!(next, PrescriptionStatus::Issued) {
+        let expires_at = expiry_from(now)?;
+
+        // Pin each item to the medicine as the doctor sees it right now
+        for item in prescription.medicines.iter_mut() {
+            item.medicine_revision = storage::get_medicine(&item.medicine_id)
+                .and_then(|medicine| medicine.current_revision);
+        }
+
+        prescription.issued_at = Some(now);
+        prescription.expires_at = Some(expires_at);
+        if let Some(refills) = prescription.refills.as_mut() {
+            refills.current_fill_started_at = now;
+        }
+    }
+
+    prescription.status = next;
+    prescription.status_updated_at = now;
+    Ok(())
+}
+
+// When the next fill of a repeat prescription may start at the earliest
+fn earliest_refill(refills: &PrescriptionRefills) -> Result<u64> {
+    refills.min_interval_days
+        .checked_mul(NANOS_PER_DAY)
+        .and_then(|interval| refills.current_fill_started_at.checked_add(interval))
+        .ok_or("Refill interval is too long".to_string())
+}
+
+/// Starts the next fill of a fully dispensed repeat prescription. The refill
+/// gets a fresh validity window and goes back to `Issued` for dispensing.
+pub fn begin_refill(prescription: &mut Prescription) -> Result<()> {
+    if !matches!(prescription.status, PrescriptionStatus::Dispensed) {
+        return Err(format!(
+            "Refills can only be requested once the current fill is dispensed (status: {:?})",
+            prescription.status
+        ));
+    }
+
+    let now = utils::get_current_timestamp();
+    let refills = prescription.refills.as_mut()
+        .ok_or("This prescription has no refills".to_string())?;
+
+    if refills.refills_remaining == 0 {
+        return Err("No refills remaining on this prescription".to_string());
+    }
+
+    let earliest = earliest_refill(refills)?;
+    if now < earliest {
+        let days_left = (earliest - now).div_ceil(NANOS_PER_DAY);
+        return Err(format!("Refill requested too early; available in {} day(s)", days_left));
+    }
+
+    let expires_at = expiry_from(now)?;
+    refills.refills_remaining -= 1;
+    refills.current_fill += 1;
+    refills.current_fill_started_at = now;
+
+    prescription.status = PrescriptionStatus::Issued;
+    prescription.status_updated_at = now;
+    prescription.expires_at = Some(expires_at);
+    Ok(())
+}
+
+/// True once an issued prescription has outlived its validity period,
+/// even if the expiry sweep hasn't recorded it yet.
+pub fn is_past_expiry(prescription: &Prescription, now: u64) -> bool {
+    matches!(prescription.status, PrescriptionStatus::Issued | PrescriptionStatus::PartiallyDispensed)
+        && prescription.expires_at.map(|expires_at| now >= expires_at).unwrap_or(false)
+}
+
+/// Marks every overdue prescription as expired. Returns how many changed.
+pub fn expire_overdue_prescriptions() -> u64 {
+    let now = utils::get_current_timestamp();
+    let mut expired = 0;
+
+    for status in [PrescriptionStatus::Issued, PrescriptionStatus::PartiallyDispensed] {
+        for mut prescription in storage::get_prescriptions_by_status(status) {
+            if is_past_expiry(&prescription, now)
+                && transition_prescription(&mut prescription, PrescriptionStatus::Expired).is_ok()
+            {
+                storage::update_prescription(prescription);
+                expired += 1;
+            }
+        }
+    }
+
+    expired
+}
+
+/// Starts the periodic expiry sweep; timers don't survive upgrades, so this
+/// runs from both init and post_upgrade.
+pub fn schedule_prescription_expiry() {
+    ic_cdk_timers::set_timer_interval(EXPIRY_SWEEP_INTERVAL, || {
+        expire_overdue_prescriptions();
+    });
+}
+
+// Helper function to load a prescription owned by the calling doctor
+fn get_own_prescription(prescription_id: &str) -> Result<Prescription> {
+    let caller_principal = caller().to_string();
+
+    let user = storage::get_user_by_principal(&caller_principal)
+        .ok_or("User not found".to_string())?;
+
+    if !matches!(user.role, UserRole::Doctor) {
+        return Err("Only doctors can manage prescriptions".to_string());
+    }
+
+    let prescription = storage::get_prescription(prescription_id)
+        .ok_or("Prescription not found".to_string())?;
+
+    if prescription.doctor_id != user.id {
+        return Err("You can only manage your own prescriptions".to_string());
+    }
+
+    Ok(prescription)
+}
+
+/// Checks every prescribed item against the medicine catalog, collecting all
+/// problems so the doctor can fix them in one pass.
+fn validate_prescription_medicines(doctor_id: &str, medicines: &[PrescriptionMedicine]) -> Vec<PrescriptionItemError> {
+    let mut errors = Vec::new();
+    let mut seen: Vec<&str> = Vec::new();
+
+    for (index, item) in medicines.iter().enumerate() {
+        let mut push = |issue: PrescriptionItemIssue, message: String| {
+            errors.push(PrescriptionItemError {
+                index: index as u32,
+                medicine_id: item.medicine_id.clone(),
+                issue,
+                message,
+            });
+        };
+
+        if seen.contains(&item.medicine_id.as_str()) {
+            push(PrescriptionItemIssue::DuplicateEntry, "Medicine is listed more than once".to_string());
+            continue;
+        }
+        seen.push(&item.medicine_id);
+
+        if item.quantity == Some(0) {
+            push(PrescriptionItemIssue::InvalidQuantity, "Quantity must be positive".to_string());
+        }
+
+        if let Some(custom_dosage) = &item.custom_dosage {
+            if let Err(message) = dosage::validate_dosage(custom_dosage) {
+                push(PrescriptionItemIssue::InvalidDosage, message);
+            }
+        }
+
+        match storage::get_medicine(&item.medicine_id) {
+            None => push(PrescriptionItemIssue::UnknownMedicine, "Medicine not found".to_string()),
+            Some(medicine) if !medicine.is_active => {
+                push(PrescriptionItemIssue::InactiveMedicine, format!("{} is inactive", medicine.name));
+            }
+            Some(medicine) if medicine.formulary.is_none() && medicine.doctor_id != doctor_id => {
+                push(PrescriptionItemIssue::NotPrescribable, format!("{} is neither in the formulary nor in your medicine list", medicine.name));
+            }
+            Some(_) => {}
+        }
+    }
+
+    errors
+}
 
 /// Create a new prescription (doctor only)
 #[ic_cdk::update]
-pub fn create_prescription(request: CreatePrescriptionRequest) -> Result<String> {
+pub async fn create_prescription(request: CreatePrescriptionRequest) -> std::result::Result<CreatePrescriptionResponse, CreatePrescriptionError> {
     let caller_principal = caller().to_string();
 
+    // Codes must come from raw_rand-seeded randomness, never the fallback
+    utils::ensure_rng_seeded().await?;
+
     // Validate user is a doctor
     let user = match storage::get_user_by_principal(&caller_principal) {
         Some(u) => u,
-        None => return Err("User not found".to_string()),
+        None => return Err("User not found".to_string().into()),
     };
     
     if !matches!(user.role, UserRole::Doctor) {
-        return Err("Only doctors can create prescriptions".to_string());
+        return Err("Only doctors can create prescriptions".to_string().into());
+    }
+
+    if request.medicines.is_empty() {
+        return Err("A prescription must include at least one medicine".to_string().into());
     }
+
+    let item_errors = validate_prescription_medicines(&user.id, &request.medicines);
+    if !item_errors.is_empty() {
+        return Err(CreatePrescriptionError::InvalidItems(item_errors));
+    }
+
+    // Contraindicated combinations need an explicit, reasoned override
+    let medicine_ids: Vec<String> = request.medicines.iter().map(|m| m.medicine_id.clone()).collect();
+    let contraindicated: Vec<InteractionWarning> = find_interactions(&medicine_ids)
+        .into_iter()
+        .filter(|warning| warning.severity == InteractionSeverity::Contraindicated)
+        .collect();
+    let override_reason = request.interaction_override_reason
+        .clone()
+        .filter(|reason| !reason.trim().is_empty());
+    if !contraindicated.is_empty() && override_reason.is_none() {
+        return Err(CreatePrescriptionError::ContraindicatedInteractions(contraindicated));
+    }
+    let interaction_override_reason = if contraindicated.is_empty() { None } else { override_reason };
+
+    // Profile matches are reported back, not enforced; the doctor decides
+    let profile_warnings = match &request.patient_id {
+        Some(patient_id) if has_profile_access(patient_id, &user.id) => {
+            find_profile_warnings(&shared_health_profile(patient_id, &user.id)?, &medicine_ids)
+        }
+        Some(patient_id) => {
+            storage::get_user(patient_id)
+                .filter(|patient| patient.role == UserRole::Patient)
+                .ok_or("Patient not found".to_string())?;
+            vec![profile_not_shared_warning()]
+        }
+        None => Vec::new(),
+    };
     
-    let prescription_id = format!("prescription_{}", utils::generate_random_id());
-    let prescription_code = format!("RX{}", utils::generate_random_id() % 1_000_000);
+    let refills = match request.refills.unwrap_or(0) {
+        0 => None,
+        count if count > MAX_REFILLS => {
+            return Err(format!("A prescription can allow at most {} refills", MAX_REFILLS).into());
+        }
+        count => {
+            let min_interval_days = request.refill_interval_days
+                .filter(|days| *days > 0)
+                .ok_or("Refills require a minimum interval of at least one day".to_string())?;
+            if min_interval_days > MAX_REFILL_INTERVAL_DAYS {
+                return Err(format!("The refill interval cannot exceed {} days", MAX_REFILL_INTERVAL_DAYS).into());
+            }
+            Some(PrescriptionRefills {
+                refills_allowed: count,
+                refills_remaining: count,
+                min_interval_days,
+                current_fill: 0,
+                current_fill_started_at: 0,
+            })
+        }
+    };
+
+    let prescription_id = utils::generate_prescription_id()?;
+    let prescription_code = loop {
+        let code = utils::generate_prescription_code()?;
+        if !storage::prescription_code_exists(&code) {
+            break code;
+        }
+    };
     let current_time = utils::get_current_timestamp();
     
-    let prescription = Prescription {
+    let mut prescription = Prescription {
         id: prescription_id.clone(),
         prescription_code: prescription_code.clone(),
         patient_name: request.patient_name,
         patient_contact: request.patient_contact,
         patient_principal: None,
-        medicines: request.medicines,
+        // Revisions are pinned on issue, never taken from the caller
+        medicines: request.medicines
+            .into_iter()
+            .map(|item| PrescriptionMedicine { medicine_revision: None, ..item })
+            .collect(),
         additional_notes: request.additional_notes,
         created_at: current_time,
         accessed_at: None,
         doctor_id: user.id,
+        status: PrescriptionStatus::Draft,
+        status_updated_at: current_time,
+        issued_at: None,
+        expires_at: None,
+        cancellation_reason: None,
+        refills,
+        interaction_override_reason,
+        claimed_at: None,
     };
+
+    if !request.as_draft.unwrap_or(false) {
+        transition_prescription(&mut prescription, PrescriptionStatus::Issued)?;
+    }
     
     storage::store_prescription(prescription);
-    Ok(prescription_code)
+    Ok(CreatePrescriptionResponse { prescription_code, profile_warnings })
+}
+
+/// Issue a draft prescription, starting its validity period
+#[ic_cdk::update]
+pub fn issue_prescription(prescription_id: String) -> Result<Prescription> {
+    let mut prescription = get_own_prescription(&prescription_id)?;
+
+    transition_prescription(&mut prescription, PrescriptionStatus::Issued)?;
+    storage::update_prescription(prescription.clone());
+    Ok(prescription)
+}
+
+/// Cancel a prescription that hasn't been fully dispensed
+#[ic_cdk::update]
+pub fn cancel_prescription(prescription_id: String, reason: String) -> Result<Prescription> {
+    let mut prescription = get_own_prescription(&prescription_id)?;
+
+    if reason.trim().is_empty() {
+        return Err("A cancellation reason is required".to_string());
+    }
+
+    transition_prescription(&mut prescription, PrescriptionStatus::Cancelled)?;
+    prescription.cancellation_reason = Some(reason);
+    storage::update_prescription(prescription.clone());
+    Ok(prescription)
+}
+
+/// Give an issued prescription a fresh code and release its claim, for when
+/// the wrong person claimed it. The old code stops working, shared access is
+/// withdrawn and the previous claimant's dose schedules are stopped.
+#[ic_cdk::update]
+pub async fn reissue_prescription_code(prescription_id: String, reason: String) -> Result<Prescription> {
+    utils::ensure_rng_seeded().await?;
+
+    let mut prescription = get_own_prescription(&prescription_id)?;
+
+    if reason.trim().is_empty() {
+        return Err("A reason is required to re-issue a code".to_string());
+    }
+
+    if !matches!(prescription.status, PrescriptionStatus::Issued | PrescriptionStatus::PartiallyDispensed) {
+        return Err("Only issued prescriptions that are still open can be re-issued".to_string());
+    }
+
+    let old_code = prescription.prescription_code.clone();
+    prescription.prescription_code = loop {
+        let code = utils::generate_prescription_code()?;
+        if !storage::prescription_code_exists(&code) {
+            break code;
+        }
+    };
+    prescription.patient_principal = None;
+    prescription.claimed_at = None;
+
+    for share in storage::get_prescription_shares(&prescription.id) {
+        storage::remove_prescription_share(&prescription.id, &share.principal);
+    }
+    for mut schedule in storage::get_prescription_dose_schedules(&prescription.id) {
+        if schedule.active {
+            schedule.active = false;
+            storage::store_dose_schedule(schedule);
+        }
+    }
+
+    storage::replace_prescription_code(&old_code, prescription.clone());
+    storage::record_prescription_access(&prescription.id, "reissue_prescription_code", AccessOutcome::Granted);
+    storage::append_audit_entry(
+        caller().to_string(),
+        "reissue_prescription_code",
+        prescription.id.clone(),
+        reason.trim().to_string(),
+        utils::get_current_timestamp(),
+    );
+    Ok(prescription)
+}
+
+/// Prescriptions issued by `doctor_id`, listed only to that doctor since
+/// their codes are what patients claim them with.
+pub fn own_doctor_prescriptions(doctor_id: &str) -> Result<Vec<Prescription>> {
+    match storage::get_user_by_principal(&caller().to_string()) {
+        Some(user) if user.role == UserRole::Doctor && user.id == doctor_id => {
+            Ok(storage::get_doctor_prescriptions(&user.id))
+        }
+        _ => Err("You can only list your own prescriptions".to_string()),
+    }
 }
 
 /// Query prescriptions belonging to a doctor
 #[ic_cdk::query]
-pub fn get_prescriptions_by_doctor(doctor_id: String) -> Vec<Prescription> {
-    storage::get_doctor_prescriptions(&doctor_id)
-}
\ No newline at end of file
+pub fn get_prescriptions_by_doctor(doctor_id: String) -> Result<Vec<Prescription>> {
+    own_doctor_prescriptions(&doctor_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_prescription() -> Prescription {
+        Prescription {
+            id: "prescription_1".to_string(),
+            prescription_code: "RX123456".to_string(),
+            patient_name: "Patient".to_string(),
+            patient_contact: String::new(),
+            patient_principal: None,
+            medicines: Vec::new(),
+            additional_notes: String::new(),
+            created_at: 0,
+            accessed_at: None,
+            doctor_id: "user_1".to_string(),
+            status: PrescriptionStatus::Draft,
+            status_updated_at: 0,
+            issued_at: None,
+            expires_at: None,
+            cancellation_reason: None,
+            refills: None,
+            interaction_override_reason: None,
+            claimed_at: None,
+        }
+    }
+
+    fn refills(min_interval_days: u64, current_fill_started_at: u64) -> PrescriptionRefills {
+        PrescriptionRefills {
+            refills_allowed: 3,
+            refills_remaining: 3,
+            min_interval_days,
+            current_fill: 0,
+            current_fill_started_at,
+        }
+    }
+
+    #[test]
+    fn lifecycle_allows_only_forward_transitions() {
+        use PrescriptionStatus::*;
+        assert!(is_allowed_transition(&Draft, &Issued));
+        assert!(is_allowed_transition(&Issued, &PartiallyDispensed));
+        assert!(is_allowed_transition(&PartiallyDispensed, &Dispensed));
+        assert!(is_allowed_transition(&Issued, &Expired));
+        assert!(is_allowed_transition(&PartiallyDispensed, &Cancelled));
+
+        assert!(!is_allowed_transition(&Draft, &Dispensed));
+        assert!(!is_allowed_transition(&Dispensed, &Issued));
+        assert!(!is_allowed_transition(&Dispensed, &Cancelled));
+        assert!(!is_allowed_transition(&Cancelled, &Issued));
+        assert!(!is_allowed_transition(&Expired, &Issued));
+        assert!(!is_allowed_transition(&Issued, &Draft));
+    }
+
+    #[test]
+    fn past_expiry_only_applies_to_open_prescriptions() {
+        let mut prescription = test_prescription();
+        prescription.status = PrescriptionStatus::Issued;
+        prescription.expires_at = Some(100);
+        assert!(!is_past_expiry(&prescription, 99));
+        assert!(is_past_expiry(&prescription, 100));
+
+        prescription.status = PrescriptionStatus::Dispensed;
+        assert!(!is_past_expiry(&prescription, 100));
+
+        prescription.status = PrescriptionStatus::Issued;
+        prescription.expires_at = None;
+        assert!(!is_past_expiry(&prescription, u64::MAX));
+    }
+
+    #[test]
+    fn earliest_refill_adds_the_interval_to_the_fill_start() {
+        assert_eq!(earliest_refill(&refills(30, 1_000)), Ok(1_000 + 30 * NANOS_PER_DAY));
+    }
+
+    #[test]
+    fn earliest_refill_rejects_overflowing_intervals() {
+        assert!(earliest_refill(&refills(u64::MAX / NANOS_PER_DAY + 1, 0)).is_err());
+        assert!(earliest_refill(&refills(MAX_REFILL_INTERVAL_DAYS, u64::MAX)).is_err());
+    }
+}
@@ -13,12 +13,12 @@ pub fn submit_verification_request(request: SubmitVerificationRequest) -> Result
         None => return Err("User not found".to_string()),
     };
     
-    // Only doctors and NGOs can submit verification requests
-    if !matches!(user.role, UserRole::Doctor | UserRole::NGO) {
-        return Err("Only doctors and NGOs can submit verification requests".to_string());
+    // Only doctors, NGOs and pharmacies can submit verification requests
+    if !matches!(user.role, UserRole::Doctor | UserRole::NGO | UserRole::Pharmacy) {
+        return Err("Only doctors, NGOs and pharmacies can submit verification requests".to_string());
     }
     
-    let request_id = format!("verification_{}", utils::generate_random_id());
+    let request_id = utils::generate_id("verification")?;
     let current_time = utils::get_current_timestamp();
     
     let verification_request = VerificationRequest {
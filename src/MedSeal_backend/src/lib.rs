@@ -1,3 +1,5 @@
+#![allow(non_snake_case)] // crate name follows the dfx project name
+
 use candid::{CandidType, Deserialize};
 
 // Module declarations
@@ -7,6 +9,7 @@ pub mod shared;
 pub mod ai;
 pub mod ngo;
 pub mod admin;
+pub mod pharmacy;
 
 // Export service modules
 pub use shared::auth::*;
@@ -14,11 +17,15 @@ pub use admin::*;
 pub use doctor::verification::*; // Add this export
 pub use patient::prescriptions::*; // Add this export
 pub use ngo::*;
+pub use pharmacy::*;
 pub use ai::*;
 
 // Avoid ambiguous glob re-exports; export doctor prescription functions explicitly
 pub use doctor::prescriptions::{
     create_prescription,
+    issue_prescription,
+    cancel_prescription,
+    reissue_prescription_code,
     get_prescriptions_by_doctor, // Updated to match renamed function
     // other doctor::prescriptions exports if needed
 };
@@ -30,8 +37,42 @@ pub use doctor::medicines::{
     get_medicine,
     get_doctor_medicines,
     toggle_medicine_status,
+    get_formulary,
+    search_medicines,
+    get_medicine_history,
+    get_medicine_revision,
+    set_medicine_overlay,
+    remove_medicine_overlay,
 };
 
+pub use shared::migrations::get_schema_info;
+
+// Canister lifecycle
+#[ic_cdk::init]
+fn init(args: Option<shared::types::InitArgs>) {
+    // Fresh canisters start on the current schema; nothing to migrate.
+    shared::storage::set_schema_version(shared::migrations::CURRENT_SCHEMA_VERSION);
+    shared::auth::bootstrap_admin(args);
+    shared::utils::schedule_rng_seed();
+    doctor::prescriptions::schedule_prescription_expiry();
+    patient::adherence::schedule_dose_reminders();
+    ngo::settlement::schedule_pool_settlement();
+}
+
+#[ic_cdk::post_upgrade]
+fn post_upgrade(args: Option<shared::types::InitArgs>) {
+    shared::migrations::run_migrations();
+    shared::storage::ensure_medicine_search_index();
+    shared::storage::ensure_medicine_revisions();
+    shared::storage::ensure_formulary_codes();
+    shared::auth::backfill_admin_permissions();
+    shared::auth::bootstrap_admin(args);
+    shared::utils::schedule_rng_seed();
+    doctor::prescriptions::schedule_prescription_expiry();
+    patient::adherence::schedule_dose_reminders();
+    ngo::settlement::schedule_pool_settlement();
+}
+
 // ICRC standards support for NFID
 #[derive(CandidType, Deserialize, Eq, PartialEq, Debug)]
 pub struct SupportedStandard {
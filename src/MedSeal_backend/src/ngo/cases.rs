@@ -1,53 +1,37 @@
-use ic_cdk::api::caller;
+use crate::shared::auth::require_admin_permission;
+use crate::shared::ledger;
+use crate::shared::storage;
 use crate::shared::types::*;
-use crate::shared::storage as storage;
-use crate::shared::utils as utils;
+use crate::shared::utils;
+use candid::{Nat, Principal};
+use ic_cdk::api::caller;
 
 // Helper function to verify patient role
 fn verify_patient() -> Result<User> {
     let caller_principal = caller().to_string();
-    
+
     match storage::get_user_by_principal(&caller_principal) {
-        Some(user) => {
-            match user.role {
-                UserRole::Patient => Ok(user),
-                _ => Err("Only patients can submit cases".to_string()),
-            }
+        Some(user) => match user.role {
+            UserRole::Patient => Ok(user),
+            _ => Err("Only patients can submit cases".to_string()),
         },
         None => Err("User not found".to_string()),
     }
 }
 
 // Helper function to verify NGO role
-fn verify_ngo() -> Result<User> {
+pub(crate) fn verify_ngo() -> Result<User> {
     let caller_principal = caller().to_string();
-    
-    match storage::get_user_by_principal(&caller_principal) {
-        Some(user) => {
-            match user.role {
-                UserRole::NGO => {
-                    if user.verification_status != VerificationStatus::Approved {
-                        return Err("NGO must be verified to create contribution pools".to_string());
-                    }
-                    Ok(user)
-                }
-                _ => Err("Only NGOs can create contribution pools".to_string()),
-            }
-        },
-        None => Err("User not found".to_string()),
-    }
-}
 
-// Helper function to verify admin role
-fn verify_admin() -> Result<User> {
-    let caller_principal = caller().to_string();
-    
     match storage::get_user_by_principal(&caller_principal) {
-        Some(user) => {
-            match user.role {
-                UserRole::Admin => Ok(user),
-                _ => Err("Only admins can process cases".to_string()),
+        Some(user) => match user.role {
+            UserRole::NGO => {
+                if user.verification_status != VerificationStatus::Approved {
+                    return Err("NGO must be verified to create contribution pools".to_string());
+                }
+                Ok(user)
             }
+            _ => Err("Only NGOs can create contribution pools".to_string()),
         },
         None => Err("User not found".to_string()),
     }
@@ -56,8 +40,8 @@ fn verify_admin() -> Result<User> {
 #[ic_cdk::update]
 pub fn submit_patient_case(request: SubmitCaseRequest) -> Result<String> {
     let patient = verify_patient()?;
-    
-    let case_id = utils::generate_id("case");
+
+    let case_id = utils::generate_id("case")?;
     let patient_case = PatientCase {
         id: case_id.clone(),
         patient_id: patient.id.clone(),
@@ -75,20 +59,20 @@ pub fn submit_patient_case(request: SubmitCaseRequest) -> Result<String> {
         reviewed_by: None,
         admin_notes: None,
     };
-    
+
     storage::store_patient_case(patient_case);
     Ok(case_id)
 }
 
 #[ic_cdk::query]
 pub fn get_all_patient_cases() -> Result<Vec<PatientCase>> {
-    let _admin = verify_admin()?;
+    let _admin = require_admin_permission(AdminPermission::ReviewCases)?;
     Ok(storage::get_all_patient_cases())
 }
 
 #[ic_cdk::query]
 pub fn get_pending_patient_cases() -> Result<Vec<PatientCase>> {
-    let _admin = verify_admin()?;
+    let _admin = require_admin_permission(AdminPermission::ReviewCases)?;
     Ok(storage::get_patient_cases_by_status(CaseStatus::Pending))
 }
 
@@ -96,11 +80,13 @@ pub fn get_pending_patient_cases() -> Result<Vec<PatientCase>> {
 pub fn get_approved_patient_cases() -> Result<Vec<PatientCase>> {
     // NGOs can view approved cases to create pools
     let caller_principal = caller().to_string();
-    let user = storage::get_user_by_principal(&caller_principal)
-        .ok_or("User not found".to_string())?;
-    
+    let user =
+        storage::get_user_by_principal(&caller_principal).ok_or("User not found".to_string())?;
+
     match user.role {
-        UserRole::NGO | UserRole::Admin => {
+        UserRole::NGO => Ok(storage::get_patient_cases_by_status(CaseStatus::Approved)),
+        UserRole::Admin => {
+            require_admin_permission(AdminPermission::ReviewCases)?;
             Ok(storage::get_patient_cases_by_status(CaseStatus::Approved))
         }
         _ => Err("Only NGOs and admins can view approved cases".to_string()),
@@ -110,15 +96,17 @@ pub fn get_approved_patient_cases() -> Result<Vec<PatientCase>> {
 #[ic_cdk::query]
 pub fn get_patient_case(case_id: String) -> Result<PatientCase> {
     let caller_principal = caller().to_string();
-    let user = storage::get_user_by_principal(&caller_principal)
-        .ok_or("User not found".to_string())?;
-    
-    let case = storage::get_patient_case(&case_id)
-        .ok_or("Case not found".to_string())?;
-    
+    let user =
+        storage::get_user_by_principal(&caller_principal).ok_or("User not found".to_string())?;
+
+    let case = storage::get_patient_case(&case_id).ok_or("Case not found".to_string())?;
+
     // Patients can view their own cases, NGOs and admins can view approved cases
     match user.role {
-        UserRole::Admin => Ok(case),
+        UserRole::Admin => {
+            require_admin_permission(AdminPermission::ReviewCases)?;
+            Ok(case)
+        }
         UserRole::Patient => {
             if case.patient_id == user.id {
                 Ok(case)
@@ -139,49 +127,60 @@ pub fn get_patient_case(case_id: String) -> Result<PatientCase> {
 
 #[ic_cdk::update]
 pub fn process_patient_case(request: ProcessCaseRequest) -> Result<String> {
-    let admin = verify_admin()?;
-    
-    let mut case = storage::get_patient_case(&request.case_id)
-        .ok_or("Case not found".to_string())?;
-    
+    let admin = require_admin_permission(AdminPermission::ReviewCases)?;
+
+    let mut case =
+        storage::get_patient_case(&request.case_id).ok_or("Case not found".to_string())?;
+
     case.status = request.status.clone();
     case.reviewed_at = Some(utils::get_current_timestamp());
     case.reviewed_by = Some(admin.id);
     case.admin_notes = request.admin_notes;
-    
+
     storage::update_patient_case(&request.case_id, case);
-    
+
     let status_msg = match request.status {
         CaseStatus::Approved => "approved",
         CaseStatus::Rejected => "rejected",
         _ => "processed",
     };
-    
+
     Ok(format!("Case {}", status_msg))
 }
 
 #[ic_cdk::update]
 pub fn create_contribution_pool(request: CreatePoolRequest) -> Result<String> {
     let ngo = verify_ngo()?;
-    
+
     // Verify the case exists and is approved
-    let case = storage::get_patient_case(&request.case_id)
-        .ok_or("Case not found".to_string())?;
-    
+    let case = storage::get_patient_case(&request.case_id).ok_or("Case not found".to_string())?;
+
     if !matches!(case.status, CaseStatus::Approved) {
         return Err("Can only create pools for approved cases".to_string());
     }
-    
+
     // Check if a pool already exists for this case
     if storage::get_pool_by_case_id(&request.case_id).is_some() {
         return Err("A contribution pool already exists for this case".to_string());
     }
-    
-    let pool_id = utils::generate_id("pool");
-    let deadline = request.deadline_days.map(|days| {
-        utils::get_current_timestamp() + (days * 24 * 60 * 60 * 1000000000) // Convert days to nanoseconds
-    });
-    
+
+    if request.target_amount == 0 {
+        return Err("Target amount must be positive".to_string());
+    }
+    if let Some(rules) = &request.rules {
+        validate_contribution_rules(rules, request.target_amount)?;
+    }
+
+    let pool_id = utils::generate_id("pool")?;
+    let deadline = match request.deadline_days {
+        Some(days) => Some(
+            days.checked_mul(24 * 60 * 60 * 1000000000) // Convert days to nanoseconds
+                .and_then(|nanos| utils::get_current_timestamp().checked_add(nanos))
+                .ok_or("Deadline is too far in the future".to_string())?,
+        ),
+        None => None,
+    };
+
     let pool = ContributionPool {
         id: pool_id.clone(),
         case_id: request.case_id,
@@ -196,12 +195,55 @@ pub fn create_contribution_pool(request: CreatePoolRequest) -> Result<String> {
         deadline,
         is_active: true,
         is_completed: false,
+        expired_at: None,
+        settlement: None,
+        rules: request.rules,
+        held_surplus: None,
     };
-    
+
     storage::store_contribution_pool(pool);
     Ok(pool_id)
 }
 
+fn validate_contribution_rules(rules: &ContributionRules, target_amount: u64) -> Result<()> {
+    if rules.min_contribution == Some(0) || rules.max_per_contributor == Some(0) {
+        return Err("Contribution limits must be positive".to_string());
+    }
+    if let (Some(min), Some(max)) = (rules.min_contribution, rules.max_per_contributor) {
+        if min > max {
+            return Err(
+                "Minimum contribution cannot exceed the per-contributor maximum".to_string(),
+            );
+        }
+    }
+    if rules
+        .min_contribution
+        .map(|min| min > target_amount)
+        .unwrap_or(false)
+    {
+        return Err("Minimum contribution cannot exceed the target amount".to_string());
+    }
+    Ok(())
+}
+
+/// Change a pool's contribution limits and surplus policy. Applies to
+/// contributions made from now on.
+#[ic_cdk::update]
+pub fn update_pool_rules(pool_id: String, rules: ContributionRules) -> Result<ContributionPool> {
+    let ngo = verify_ngo()?;
+    let mut pool = storage::get_contribution_pool(&pool_id).ok_or("Pool not found".to_string())?;
+
+    if pool.ngo_id != ngo.id {
+        return Err("You can only change your own pools".to_string());
+    }
+    ensure_pool_open(&pool)?;
+    validate_contribution_rules(&rules, pool.target_amount)?;
+
+    pool.rules = Some(rules);
+    storage::update_contribution_pool(&pool_id, pool.clone());
+    Ok(pool)
+}
+
 #[ic_cdk::query]
 pub fn get_contribution_pools() -> Result<Vec<ContributionPool>> {
     Ok(storage::get_all_contribution_pools())
@@ -215,9 +257,9 @@ pub fn get_active_contribution_pools() -> Result<Vec<ContributionPool>> {
 #[ic_cdk::query]
 pub fn get_ngo_contribution_pools(ngo_id: String) -> Result<Vec<ContributionPool>> {
     let caller_principal = caller().to_string();
-    let user = storage::get_user_by_principal(&caller_principal)
-        .ok_or("User not found".to_string())?;
-    
+    let user =
+        storage::get_user_by_principal(&caller_principal).ok_or("User not found".to_string())?;
+
     match user.role {
         UserRole::NGO => {
             if user.id != ngo_id {
@@ -225,97 +267,870 @@ pub fn get_ngo_contribution_pools(ngo_id: String) -> Result<Vec<ContributionPool
             }
             Ok(storage::get_contribution_pools_by_ngo(&ngo_id))
         }
-        UserRole::Admin => Ok(storage::get_contribution_pools_by_ngo(&ngo_id)),
+        UserRole::Admin => {
+            require_admin_permission(AdminPermission::ViewAnalytics)?;
+            Ok(storage::get_contribution_pools_by_ngo(&ngo_id))
+        }
         _ => Err("Access denied".to_string()),
     }
 }
 
 #[ic_cdk::query]
 pub fn get_contribution_pool(pool_id: String) -> Result<ContributionPool> {
-    storage::get_contribution_pool(&pool_id)
-        .ok_or("Pool not found".to_string())
+    storage::get_contribution_pool(&pool_id).ok_or("Pool not found".to_string())
 }
 
-#[ic_cdk::update]
-pub fn contribute_to_pool(request: ContributeRequest) -> Result<String> {
-    let caller_principal = caller().to_string();
-    
-    let mut pool = storage::get_contribution_pool(&request.pool_id)
-        .ok_or("Pool not found".to_string())?;
-    
+// Checks a pool can still take contributions
+pub(crate) fn ensure_pool_open(pool: &ContributionPool) -> Result<()> {
+    pool_open_at(pool, utils::get_current_timestamp())
+}
+
+fn pool_open_at(pool: &ContributionPool, now: u64) -> Result<()> {
     if !pool.is_active {
         return Err("Pool is not active".to_string());
     }
-    
+
     if pool.is_completed {
         return Err("Pool is already completed".to_string());
     }
-    
+
     // Check deadline
     if let Some(deadline) = pool.deadline {
-        if utils::get_current_timestamp() > deadline {
+        if now > deadline {
             return Err("Pool deadline has passed".to_string());
         }
     }
-    
-    let contribution_id = utils::generate_id("contrib");
-    let contribution = Contribution {
-        id: contribution_id.clone(),
-        pool_id: request.pool_id,
-        contributor_principal: caller_principal,
-        amount: request.amount,
-        message: request.message,
-        contributed_at: utils::get_current_timestamp(),
-        is_anonymous: request.is_anonymous,
-    };
-    
-    // Update pool statistics
-    pool.current_amount += request.amount;
-    pool.contributors_count += 1;
-    
-    // Check if pool is completed
+
+    Ok(())
+}
+
+// Adds funds to a pool's totals up to its target, marking the pool completed
+// once the target is reached. Returns the surplus that did not fit.
+fn apply_pool_funding(pool: &mut ContributionPool, amount: u64, contributors: u64) -> u64 {
+    let accepted = amount.min(pool.target_amount.saturating_sub(pool.current_amount));
+    pool.current_amount += accepted;
+    pool.contributors_count = pool.contributors_count.saturating_add(contributors);
+
     if pool.current_amount >= pool.target_amount {
         pool.is_completed = true;
-        
-        // Update case status to funded
+    }
+
+    amount - accepted
+}
+
+// Adds funds to a pool, marking its case funded once the target is reached.
+// Returns the surplus that did not fit.
+pub(crate) fn record_pool_funding(
+    pool: &mut ContributionPool,
+    amount: u64,
+    contributors: u64,
+) -> u64 {
+    let surplus = apply_pool_funding(pool, amount, contributors);
+    mark_case_funded(pool);
+    surplus
+}
+
+fn mark_case_funded(pool: &ContributionPool) {
+    if pool.is_completed {
         if let Some(mut case) = storage::get_patient_case(&pool.case_id) {
             case.status = CaseStatus::Funded;
             storage::update_patient_case(&pool.case_id, case);
         }
     }
-    
-    storage::store_contribution(contribution);
-    let pool_id_clone = pool.id.clone();
-    storage::update_contribution_pool(&pool_id_clone, pool);
-    
+}
+
+// What a contributor has put into a pool so far
+fn contributor_total(pool_id: &str, contributor: &str) -> u64 {
+    storage::get_contributions_by_pool(pool_id)
+        .iter()
+        .filter(|contribution| contribution.contributor_principal == contributor)
+        .fold(0u64, |total, contribution| {
+            total.saturating_add(contribution.amount)
+        })
+}
+
+// How much of `amount` goes past the pool's per-contributor maximum, given
+// what the contributor has already put in
+fn contributor_excess(pool: &ContributionPool, given: u64, amount: u64) -> u64 {
+    match pool
+        .rules
+        .as_ref()
+        .and_then(|rules| rules.max_per_contributor)
+    {
+        Some(max) => amount.saturating_sub(max.saturating_sub(given)),
+        None => 0,
+    }
+}
+
+// Works out how much of a contribution the pool can take, clipping it to the
+// remaining target if the contributor allowed that. `given` is what the
+// contributor has put into the pool before.
+fn accepted_contribution(
+    pool: &ContributionPool,
+    given: u64,
+    request: &ContributeRequest,
+) -> Result<u64> {
+    let remaining = pool.target_amount.saturating_sub(pool.current_amount);
+    if remaining == 0 {
+        return Err("Pool has already reached its target".to_string());
+    }
+
+    let amount = if request.amount <= remaining {
+        request.amount
+    } else if request.allow_partial.unwrap_or(false) {
+        remaining
+    } else {
+        return Err(format!(
+            "Only {} is still needed to reach the target",
+            remaining
+        ));
+    };
+
+    let Some(rules) = &pool.rules else {
+        return Ok(amount);
+    };
+
+    // The last contribution may be smaller than the minimum if that is all that is left
+    if let Some(min) = rules.min_contribution {
+        if amount < min && amount != remaining {
+            return Err(format!("The minimum contribution to this pool is {}", min));
+        }
+    }
+
+    if contributor_excess(pool, given, amount) > 0 {
+        let left = rules
+            .max_per_contributor
+            .unwrap_or(u64::MAX)
+            .saturating_sub(given);
+        return Err(format!(
+            "You can contribute at most {} more to this pool",
+            left
+        ));
+    }
+
+    Ok(amount)
+}
+
+// Adds a contribution whose tokens have landed to the pool, returning the part
+// that did not fit: anything past the target or the contributor's limit, which
+// either may have been reached while the transfer was in flight. A pool that
+// closed meanwhile takes nothing and the whole amount goes back.
+fn take_landed_contribution(
+    pool: &mut ContributionPool,
+    given: u64,
+    amount: u64,
+    now: u64,
+) -> Option<ContributionSurplus> {
+    let (surplus, policy) = if pool_open_at(pool, now).is_err() {
+        (amount, SurplusPolicy::Return)
+    } else {
+        let over_limit = contributor_excess(pool, given, amount);
+        let policy = pool
+            .rules
+            .as_ref()
+            .map(|rules| rules.surplus_policy.clone())
+            .unwrap_or(SurplusPolicy::Return);
+        (
+            over_limit + apply_pool_funding(pool, amount - over_limit, 1),
+            policy,
+        )
+    };
+
+    (surplus > 0).then_some(ContributionSurplus {
+        amount: surplus,
+        policy,
+        ledger_block_index: None,
+        error: None,
+        ledger_fee: None,
+        transfer_created_at: None,
+        failed_at: None,
+    })
+}
+
+// Adds surplus that stays in a pool's escrow to the pool's held amount
+pub(crate) fn hold_surplus(pool_id: &str, amount: u64) {
+    if let Some(mut pool) = storage::get_contribution_pool(pool_id) {
+        pool.held_surplus = Some(pool.held_surplus.unwrap_or(0).saturating_add(amount));
+        storage::update_contribution_pool(pool_id, pool);
+    }
+}
+
+// Moves a contribution's surplus on as its policy says. A transfer whose
+// outcome is unknown is left pending and the settlement sweep retries it with
+// the same fee and timestamp, so the ledger deduplicates it if an earlier
+// attempt went through. One the ledger refused is left for an admin.
+async fn transfer_surplus(
+    ledger_id: Principal,
+    pool: &ContributionPool,
+    contribution: &mut Contribution,
+) {
+    let Some(mut surplus) = contribution.surplus.clone() else {
+        return;
+    };
+
+    let destination = match surplus.policy {
+        SurplusPolicy::Return => Principal::from_text(&contribution.contributor_principal)
+            .map(|owner| ledger::Account {
+                owner,
+                subaccount: None,
+            })
+            .map_err(|_| "Invalid contributor principal".to_string()),
+        SurplusPolicy::RedirectToNgoFund => Ok(ledger::ngo_fund_account(&pool.ngo_id)),
+        SurplusPolicy::Hold => return,
+    };
+    let fee = match surplus.ledger_fee {
+        Some(fee) => Ok(fee),
+        None => ledger::fee(ledger_id).await,
+    };
+
+    match (destination, fee) {
+        (Ok(_), Ok(fee)) if surplus.amount <= fee => {
+            // Too small to move under any policy, so it stays in the escrow
+            surplus.transfer_created_at = None;
+            surplus.error = Some(
+                "The surplus is too small to cover the ledger fee and is held in the pool"
+                    .to_string(),
+            );
+            hold_surplus(&pool.id, surplus.amount);
+        }
+        (Ok(destination), Ok(fee)) => {
+            surplus.ledger_fee = Some(fee);
+            let created_at_time = *surplus
+                .transfer_created_at
+                .get_or_insert_with(utils::get_current_timestamp);
+            contribution.surplus = Some(surplus.clone());
+            storage::store_contribution(contribution.clone());
+
+            let result = ledger::transfer(
+                ledger_id,
+                ledger::pool_subaccount(&pool.id),
+                destination,
+                surplus.amount - fee,
+                fee,
+                contribution.id.as_bytes().to_vec(),
+                created_at_time,
+            )
+            .await;
+            match result {
+                Ok(block_index) => {
+                    surplus.ledger_block_index = Some(block_index);
+                    surplus.error = None;
+                }
+                Err(failure) => {
+                    // Retrying the same transfer cannot help: either nothing
+                    // moved, or the ledger can no longer tell
+                    if failure.is_final() {
+                        surplus.ledger_fee = None;
+                        surplus.transfer_created_at = None;
+                    }
+                    if failure.is_final() || failure.needs_lookup() {
+                        surplus.failed_at = Some(utils::get_current_timestamp());
+                    }
+                    surplus.error = Some(failure.into());
+                }
+            }
+        }
+        (Err(message), _) => {
+            surplus.transfer_created_at = None;
+            surplus.failed_at = Some(utils::get_current_timestamp());
+            surplus.error = Some(message);
+        }
+        (_, Err(message)) => {
+            surplus
+                .transfer_created_at
+                .get_or_insert_with(utils::get_current_timestamp);
+            surplus.error = Some(message);
+        }
+    }
+
+    contribution.surplus = Some(surplus);
+    storage::store_contribution(contribution.clone());
+}
+
+/// Retries every surplus transfer that has not gone through yet. Run by the
+/// settlement sweep.
+pub(crate) async fn retry_pending_surpluses(ledger_id: Principal) {
+    for mut contribution in storage::get_contributions_with_pending_surplus() {
+        if let Some(pool) = storage::get_contribution_pool(&contribution.pool_id) {
+            transfer_surplus(ledger_id, &pool, &mut contribution).await;
+        }
+    }
+}
+
+/// Retries the surplus transfers still pending out of one pool, so settling
+/// an expired pool does not wait for the next sweep.
+pub(crate) async fn retry_pool_surpluses(ledger_id: Principal, pool: &ContributionPool) {
+    let pending = storage::get_contributions_by_pool(&pool.id)
+        .into_iter()
+        .filter(|contribution| {
+            contribution
+                .surplus
+                .as_ref()
+                .is_some_and(ContributionSurplus::is_pending)
+        });
+    for mut contribution in pending {
+        transfer_surplus(ledger_id, pool, &mut contribution).await;
+    }
+}
+
+/// Surplus still sitting in the pool's subaccount but owed elsewhere: pending
+/// transfers and ones the ledger refused. Held surplus is not owed elsewhere.
+pub(crate) fn surplus_owed(contributions: &[Contribution]) -> u64 {
+    contributions
+        .iter()
+        .filter_map(|contribution| contribution.surplus.as_ref())
+        .filter(|surplus| surplus.is_pending() || surplus.is_failed())
+        .fold(0u64, |total, surplus| total.saturating_add(surplus.amount))
+}
+
+/// Contribute tokens to a pool. The caller must first `icrc2_approve` this
+/// canister on the configured ledger for the amount plus the ledger fee; the
+/// tokens move into the pool's subaccount and the contribution is recorded
+/// only once the ledger has confirmed the transfer.
+#[ic_cdk::update]
+pub async fn contribute_to_pool(request: ContributeRequest) -> Result<String> {
+    let caller_principal = caller();
+
+    // Anything sent back to the anonymous principal could be taken by anyone
+    if caller_principal == Principal::anonymous() {
+        return Err("Sign in to contribute".to_string());
+    }
+    if request.amount == 0 {
+        return Err("Contribution amount must be positive".to_string());
+    }
+
+    let ledger_id = ledger::configured_ledger()?;
+    let pool =
+        storage::get_contribution_pool(&request.pool_id).ok_or("Pool not found".to_string())?;
+    ensure_pool_open(&pool)?;
+    let given = contributor_total(&pool.id, &caller_principal.to_string());
+    let amount = accepted_contribution(&pool, given, &request)?;
+
+    let contribution_id = utils::generate_id("contrib")?;
+    let block_index = ledger::transfer_from(
+        ledger_id,
+        ledger::Account {
+            owner: caller_principal,
+            subaccount: None,
+        },
+        ledger::pool_account(&pool.id),
+        amount,
+        contribution_id.as_bytes().to_vec(),
+        utils::get_current_timestamp(),
+    )
+    .await?;
+
+    // The pool may have changed while the transfer was in flight; the tokens
+    // have moved regardless, so the contribution is always recorded
+    let mut pool =
+        storage::get_contribution_pool(&request.pool_id).ok_or("Pool not found".to_string())?;
+    let now = utils::get_current_timestamp();
+    let pool_was_open = pool_open_at(&pool, now).is_ok();
+    let given = contributor_total(&pool.id, &caller_principal.to_string());
+    let surplus = take_landed_contribution(&mut pool, given, amount, now);
+    mark_case_funded(&pool);
+
+    let mut contribution = Contribution {
+        id: contribution_id.clone(),
+        pool_id: request.pool_id,
+        contributor_principal: caller_principal.to_string(),
+        amount: amount - surplus.as_ref().map_or(0, |surplus| surplus.amount),
+        message: request.message,
+        contributed_at: now,
+        is_anonymous: request.is_anonymous,
+        ledger_block_index: Some(block_index),
+        // Nothing of it is in the pool for settlement to pay back or move
+        outcome: (!pool_was_open).then(|| ContributionOutcome::NotRefunded(
+            "The pool had closed by the time the contribution landed; it is returned as surplus".to_string(),
+        )),
+        surplus,
+        refund_created_at: None,
+    };
+
+    storage::store_contribution(contribution.clone());
+    storage::update_contribution_pool(&pool.id, pool.clone());
+
+    match contribution
+        .surplus
+        .as_ref()
+        .map(|surplus| (surplus.policy.clone(), surplus.amount))
+    {
+        Some((SurplusPolicy::Hold, amount)) => hold_surplus(&pool.id, amount),
+        Some(_) => transfer_surplus(ledger_id, &pool, &mut contribution).await,
+        None => {}
+    }
+
     Ok(contribution_id)
 }
 
+/// The ledger account a pool's funds are held in.
+#[ic_cdk::query]
+pub fn get_pool_account(pool_id: String) -> Result<PoolAccount> {
+    let ledger_canister_id = ledger::configured_ledger()?;
+    let pool = storage::get_contribution_pool(&pool_id).ok_or("Pool not found".to_string())?;
+
+    Ok(PoolAccount {
+        ledger_canister_id,
+        owner: ic_cdk::api::id(),
+        subaccount: ledger::pool_subaccount(&pool.id),
+    })
+}
+
+/// The ledger account surplus is redirected to under
+/// `SurplusPolicy::RedirectToNgoFund`. The NGO draws on it with
+/// `withdraw_ngo_fund`.
+#[ic_cdk::query]
+pub fn get_ngo_fund_account(ngo_id: String) -> Result<PoolAccount> {
+    let ledger_canister_id = ledger::configured_ledger()?;
+
+    Ok(PoolAccount {
+        ledger_canister_id,
+        owner: ic_cdk::api::id(),
+        subaccount: ledger::ngo_fund_subaccount(&ngo_id),
+    })
+}
+
+/// The caller's NGO fund balance as reported by the ledger.
+#[ic_cdk::update]
+pub async fn get_ngo_fund_balance() -> Result<Nat> {
+    let ngo = verify_ngo()?;
+    let ledger_id = ledger::configured_ledger()?;
+
+    ledger::balance_of(ledger_id, ledger::ngo_fund_account(&ngo.id)).await
+}
+
+/// Pay `amount` out of the caller's NGO fund to the NGO's own account. The
+/// ledger fee comes out of the fund on top. Returns the ledger block index.
+#[ic_cdk::update]
+pub async fn withdraw_ngo_fund(amount: u64) -> Result<u64> {
+    let ngo = verify_ngo()?;
+    let ledger_id = ledger::configured_ledger()?;
+
+    if amount == 0 {
+        return Err("Withdrawal amount must be positive".to_string());
+    }
+    let owner = Principal::from_text(&ngo.user_principal)
+        .map_err(|_| "The NGO account has no valid principal".to_string())?;
+
+    let fee = ledger::fee(ledger_id).await?;
+    let block_index = ledger::transfer(
+        ledger_id,
+        ledger::ngo_fund_subaccount(&ngo.id),
+        ledger::Account {
+            owner,
+            subaccount: None,
+        },
+        amount,
+        fee,
+        ngo.id.as_bytes().to_vec(),
+        utils::get_current_timestamp(),
+    )
+    .await?;
+
+    storage::append_audit_entry(
+        caller().to_string(),
+        "withdraw_ngo_fund",
+        ngo.id,
+        format!(
+            "Withdrew {} from the NGO fund in block {}",
+            amount, block_index
+        ),
+        utils::get_current_timestamp(),
+    );
+    Ok(block_index)
+}
+
+/// The pool subaccount's balance as reported by the ledger.
+#[ic_cdk::update]
+pub async fn get_pool_balance(pool_id: String) -> Result<Nat> {
+    let ledger_id = ledger::configured_ledger()?;
+    let pool = storage::get_contribution_pool(&pool_id).ok_or("Pool not found".to_string())?;
+
+    ledger::balance_of(ledger_id, ledger::pool_account(&pool.id)).await
+}
+
 #[ic_cdk::query]
 pub fn get_pool_contributions(pool_id: String) -> Result<Vec<Contribution>> {
-    Ok(storage::get_contributions_by_pool(&pool_id))
+    let full_view = require_admin_permission(AdminPermission::ViewAnalytics).is_ok();
+
+    Ok(storage::get_contributions_by_pool(&pool_id)
+        .into_iter()
+        .map(|contribution| {
+            if full_view {
+                contribution
+            } else {
+                redact_contribution(contribution)
+            }
+        })
+        .collect())
 }
 
+/// Hides who made an anonymous contribution and what they wrote from
+/// everyone but the contributor. Ledger block indices and transfer
+/// timestamps go too, as they lead to the contributor's ledger account;
+/// the block index of a refund reads 0.
+pub fn redact_contribution(mut contribution: Contribution) -> Contribution {
+    if !is_own_contribution(&contribution, caller()) {
+        redact_contributor(&mut contribution);
+    }
+    contribution
+}
+
+// Whether `viewer` may see who made the contribution. The anonymous principal
+// never may, as anyone can call as it.
+fn is_own_contribution(contribution: &Contribution, viewer: Principal) -> bool {
+    !contribution.is_anonymous
+        || (viewer != Principal::anonymous()
+            && contribution.contributor_principal == viewer.to_string())
+}
+
+fn redact_contributor(contribution: &mut Contribution) {
+    contribution.contributor_principal = String::new();
+    contribution.message = None;
+    contribution.ledger_block_index = None;
+    contribution.refund_created_at = None;
+    if let Some(ContributionOutcome::Refunded {
+        ledger_block_index, ..
+    }) = &mut contribution.outcome
+    {
+        *ledger_block_index = 0;
+    }
+    if let Some(surplus) = &mut contribution.surplus {
+        surplus.ledger_block_index = None;
+        surplus.transfer_created_at = None;
+    }
+}
+
+/// What the caller has given, and to which cases.
 #[ic_cdk::query]
-pub fn get_user_contributions() -> Result<Vec<Contribution>> {
+pub fn get_user_contributions() -> Result<DonorSummary> {
     let caller_principal = caller().to_string();
-    Ok(storage::get_contributions_by_user(&caller_principal))
+    let contributions = storage::get_contributions_by_user(&caller_principal);
+
+    let mut summary = DonorSummary {
+        total_given: 0,
+        total_refunded: 0,
+        cases_supported: Vec::new(),
+        contributions: Vec::new(),
+    };
+
+    for contribution in &contributions {
+        match &contribution.outcome {
+            // The copy in the target pool carries the money from here on
+            Some(ContributionOutcome::Reallocated { .. }) => continue,
+            Some(ContributionOutcome::Refunded { amount, .. }) => {
+                summary.total_refunded = summary.total_refunded.saturating_add(*amount);
+            }
+            _ => {}
+        }
+        summary.total_given = summary.total_given.saturating_add(contribution.amount);
+
+        let Some(pool) = storage::get_contribution_pool(&contribution.pool_id) else {
+            continue;
+        };
+        match summary
+            .cases_supported
+            .iter_mut()
+            .find(|case| case.pool_id == pool.id)
+        {
+            Some(case) => case.amount_given = case.amount_given.saturating_add(contribution.amount),
+            None => summary.cases_supported.push(SupportedCase {
+                case_id: pool.case_id.clone(),
+                case_title: storage::get_patient_case(&pool.case_id)
+                    .map(|case| case.case_title)
+                    .unwrap_or_default(),
+                pool_id: pool.id.clone(),
+                pool_title: pool.pool_title.clone(),
+                amount_given: contribution.amount,
+                is_completed: pool.is_completed,
+            }),
+        }
+    }
+
+    summary.contributions = contributions;
+    Ok(summary)
 }
 
 #[ic_cdk::query]
 pub fn get_my_patient_cases() -> Result<Vec<PatientCase>> {
     let caller_principal = caller().to_string();
-    
-    let user = storage::get_user_by_principal(&caller_principal)
-        .ok_or("User not found".to_string())?;
-    
+
+    let user =
+        storage::get_user_by_principal(&caller_principal).ok_or("User not found".to_string())?;
+
     match user.role {
-        UserRole::Patient => {
-            Ok(storage::get_patient_cases_by_patient(&user.id))
-        }
+        UserRole::Patient => Ok(storage::get_patient_cases_by_patient(&user.id)),
         UserRole::Admin => {
+            require_admin_permission(AdminPermission::ReviewCases)?;
             Ok(storage::get_all_patient_cases())
         }
         _ => Err("Access denied".to_string()),
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(
+        target_amount: u64,
+        current_amount: u64,
+        rules: Option<ContributionRules>,
+    ) -> ContributionPool {
+        ContributionPool {
+            id: "pool_1".to_string(),
+            case_id: "case_1".to_string(),
+            ngo_id: "ngo_1".to_string(),
+            ngo_name: "NGO".to_string(),
+            target_amount,
+            current_amount,
+            contributors_count: 0,
+            pool_title: "Pool".to_string(),
+            pool_description: String::new(),
+            created_at: 0,
+            deadline: None,
+            is_active: true,
+            is_completed: false,
+            expired_at: None,
+            settlement: None,
+            rules,
+            held_surplus: None,
+        }
+    }
+
+    fn rules(
+        min_contribution: Option<u64>,
+        max_per_contributor: Option<u64>,
+    ) -> Option<ContributionRules> {
+        Some(ContributionRules {
+            min_contribution,
+            max_per_contributor,
+            surplus_policy: SurplusPolicy::Return,
+        })
+    }
+
+    fn request(amount: u64, allow_partial: bool) -> ContributeRequest {
+        ContributeRequest {
+            pool_id: "pool_1".to_string(),
+            amount,
+            message: None,
+            is_anonymous: false,
+            allow_partial: Some(allow_partial),
+        }
+    }
+
+    #[test]
+    fn funding_up_to_the_target_leaves_no_surplus() {
+        let mut pool = pool(1_000, 400, None);
+        assert_eq!(apply_pool_funding(&mut pool, 600, 1), 0);
+        assert_eq!(pool.current_amount, 1_000);
+        assert_eq!(pool.contributors_count, 1);
+        assert!(pool.is_completed);
+    }
+
+    #[test]
+    fn funding_past_the_target_returns_the_surplus() {
+        let mut pool = pool(1_000, 900, None);
+        assert_eq!(apply_pool_funding(&mut pool, 250, 1), 150);
+        assert_eq!(pool.current_amount, 1_000);
+        assert!(pool.is_completed);
+    }
+
+    #[test]
+    fn partial_funding_keeps_the_pool_open() {
+        let mut pool = pool(1_000, 0, None);
+        assert_eq!(apply_pool_funding(&mut pool, 300, 1), 0);
+        assert!(!pool.is_completed);
+    }
+
+    #[test]
+    fn contributions_past_the_remaining_target_need_allow_partial() {
+        let pool = pool(1_000, 900, None);
+        assert!(accepted_contribution(&pool, 0, &request(200, false)).is_err());
+        assert_eq!(
+            accepted_contribution(&pool, 0, &request(200, true)),
+            Ok(100)
+        );
+    }
+
+    #[test]
+    fn full_pools_refuse_contributions() {
+        let pool = pool(1_000, 1_000, None);
+        assert!(accepted_contribution(&pool, 0, &request(1, true)).is_err());
+    }
+
+    #[test]
+    fn minimum_applies_except_to_the_last_contribution() {
+        let open = pool(1_000, 0, rules(Some(100), None));
+        assert!(accepted_contribution(&open, 0, &request(50, false)).is_err());
+
+        let nearly_full = pool(1_000, 950, rules(Some(100), None));
+        assert_eq!(
+            accepted_contribution(&nearly_full, 0, &request(50, false)),
+            Ok(50)
+        );
+    }
+
+    #[test]
+    fn maximum_counts_earlier_contributions() {
+        let pool = pool(1_000, 0, rules(None, Some(300)));
+        assert_eq!(
+            accepted_contribution(&pool, 200, &request(100, false)),
+            Ok(100)
+        );
+        assert!(accepted_contribution(&pool, 200, &request(101, false)).is_err());
+    }
+
+    #[test]
+    fn excess_over_the_maximum_is_what_no_longer_fits() {
+        let limited = pool(1_000, 0, rules(None, Some(300)));
+        assert_eq!(contributor_excess(&limited, 0, 300), 0);
+        assert_eq!(contributor_excess(&limited, 250, 100), 50);
+        assert_eq!(contributor_excess(&limited, 400, 100), 100);
+
+        let unlimited = pool(1_000, 0, None);
+        assert_eq!(contributor_excess(&unlimited, 5_000, 100), 0);
+    }
+
+    #[test]
+    fn contributions_landing_in_a_closed_pool_go_back_whole() {
+        let mut settling = pool(1_000, 400, rules(None, None));
+        settling.is_active = false;
+        settling.settlement = Some(PoolSettlement::Refunding {
+            distributable: 390,
+            contributed: 400,
+            fee: 10,
+        });
+        let surplus = take_landed_contribution(&mut settling, 0, 300, 5).unwrap();
+        assert_eq!(surplus.amount, 300);
+        assert_eq!(surplus.policy, SurplusPolicy::Return);
+        assert_eq!(
+            (settling.current_amount, settling.contributors_count),
+            (400, 0)
+        );
+
+        let mut past_deadline = pool(1_000, 400, None);
+        past_deadline.deadline = Some(4);
+        assert_eq!(
+            take_landed_contribution(&mut past_deadline, 0, 300, 5)
+                .unwrap()
+                .amount,
+            300
+        );
+        assert_eq!(past_deadline.current_amount, 400);
+    }
+
+    #[test]
+    fn contributions_landing_in_an_open_pool_are_split_at_the_target_and_limit() {
+        let mut open = pool(800, 400, rules(None, Some(500)));
+        assert!(take_landed_contribution(&mut open, 0, 300, 5).is_none());
+        assert_eq!(open.current_amount, 700);
+
+        // 200 past the contributor's limit, then 100 more past the target
+        let surplus = take_landed_contribution(&mut open, 300, 400, 5).unwrap();
+        assert_eq!(surplus.amount, 300);
+        assert_eq!(open.current_amount, 800);
+        assert!(open.is_completed);
+    }
+
+    #[test]
+    fn surplus_is_pending_until_its_transfer_lands() {
+        let mut surplus = ContributionSurplus {
+            amount: 100,
+            policy: SurplusPolicy::Return,
+            ledger_block_index: None,
+            error: Some("Ledger call failed".to_string()),
+            ledger_fee: Some(10),
+            transfer_created_at: Some(1),
+            failed_at: None,
+        };
+        assert!(surplus.is_pending());
+
+        surplus.ledger_block_index = Some(42);
+        assert!(!surplus.is_pending());
+
+        // Refused by the ledger: waits for an admin instead of the sweep
+        surplus.ledger_block_index = None;
+        surplus.failed_at = Some(2);
+        assert!(!surplus.is_pending());
+        assert!(surplus.is_failed());
+        surplus.failed_at = None;
+
+        // Held surplus never gets a transfer
+        surplus.ledger_block_index = None;
+        surplus.transfer_created_at = None;
+        assert!(!surplus.is_pending());
+    }
+
+    #[test]
+    fn anonymous_contributions_are_only_shown_in_full_to_a_signed_in_contributor() {
+        let contributor = Principal::from_slice(&[1]);
+        let someone_else = Principal::from_slice(&[2]);
+        let mut contribution = Contribution {
+            id: "contrib_1".to_string(),
+            pool_id: "pool_1".to_string(),
+            contributor_principal: contributor.to_string(),
+            amount: 500,
+            message: None,
+            contributed_at: 1,
+            is_anonymous: true,
+            ledger_block_index: Some(7),
+            outcome: None,
+            surplus: None,
+            refund_created_at: None,
+        };
+        assert!(is_own_contribution(&contribution, contributor));
+        assert!(!is_own_contribution(&contribution, someone_else));
+
+        contribution.contributor_principal = Principal::anonymous().to_string();
+        assert!(!is_own_contribution(&contribution, Principal::anonymous()));
+
+        contribution.is_anonymous = false;
+        assert!(is_own_contribution(&contribution, Principal::anonymous()));
+    }
+
+    #[test]
+    fn redaction_removes_everything_leading_to_the_contributor() {
+        let mut contribution = Contribution {
+            id: "contrib_1".to_string(),
+            pool_id: "pool_1".to_string(),
+            contributor_principal: "aaaaa-aa".to_string(),
+            amount: 500,
+            message: Some("Get well soon".to_string()),
+            contributed_at: 1,
+            is_anonymous: true,
+            ledger_block_index: Some(7),
+            outcome: Some(ContributionOutcome::Refunded {
+                amount: 490,
+                ledger_block_index: 9,
+            }),
+            surplus: Some(ContributionSurplus {
+                amount: 100,
+                policy: SurplusPolicy::Return,
+                ledger_block_index: Some(8),
+                error: None,
+                ledger_fee: Some(10),
+                transfer_created_at: Some(2),
+                failed_at: None,
+            }),
+            refund_created_at: Some(3),
+        };
+        redact_contributor(&mut contribution);
+
+        assert!(contribution.contributor_principal.is_empty());
+        assert!(contribution.message.is_none());
+        assert!(contribution.ledger_block_index.is_none());
+        assert!(contribution.refund_created_at.is_none());
+        assert!(matches!(
+            contribution.outcome,
+            Some(ContributionOutcome::Refunded {
+                amount: 490,
+                ledger_block_index: 0
+            })
+        ));
+        let surplus = contribution.surplus.unwrap();
+        assert!(surplus.ledger_block_index.is_none());
+        assert!(surplus.transfer_created_at.is_none());
+        assert_eq!(surplus.amount, 100);
+    }
+}
@@ -0,0 +1,527 @@
+use candid::Principal;
+use ic_cdk::api::caller;
+use crate::shared::types::*;
+use crate::shared::storage as storage;
+use crate::shared::utils as utils;
+use crate::shared::auth::require_admin_permission;
+use crate::shared::ledger;
+use super::cases::verify_ngo;
+
+// What a disbursement holds against its pool's escrow: proposals reserve their
+// amount, transfers in flight or done also the fee the ledger took
+fn held_amount(disbursement: &Disbursement) -> u64 {
+    match disbursement.status {
+        DisbursementStatus::Proposed => disbursement.amount,
+        DisbursementStatus::Transferring
+        | DisbursementStatus::Unconfirmed(_)
+        | DisbursementStatus::NeedsReconciliation(_)
+        | DisbursementStatus::Paid => {
+            disbursement.amount.saturating_add(disbursement.ledger_fee.unwrap_or(0))
+        }
+        _ => 0,
+    }
+}
+
+// Pool funds not yet paid out or reserved, leaving out `exclude_id`. Held
+// surplus is part of the escrow too.
+fn available_amount(pool: &ContributionPool, exclude_id: Option<&str>) -> u64 {
+    let held = storage::get_disbursements_by_pool(&pool.id)
+        .iter()
+        .filter(|disbursement| Some(disbursement.id.as_str()) != exclude_id)
+        .fold(0u64, |total, disbursement| total.saturating_add(held_amount(disbursement)));
+    pool.current_amount
+        .saturating_add(pool.held_surplus.unwrap_or(0))
+        .saturating_sub(held)
+}
+
+fn validate_invoices(invoices: &[Invoice], amount: u64) -> Result<()> {
+    if invoices.is_empty() {
+        return Err("At least one invoice is required".to_string());
+    }
+
+    let mut invoiced: u64 = 0;
+    for invoice in invoices {
+        if invoice.number.trim().is_empty() || invoice.issuer.trim().is_empty() {
+            return Err("Each invoice needs a number and an issuer".to_string());
+        }
+        if invoice.amount == 0 {
+            return Err(format!("Invoice {} has no amount", invoice.number));
+        }
+        invoiced = invoiced.checked_add(invoice.amount)
+            .ok_or("Invoice total is too large".to_string())?;
+    }
+
+    if amount > invoiced {
+        return Err(format!("Payout of {} exceeds the invoiced total of {}", amount, invoiced));
+    }
+    Ok(())
+}
+
+// Helper function to resolve the ledger owner a payout goes to
+fn resolve_payee(case: &PatientCase, request: &ProposeDisbursementRequest) -> Result<Principal> {
+    match request.payee_kind {
+        PayeeKind::Patient => {
+            let patient = storage::get_user(&case.patient_id)
+                .ok_or("The case's patient account no longer exists".to_string())?;
+            Principal::from_text(&patient.user_principal)
+                .map_err(|_| "The case's patient has no valid principal".to_string())
+        }
+        PayeeKind::Hospital | PayeeKind::Pharmacy => {
+            request.payee_owner.ok_or("A payee account is required for hospital and pharmacy payouts".to_string())
+        }
+    }
+}
+
+/// Propose paying part of a funded pool to the patient or one of their
+/// providers. Nothing moves until an admin approves the proposal.
+#[ic_cdk::update]
+pub fn propose_disbursement(request: ProposeDisbursementRequest) -> Result<String> {
+    let ngo = verify_ngo()?;
+
+    let pool = storage::get_contribution_pool(&request.pool_id)
+        .ok_or("Pool not found".to_string())?;
+    if pool.ngo_id != ngo.id {
+        return Err("You can only disburse from your own pools".to_string());
+    }
+    if !pool.is_completed {
+        return Err("Funds can only be disbursed once the pool is fully funded".to_string());
+    }
+
+    let case = storage::get_patient_case(&pool.case_id)
+        .ok_or("Case not found".to_string())?;
+
+    if request.amount == 0 {
+        return Err("Disbursement amount must be positive".to_string());
+    }
+    validate_invoices(&request.invoices, request.amount)?;
+    if let Some(subaccount) = &request.payee_subaccount {
+        if subaccount.len() != 32 {
+            return Err("Subaccounts must be 32 bytes".to_string());
+        }
+    }
+    let payee_owner = resolve_payee(&case, &request)?;
+
+    let available = available_amount(&pool, None);
+    if request.amount > available {
+        return Err(format!("Only {} of the pool is still available to disburse", available));
+    }
+
+    let disbursement_id = utils::generate_id("disb")?;
+    storage::store_disbursement(Disbursement {
+        id: disbursement_id.clone(),
+        case_id: case.id,
+        pool_id: pool.id,
+        ngo_id: ngo.id,
+        payee_kind: request.payee_kind.clone(),
+        payee_owner,
+        // Patients are paid to their default account
+        payee_subaccount: match request.payee_kind {
+            PayeeKind::Patient => None,
+            _ => request.payee_subaccount,
+        },
+        amount: request.amount,
+        invoices: request.invoices,
+        notes: request.notes,
+        status: DisbursementStatus::Proposed,
+        proposed_at: utils::get_current_timestamp(),
+        reviewed_by: None,
+        reviewed_at: None,
+        review_notes: None,
+        ledger_fee: None,
+        transfer_created_at: None,
+        ledger_block_index: None,
+        paid_at: None,
+    });
+
+    Ok(disbursement_id)
+}
+
+// Helper function to load a disbursement that is still awaiting a decision
+fn get_open_disbursement(disbursement_id: &str) -> Result<Disbursement> {
+    let disbursement = storage::get_disbursement(disbursement_id)
+        .ok_or("Disbursement not found".to_string())?;
+
+    match disbursement.status {
+        DisbursementStatus::Proposed | DisbursementStatus::Failed(_) => Ok(disbursement),
+        _ => Err("This disbursement has already been decided".to_string()),
+    }
+}
+
+// Pays the disbursement out of its pool's subaccount, returning the ledger's
+// answer. A retry reuses the fee and timestamp of the first attempt, so the
+// ledger sees the identical transfer and reports it as a duplicate if the
+// first one went through. Fails without an answer if checks stop the
+// transfer before it is sent.
+async fn release(ledger_id: Principal, disbursement: &mut Disbursement) -> Result<std::result::Result<u64, ledger::TransferFailure>> {
+    let fee = match disbursement.ledger_fee {
+        Some(fee) => fee,
+        None => ledger::fee(ledger_id).await?,
+    };
+
+    let pool = storage::get_contribution_pool(&disbursement.pool_id)
+        .ok_or("Pool not found".to_string())?;
+    let needed = disbursement.amount.checked_add(fee)
+        .ok_or("Disbursement amount is too large".to_string())?;
+    let available = available_amount(&pool, Some(&disbursement.id));
+    if needed > available {
+        return Err(format!(
+            "The pool holds {} but the payout needs {} including the ledger fee",
+            available, needed
+        ));
+    }
+
+    let created_at_time = *disbursement.transfer_created_at.get_or_insert_with(utils::get_current_timestamp);
+    disbursement.ledger_fee = Some(fee);
+    storage::store_disbursement(disbursement.clone());
+
+    Ok(ledger::transfer(
+        ledger_id,
+        ledger::pool_subaccount(&pool.id),
+        ledger::Account {
+            owner: disbursement.payee_owner,
+            subaccount: disbursement.payee_subaccount.clone(),
+        },
+        disbursement.amount,
+        fee,
+        disbursement.id.as_bytes().to_vec(),
+        created_at_time,
+    )
+    .await)
+}
+
+// Where the ledger's answer to a transfer leaves its disbursement. Any answer
+// to the identical transfer settles earlier attempts too: a refusal means none
+// of them went through, as the ledger would have reported a duplicate.
+fn status_after_transfer(result: &std::result::Result<u64, ledger::TransferFailure>, retrying: bool) -> DisbursementStatus {
+    match result {
+        Ok(_) => DisbursementStatus::Paid,
+        Err(failure) if failure.is_final() => DisbursementStatus::Failed(String::from(failure.clone())),
+        // A first attempt's timestamp is fresh, so nothing went through under it
+        Err(failure) if failure.needs_lookup() && !retrying => DisbursementStatus::Failed(String::from(failure.clone())),
+        Err(failure) if failure.needs_lookup() => DisbursementStatus::NeedsReconciliation(format!(
+            "{}; look the transfer up on the ledger and reconcile the disbursement",
+            String::from(failure.clone())
+        )),
+        Err(failure) => DisbursementStatus::Unconfirmed(String::from(failure.clone())),
+    }
+}
+
+/// Approve a proposed (or previously failed) disbursement and transfer the
+/// funds. The disbursement is marked paid only once the ledger confirms; if
+/// the outcome is unknown it stays held as unconfirmed until approved again.
+/// If the ledger can no longer tell, it has to be reconciled instead.
+#[ic_cdk::update]
+pub async fn approve_disbursement(disbursement_id: String, review_notes: Option<String>) -> Result<Disbursement> {
+    let admin = require_admin_permission(AdminPermission::ReviewCases)?;
+    let ledger_id = ledger::configured_ledger()?;
+    let mut disbursement = storage::get_disbursement(&disbursement_id)
+        .ok_or("Disbursement not found".to_string())?;
+
+    let previous = disbursement.clone();
+    let retrying = match disbursement.status {
+        DisbursementStatus::Proposed | DisbursementStatus::Failed(_) => false,
+        DisbursementStatus::Unconfirmed(_) => true,
+        DisbursementStatus::NeedsReconciliation(_) => {
+            return Err("The ledger can no longer confirm this transfer; reconcile it instead".to_string());
+        }
+        _ => return Err("This disbursement has already been decided".to_string()),
+    };
+
+    // Marked before the first await so the funds stay held and a second
+    // approval cannot start another transfer meanwhile
+    disbursement.status = DisbursementStatus::Transferring;
+    disbursement.reviewed_by = Some(admin.id);
+    disbursement.reviewed_at = Some(utils::get_current_timestamp());
+    disbursement.review_notes = review_notes;
+    storage::store_disbursement(disbursement.clone());
+
+    let result = match release(ledger_id, &mut disbursement).await {
+        Ok(result) => result,
+        Err(message) => {
+            // Nothing was sent, so the disbursement is left as it was
+            storage::store_disbursement(previous);
+            return Err(message);
+        }
+    };
+
+    disbursement.status = status_after_transfer(&result, retrying);
+    match (&result, &disbursement.status) {
+        (Ok(block_index), _) => {
+            disbursement.ledger_block_index = Some(*block_index);
+            disbursement.paid_at = Some(utils::get_current_timestamp());
+        }
+        // Nothing moved, so approving again may start afresh
+        (Err(_), DisbursementStatus::Failed(_)) => {
+            disbursement.ledger_fee = None;
+            disbursement.transfer_created_at = None;
+        }
+        _ => {}
+    }
+    storage::store_disbursement(disbursement.clone());
+
+    storage::append_audit_entry(
+        caller().to_string(),
+        "approve_disbursement",
+        disbursement.id.clone(),
+        match &disbursement.status {
+            DisbursementStatus::Paid => format!(
+                "Paid {} from pool {} in block {}",
+                disbursement.amount, disbursement.pool_id, disbursement.ledger_block_index.unwrap_or_default()
+            ),
+            DisbursementStatus::Unconfirmed(message) => format!("Transfer unconfirmed: {}", message),
+            DisbursementStatus::NeedsReconciliation(message) => format!("Transfer needs reconciling: {}", message),
+            _ => format!("Transfer failed: {:?}", disbursement.status),
+        },
+        utils::get_current_timestamp(),
+    );
+
+    result.map(|_| disbursement).map_err(String::from)
+}
+
+/// Settle a disbursement whose transfer may or may not have gone through,
+/// after looking it up on the ledger: with the block index it landed in it is
+/// marked paid, without one it fails and can be approved again.
+#[ic_cdk::update]
+pub fn reconcile_disbursement(disbursement_id: String, ledger_block_index: Option<u64>, notes: String) -> Result<Disbursement> {
+    let admin = require_admin_permission(AdminPermission::ReviewCases)?;
+    let mut disbursement = storage::get_disbursement(&disbursement_id)
+        .ok_or("Disbursement not found".to_string())?;
+
+    if !matches!(
+        disbursement.status,
+        DisbursementStatus::Unconfirmed(_) | DisbursementStatus::NeedsReconciliation(_)
+    ) {
+        return Err("Only unconfirmed transfers can be reconciled".to_string());
+    }
+    if notes.trim().is_empty() {
+        return Err("Notes on how the transfer was looked up are required".to_string());
+    }
+
+    match ledger_block_index {
+        Some(block_index) => {
+            disbursement.status = DisbursementStatus::Paid;
+            disbursement.ledger_block_index = Some(block_index);
+            disbursement.paid_at = Some(utils::get_current_timestamp());
+        }
+        None => {
+            disbursement.status = DisbursementStatus::Failed(format!("Not found on the ledger: {}", notes.trim()));
+            disbursement.ledger_fee = None;
+            disbursement.transfer_created_at = None;
+        }
+    }
+    disbursement.reviewed_by = Some(admin.id);
+    disbursement.reviewed_at = Some(utils::get_current_timestamp());
+    disbursement.review_notes = Some(notes.trim().to_string());
+    storage::store_disbursement(disbursement.clone());
+
+    storage::append_audit_entry(
+        caller().to_string(),
+        "reconcile_disbursement",
+        disbursement.id.clone(),
+        match ledger_block_index {
+            Some(block_index) => format!("Found in block {}: {}", block_index, notes.trim()),
+            None => format!("Not found on the ledger: {}", notes.trim()),
+        },
+        utils::get_current_timestamp(),
+    );
+    Ok(disbursement)
+}
+
+#[ic_cdk::update]
+pub fn reject_disbursement(disbursement_id: String, reason: String) -> Result<Disbursement> {
+    let admin = require_admin_permission(AdminPermission::ReviewCases)?;
+    let mut disbursement = get_open_disbursement(&disbursement_id)?;
+
+    if reason.trim().is_empty() {
+        return Err("A reason is required to reject a disbursement".to_string());
+    }
+
+    disbursement.status = DisbursementStatus::Rejected;
+    disbursement.reviewed_by = Some(admin.id);
+    disbursement.reviewed_at = Some(utils::get_current_timestamp());
+    disbursement.review_notes = Some(reason.trim().to_string());
+    storage::store_disbursement(disbursement.clone());
+
+    storage::append_audit_entry(
+        caller().to_string(),
+        "reject_disbursement",
+        disbursement.id.clone(),
+        reason.trim().to_string(),
+        utils::get_current_timestamp(),
+    );
+    Ok(disbursement)
+}
+
+/// Withdraw a proposal that has not been paid out.
+#[ic_cdk::update]
+pub fn cancel_disbursement(disbursement_id: String) -> Result<Disbursement> {
+    let ngo = verify_ngo()?;
+    let mut disbursement = get_open_disbursement(&disbursement_id)?;
+
+    if disbursement.ngo_id != ngo.id {
+        return Err("You can only cancel your own disbursements".to_string());
+    }
+
+    disbursement.status = DisbursementStatus::Cancelled;
+    storage::store_disbursement(disbursement.clone());
+    Ok(disbursement)
+}
+
+/// Every payout proposed against a case. Visible to admins, the NGO running
+/// the case's pool and the patient.
+#[ic_cdk::query]
+pub fn get_case_disbursements(case_id: String) -> Result<Vec<Disbursement>> {
+    let caller_principal = caller().to_string();
+    let user = storage::get_user_by_principal(&caller_principal)
+        .ok_or("User not found".to_string())?;
+
+    let case = storage::get_patient_case(&case_id)
+        .ok_or("Case not found".to_string())?;
+
+    match user.role {
+        UserRole::Admin => {
+            require_admin_permission(AdminPermission::ReviewCases)?;
+        }
+        UserRole::Patient if case.patient_id == user.id => {}
+        UserRole::NGO if storage::get_pool_by_case_id(&case.id).is_some_and(|pool| pool.ngo_id == user.id) => {}
+        _ => return Err("Access denied".to_string()),
+    }
+
+    Ok(storage::get_disbursements_by_case(&case.id))
+}
+
+#[ic_cdk::query]
+pub fn get_pending_disbursements() -> Result<Vec<Disbursement>> {
+    let _admin = require_admin_permission(AdminPermission::ReviewCases)?;
+    // Unconfirmed transfers need another approval or reconciling to settle them
+    Ok(storage::get_disbursements_by_status(|status| {
+        matches!(
+            status,
+            DisbursementStatus::Proposed | DisbursementStatus::Unconfirmed(_) | DisbursementStatus::NeedsReconciliation(_)
+        )
+    }))
+}
+
+/// What is left in a pool to propose payouts from.
+#[ic_cdk::query]
+pub fn get_pool_available_amount(pool_id: String) -> Result<u64> {
+    let pool = storage::get_contribution_pool(&pool_id)
+        .ok_or("Pool not found".to_string())?;
+    Ok(available_amount(&pool, None))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn disbursement(status: DisbursementStatus, ledger_fee: Option<u64>) -> Disbursement {
+        Disbursement {
+            id: "disb_1".to_string(),
+            case_id: "case_1".to_string(),
+            pool_id: "pool_1".to_string(),
+            ngo_id: "ngo_1".to_string(),
+            payee_kind: PayeeKind::Hospital,
+            payee_owner: Principal::anonymous(),
+            payee_subaccount: None,
+            amount: 1_000,
+            invoices: Vec::new(),
+            notes: None,
+            status,
+            proposed_at: 0,
+            reviewed_by: None,
+            reviewed_at: None,
+            review_notes: None,
+            ledger_fee,
+            transfer_created_at: None,
+            ledger_block_index: None,
+            paid_at: None,
+        }
+    }
+
+    fn invoice(number: &str, amount: u64) -> Invoice {
+        Invoice {
+            number: number.to_string(),
+            issuer: "General Hospital".to_string(),
+            amount,
+            document: String::new(),
+        }
+    }
+
+    #[test]
+    fn proposals_hold_their_amount() {
+        assert_eq!(held_amount(&disbursement(DisbursementStatus::Proposed, None)), 1_000);
+    }
+
+    #[test]
+    fn transfers_hold_amount_and_fee_until_known_to_have_failed() {
+        for status in [
+            DisbursementStatus::Transferring,
+            DisbursementStatus::Unconfirmed("Ledger call failed".to_string()),
+            DisbursementStatus::NeedsReconciliation("Too old".to_string()),
+            DisbursementStatus::Paid,
+        ] {
+            assert_eq!(held_amount(&disbursement(status, Some(10))), 1_010);
+        }
+    }
+
+    #[test]
+    fn final_outcomes_release_the_hold() {
+        for status in [
+            DisbursementStatus::Failed("Insufficient funds".to_string()),
+            DisbursementStatus::Rejected,
+            DisbursementStatus::Cancelled,
+        ] {
+            assert_eq!(held_amount(&disbursement(status, Some(10))), 0);
+        }
+    }
+
+    #[test]
+    fn ledger_answers_to_a_first_attempt_settle_it() {
+        assert_eq!(status_after_transfer(&Ok(7), false), DisbursementStatus::Paid);
+        assert!(matches!(
+            status_after_transfer(&Err(ledger::TransferFailure::Rejected("Insufficient funds".to_string())), false),
+            DisbursementStatus::Failed(_)
+        ));
+        assert!(matches!(
+            status_after_transfer(&Err(ledger::TransferFailure::TooOld("Too old".to_string())), false),
+            DisbursementStatus::Failed(_)
+        ));
+        assert!(matches!(
+            status_after_transfer(&Err(ledger::TransferFailure::Unknown("Ledger call failed".to_string())), false),
+            DisbursementStatus::Unconfirmed(_)
+        ));
+    }
+
+    #[test]
+    fn retries_fail_on_a_refusal_and_need_a_lookup_once_too_old() {
+        assert_eq!(status_after_transfer(&Ok(7), true), DisbursementStatus::Paid);
+        assert!(matches!(
+            status_after_transfer(&Err(ledger::TransferFailure::Rejected("Insufficient funds".to_string())), true),
+            DisbursementStatus::Failed(_)
+        ));
+        assert!(matches!(
+            status_after_transfer(&Err(ledger::TransferFailure::TooOld("Too old".to_string())), true),
+            DisbursementStatus::NeedsReconciliation(_)
+        ));
+        assert!(matches!(
+            status_after_transfer(&Err(ledger::TransferFailure::Unknown("Ledger call failed".to_string())), true),
+            DisbursementStatus::Unconfirmed(_)
+        ));
+    }
+
+    #[test]
+    fn payouts_must_be_covered_by_invoices() {
+        let invoices = vec![invoice("INV-1", 600), invoice("INV-2", 400)];
+        assert!(validate_invoices(&invoices, 1_000).is_ok());
+        assert!(validate_invoices(&invoices, 1_001).is_err());
+        assert!(validate_invoices(&[], 1).is_err());
+        assert!(validate_invoices(&[invoice("INV-3", 0)], 0).is_err());
+        assert!(validate_invoices(&[invoice(" ", 100)], 100).is_err());
+    }
+
+    #[test]
+    fn invoice_totals_cannot_overflow() {
+        let invoices = vec![invoice("INV-1", u64::MAX), invoice("INV-2", 1)];
+        assert!(validate_invoices(&invoices, 1).is_err());
+    }
+}
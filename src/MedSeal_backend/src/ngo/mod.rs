@@ -1,3 +1,7 @@
 pub mod cases;
+pub mod disbursements;
+pub mod settlement;
 
-pub use cases::*;
\ No newline at end of file
+pub use cases::*;
+pub use disbursements::*;
+pub use settlement::*;
@@ -0,0 +1,734 @@
+use std::cell::Cell;
+use std::time::Duration;
+use candid::Principal;
+use ic_cdk::api::caller;
+use crate::shared::types::*;
+use crate::shared::storage as storage;
+use crate::shared::utils as utils;
+use crate::shared::auth::require_admin_permission;
+use crate::shared::ledger;
+use super::cases::{
+    verify_ngo, ensure_pool_open, record_pool_funding, hold_surplus, retry_pending_surpluses, retry_pool_surpluses,
+    surplus_owed,
+};
+
+const SETTLEMENT_SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+// How long an NGO has after expiry to ask for its funds to be reallocated
+const REALLOCATION_WINDOW_NANOS: u64 = 7 * 24 * 60 * 60 * 1_000_000_000;
+
+thread_local! {
+    // Refund sweeps await the ledger; a slow one must not overlap the next tick
+    static SWEEP_RUNNING: Cell<bool> = const { Cell::new(false) };
+}
+
+// Clears SWEEP_RUNNING when the sweep ends, including when it traps after an
+// await and its future is dropped during cleanup
+struct SweepGuard;
+
+impl SweepGuard {
+    fn start() -> Option<SweepGuard> {
+        if SWEEP_RUNNING.with(|running| running.replace(true)) {
+            None
+        } else {
+            Some(SweepGuard)
+        }
+    }
+}
+
+impl Drop for SweepGuard {
+    fn drop(&mut self) {
+        SWEEP_RUNNING.with(|running| running.set(false));
+    }
+}
+
+/// Marks every open pool whose deadline passed before it was funded as
+/// expired. Returns how many changed.
+pub fn expire_overdue_pools() -> u64 {
+    let now = utils::get_current_timestamp();
+    let mut expired = 0;
+
+    for mut pool in storage::get_active_contribution_pools() {
+        if pool.deadline.map(|deadline| now > deadline).unwrap_or(false) {
+            pool.is_active = false;
+            pool.expired_at = Some(now);
+            pool.settlement = Some(PoolSettlement::AwaitingDecision {
+                refund_after: now + REALLOCATION_WINDOW_NANOS,
+            });
+            storage::update_contribution_pool(&pool.id.clone(), pool);
+            expired += 1;
+        }
+    }
+
+    expired
+}
+
+// Contributions that still need settling: recorded with real tokens and not
+// yet paid back or moved
+fn unsettled_contributions(pool_id: &str) -> Vec<Contribution> {
+    storage::get_contributions_by_pool(pool_id)
+        .into_iter()
+        .filter(|contribution| contribution.ledger_block_index.is_some())
+        .filter(|contribution| matches!(contribution.outcome, None | Some(ContributionOutcome::RefundFailed(_))))
+        .collect()
+}
+
+// Contributions recorded before contributions moved tokens have nothing to
+// return; note that once so every contribution carries an outcome
+fn settle_unfunded_contributions(pool_id: &str) {
+    for mut contribution in storage::get_contributions_by_pool(pool_id) {
+        if contribution.ledger_block_index.is_none() && contribution.outcome.is_none() {
+            contribution.outcome = Some(ContributionOutcome::NotRefunded(
+                "Recorded before contributions moved tokens".to_string(),
+            ));
+            storage::store_contribution(contribution);
+        }
+    }
+}
+
+// What a contribution counts for when its pool's funds are shared out: its
+// amount plus any surplus of it the pool held on to
+fn settlement_weight(contribution: &Contribution) -> u64 {
+    let held = contribution.surplus
+        .as_ref()
+        .filter(|surplus| surplus.is_held())
+        .map_or(0, |surplus| surplus.amount);
+    contribution.amount.saturating_add(held)
+}
+
+// Each contribution's share of `total`, in proportion to what it put in
+fn pro_rata_share(total: u64, amount: u64, contributed: u64) -> u64 {
+    if contributed == 0 {
+        return 0;
+    }
+    (total as u128 * amount as u128 / contributed as u128) as u64
+}
+
+// Snapshots the pool balance so every refund is worked out against the same
+// figures, however many sweeps the refunds take. Surplus owed elsewhere stays
+// behind for its own transfer; held surplus goes back with the contributions.
+async fn begin_refunds(ledger_id: Principal, pool: &ContributionPool) -> Result<PoolSettlement> {
+    settle_unfunded_contributions(&pool.id);
+    retry_pool_surpluses(ledger_id, pool).await;
+
+    let balance = ledger::nat_to_u64(ledger::balance_of(ledger_id, ledger::pool_account(&pool.id)).await?)?;
+    let fee = ledger::fee(ledger_id).await?;
+
+    // Read after the ledger answered so surplus recorded meanwhile is owed too
+    let owed = surplus_owed(&storage::get_contributions_by_pool(&pool.id));
+    let contributions = unsettled_contributions(&pool.id);
+    let contributed = contributions
+        .iter()
+        .try_fold(0u64, |total, contribution| total.checked_add(settlement_weight(contribution)))
+        .ok_or("Pool contributions overflow".to_string())?;
+    let fees = fee.saturating_mul(contributions.len() as u64);
+
+    Ok(PoolSettlement::Refunding {
+        distributable: balance.saturating_sub(owed).saturating_sub(fees),
+        contributed,
+        fee,
+    })
+}
+
+// Pays back every contribution still owed its share. Transfers whose outcome
+// is unknown are left for the next sweep, ones the ledger refused for an
+// admin; the pool is refunded once none remain.
+async fn refund_contributions(ledger_id: Principal, pool_id: &str, distributable: u64, contributed: u64, fee: u64) -> bool {
+    let mut done = true;
+
+    for mut contribution in unsettled_contributions(pool_id) {
+        let share = pro_rata_share(distributable, settlement_weight(&contribution), contributed);
+        if share == 0 {
+            contribution.outcome = Some(ContributionOutcome::NotRefunded(
+                "The share is too small to cover the ledger fee".to_string(),
+            ));
+            storage::store_contribution(contribution);
+            continue;
+        }
+
+        let owner = match Principal::from_text(&contribution.contributor_principal) {
+            Ok(owner) => owner,
+            Err(_) => {
+                contribution.outcome = Some(ContributionOutcome::NotRefunded("Invalid contributor principal".to_string()));
+                storage::store_contribution(contribution);
+                continue;
+            }
+        };
+
+        // Persisted before the transfer so a retry after an unknown outcome
+        // sends the identical transfer
+        let created_at_time = *contribution.refund_created_at.get_or_insert_with(utils::get_current_timestamp);
+        storage::store_contribution(contribution.clone());
+
+        let result = ledger::transfer(
+            ledger_id,
+            ledger::pool_subaccount(pool_id),
+            ledger::Account { owner, subaccount: None },
+            share,
+            fee,
+            contribution.id.as_bytes().to_vec(),
+            created_at_time,
+        )
+        .await;
+
+        contribution.outcome = Some(match result {
+            Ok(ledger_block_index) => ContributionOutcome::Refunded { amount: share, ledger_block_index },
+            Err(failure) => {
+                done = false;
+                // Retrying the same transfer cannot help: either nothing
+                // moved, or the ledger can no longer tell
+                if failure.is_final() {
+                    contribution.refund_created_at = None;
+                }
+                if failure.is_final() || failure.needs_lookup() {
+                    ContributionOutcome::RefundRejected(failure.into())
+                } else {
+                    ContributionOutcome::RefundFailed(failure.into())
+                }
+            }
+        });
+        storage::store_contribution(contribution);
+    }
+
+    done && !storage::get_contributions_by_pool(pool_id)
+        .iter()
+        .any(|contribution| matches!(contribution.outcome, Some(ContributionOutcome::RefundRejected(_))))
+}
+
+async fn settle_pool(ledger_id: Principal, pool_id: String) -> Result<()> {
+    let mut pool = storage::get_contribution_pool(&pool_id)
+        .ok_or("Pool not found".to_string())?;
+
+    if let Some(PoolSettlement::AwaitingDecision { .. }) = pool.settlement {
+        let settlement = begin_refunds(ledger_id, &pool).await?;
+        pool = storage::get_contribution_pool(&pool_id)
+            .ok_or("Pool not found".to_string())?;
+        // The NGO may have asked for a reallocation while the ledger answered
+        if !matches!(pool.settlement, Some(PoolSettlement::AwaitingDecision { .. })) {
+            return Ok(());
+        }
+        pool.settlement = Some(settlement);
+        // Shared out with the refunds
+        pool.held_surplus = None;
+        storage::update_contribution_pool(&pool_id, pool.clone());
+    }
+
+    if let Some(PoolSettlement::Refunding { distributable, contributed, fee }) = pool.settlement {
+        if refund_contributions(ledger_id, &pool_id, distributable, contributed, fee).await {
+            let mut pool = storage::get_contribution_pool(&pool_id)
+                .ok_or("Pool not found".to_string())?;
+            pool.settlement = Some(PoolSettlement::Refunded);
+            storage::update_contribution_pool(&pool_id, pool);
+        }
+    }
+
+    Ok(())
+}
+
+/// Expires overdue pools, refunds contributors of every pool whose
+/// reallocation window has closed and retries surplus transfers that failed.
+pub async fn settle_expired_pools() {
+    let Some(_guard) = SweepGuard::start() else {
+        return;
+    };
+
+    expire_overdue_pools();
+
+    let now = utils::get_current_timestamp();
+    let due: Vec<String> = storage::get_all_contribution_pools()
+        .into_iter()
+        .filter(|pool| match &pool.settlement {
+            Some(PoolSettlement::AwaitingDecision { refund_after }) => now >= *refund_after,
+            Some(PoolSettlement::Refunding { .. }) => true,
+            _ => false,
+        })
+        .map(|pool| pool.id)
+        .collect();
+
+    if due.is_empty() && storage::get_contributions_with_pending_surplus().is_empty() {
+        return;
+    }
+
+    match ledger::configured_ledger() {
+        Ok(ledger_id) => {
+            for pool_id in due {
+                if let Err(message) = settle_pool(ledger_id, pool_id.clone()).await {
+                    ic_cdk::println!("Settling pool {} failed: {}", pool_id, message);
+                }
+            }
+            retry_pending_surpluses(ledger_id).await;
+        }
+        Err(message) => ic_cdk::println!("Skipping pool settlement: {}", message),
+    }
+}
+
+/// Starts the periodic settlement sweep; timers don't survive upgrades, so
+/// this runs from both init and post_upgrade.
+pub fn schedule_pool_settlement() {
+    ic_cdk_timers::set_timer_interval(SETTLEMENT_SWEEP_INTERVAL, || {
+        ic_cdk::spawn(settle_expired_pools());
+    });
+}
+
+// Helper function to load an expired pool owned by the calling NGO
+fn get_own_expired_pool(pool_id: &str) -> Result<ContributionPool> {
+    let ngo = verify_ngo()?;
+    let pool = storage::get_contribution_pool(pool_id)
+        .ok_or("Pool not found".to_string())?;
+
+    if pool.ngo_id != ngo.id {
+        return Err("You can only settle your own pools".to_string());
+    }
+    if pool.expired_at.is_none() {
+        return Err("Pool has not expired".to_string());
+    }
+    Ok(pool)
+}
+
+/// Ask for an expired pool's funds to go to another approved case's pool
+/// instead of back to contributors. An admin has to approve the move.
+#[ic_cdk::update]
+pub fn request_pool_reallocation(pool_id: String, target_case_id: String) -> Result<ContributionPool> {
+    let mut pool = get_own_expired_pool(&pool_id)?;
+
+    match pool.settlement {
+        Some(PoolSettlement::AwaitingDecision { refund_after }) if utils::get_current_timestamp() < refund_after => {}
+        Some(PoolSettlement::AwaitingDecision { .. }) => {
+            return Err("The reallocation window has closed; contributors are being refunded".to_string());
+        }
+        _ => return Err("This pool's funds have already been settled".to_string()),
+    }
+
+    let target_case = storage::get_patient_case(&target_case_id)
+        .ok_or("Target case not found".to_string())?;
+    if !matches!(target_case.status, CaseStatus::Approved) {
+        return Err("Funds can only be reallocated to an approved case".to_string());
+    }
+    let target_pool = storage::get_pool_by_case_id(&target_case.id)
+        .ok_or("Create a contribution pool for the target case first".to_string())?;
+    if target_pool.id == pool.id {
+        return Err("Choose a different case".to_string());
+    }
+    ensure_pool_open(&target_pool)?;
+
+    pool.settlement = Some(PoolSettlement::ReallocationRequested {
+        target_pool_id: target_pool.id,
+        requested_at: utils::get_current_timestamp(),
+    });
+    storage::update_contribution_pool(&pool_id, pool.clone());
+    Ok(pool)
+}
+
+// Works out the transfer that moves the pool balance to the target pool and
+// records it on the pool before anything is sent, so an approval retried
+// after an unknown outcome sends the identical transfer
+async fn begin_reallocation(ledger_id: Principal, pool: &ContributionPool, target_pool_id: &str) -> Result<PoolSettlement> {
+    retry_pool_surpluses(ledger_id, pool).await;
+    let balance = ledger::nat_to_u64(ledger::balance_of(ledger_id, ledger::pool_account(&pool.id)).await?)?;
+    let fee = ledger::fee(ledger_id).await?;
+
+    // Surplus owed elsewhere stays behind for its own transfer; held surplus
+    // moves with the rest
+    let movable = balance.saturating_sub(surplus_owed(&storage::get_contributions_by_pool(&pool.id)));
+    if movable <= fee {
+        return Err("The pool balance does not cover the ledger fee".to_string());
+    }
+
+    // Another approval, a rejection or a refund may have got in while the
+    // ledger answered
+    let mut pool = storage::get_contribution_pool(&pool.id)
+        .ok_or("Pool not found".to_string())?;
+    let requested_at = match &pool.settlement {
+        Some(PoolSettlement::ReallocationRequested { target_pool_id: requested, requested_at }) if requested == target_pool_id => *requested_at,
+        _ => return Err("The reallocation request changed while it was being approved".to_string()),
+    };
+
+    let settlement = PoolSettlement::Reallocating {
+        target_pool_id: target_pool_id.to_string(),
+        requested_at,
+        amount: movable - fee,
+        fee,
+        created_at_time: utils::get_current_timestamp(),
+    };
+    pool.settlement = Some(settlement.clone());
+    storage::update_contribution_pool(&pool.id.clone(), pool);
+    Ok(settlement)
+}
+
+// Re-records each contribution's share of `amount` in the target pool, so
+// contributors keep a record of where their money went
+fn reallocate_contributions(pool_id: &str, target_pool_id: &str, amount: u64, block_index: u64) {
+    settle_unfunded_contributions(pool_id);
+
+    let contributions = unsettled_contributions(pool_id);
+    let contributed = contributions.iter().fold(0u64, |total, contribution| total.saturating_add(settlement_weight(contribution)));
+    let now = utils::get_current_timestamp();
+
+    let mut allocated = 0u64;
+    let count = contributions.len();
+
+    for (index, mut contribution) in contributions.into_iter().enumerate() {
+        // The last contribution takes the rounding remainder
+        let share = if index + 1 == count {
+            amount - allocated
+        } else {
+            pro_rata_share(amount, settlement_weight(&contribution), contributed)
+        };
+        allocated += share;
+
+        let moved = Contribution {
+            // Derived rather than generated, so nothing after the transfer can fail
+            id: utils::derive_id("contrib", &format!("{}:{}", contribution.id, target_pool_id)),
+            pool_id: target_pool_id.to_string(),
+            contributor_principal: contribution.contributor_principal.clone(),
+            amount: share,
+            message: contribution.message.clone(),
+            contributed_at: now,
+            is_anonymous: contribution.is_anonymous,
+            ledger_block_index: Some(block_index),
+            outcome: None,
+            surplus: None,
+            refund_created_at: None,
+        };
+        contribution.outcome = Some(ContributionOutcome::Reallocated {
+            pool_id: target_pool_id.to_string(),
+            contribution_id: moved.id.clone(),
+        });
+        storage::store_contribution(moved);
+        storage::store_contribution(contribution);
+    }
+
+    // Pools are never removed, so the target is still there
+    if let Some(mut target_pool) = storage::get_contribution_pool(target_pool_id) {
+        // What overshoots the target stays with the target pool's escrow
+        let surplus = record_pool_funding(&mut target_pool, amount, count as u64);
+        if surplus > 0 {
+            target_pool.held_surplus = Some(target_pool.held_surplus.unwrap_or(0).saturating_add(surplus));
+        }
+        storage::update_contribution_pool(target_pool_id, target_pool);
+    }
+}
+
+/// Move an expired pool's funds to the target pool its NGO asked for. If
+/// the ledger's answer is lost the pool stays reallocating, and approving
+/// again resends the identical transfer, which the ledger deduplicates.
+#[ic_cdk::update]
+pub async fn approve_pool_reallocation(pool_id: String) -> Result<ContributionPool> {
+    let _admin = require_admin_permission(AdminPermission::ReviewCases)?;
+    let ledger_id = ledger::configured_ledger()?;
+    let pool = storage::get_contribution_pool(&pool_id)
+        .ok_or("Pool not found".to_string())?;
+
+    let settlement = match pool.settlement.clone() {
+        Some(PoolSettlement::ReallocationRequested { target_pool_id, .. }) => {
+            let target_pool = storage::get_contribution_pool(&target_pool_id)
+                .ok_or("Target pool not found".to_string())?;
+            ensure_pool_open(&target_pool)?;
+            begin_reallocation(ledger_id, &pool, &target_pool_id).await?
+        }
+        Some(settlement @ PoolSettlement::Reallocating { .. }) => settlement,
+        _ => return Err("No reallocation has been requested for this pool".to_string()),
+    };
+    let PoolSettlement::Reallocating { target_pool_id, requested_at, amount, fee, created_at_time } = settlement.clone() else {
+        unreachable!("begin_reallocation records a reallocating settlement");
+    };
+
+    let result = ledger::transfer(
+        ledger_id,
+        ledger::pool_subaccount(&pool_id),
+        ledger::pool_account(&target_pool_id),
+        amount,
+        fee,
+        pool_id.as_bytes().to_vec(),
+        created_at_time,
+    )
+    .await;
+
+    let mut pool = storage::get_contribution_pool(&pool_id)
+        .ok_or("Pool not found".to_string())?;
+    // A concurrent approval sending the same transfer may have recorded it
+    if pool.settlement.as_ref() != Some(&settlement) {
+        return Ok(pool);
+    }
+
+    let block_index = match result {
+        Ok(block_index) => block_index,
+        Err(failure) if failure.is_final() => {
+            // Nothing moved: back to the request so it can be approved again or rejected
+            pool.settlement = Some(PoolSettlement::ReallocationRequested { target_pool_id, requested_at });
+            storage::update_contribution_pool(&pool_id, pool);
+            return Err(failure.into());
+        }
+        Err(failure) => {
+            return Err(format!(
+                "{}; the transfer may have gone through, approve again to resend it unchanged",
+                String::from(failure)
+            ));
+        }
+    };
+
+    reallocate_contributions(&pool_id, &target_pool_id, amount, block_index);
+    pool.settlement = Some(PoolSettlement::Reallocated {
+        target_pool_id: target_pool_id.clone(),
+        ledger_block_index: block_index,
+    });
+    // Moved along with the contributions
+    pool.held_surplus = None;
+    storage::update_contribution_pool(&pool_id, pool.clone());
+
+    storage::append_audit_entry(
+        caller().to_string(),
+        "approve_pool_reallocation",
+        pool_id,
+        format!("Moved {} to pool {} in block {}", amount, target_pool_id, block_index),
+        utils::get_current_timestamp(),
+    );
+    Ok(pool)
+}
+
+/// Turn down a reallocation request; the pool's contributors are refunded
+/// on the next sweep.
+#[ic_cdk::update]
+pub fn reject_pool_reallocation(pool_id: String, reason: String) -> Result<ContributionPool> {
+    let _admin = require_admin_permission(AdminPermission::ReviewCases)?;
+    let mut pool = storage::get_contribution_pool(&pool_id)
+        .ok_or("Pool not found".to_string())?;
+
+    if !matches!(pool.settlement, Some(PoolSettlement::ReallocationRequested { .. })) {
+        return Err("No reallocation has been requested for this pool".to_string());
+    }
+    if reason.trim().is_empty() {
+        return Err("A reason is required to reject a reallocation".to_string());
+    }
+
+    pool.settlement = Some(PoolSettlement::AwaitingDecision {
+        refund_after: utils::get_current_timestamp(),
+    });
+    storage::update_contribution_pool(&pool_id, pool.clone());
+
+    storage::append_audit_entry(
+        caller().to_string(),
+        "reject_pool_reallocation",
+        pool_id,
+        reason.trim().to_string(),
+        utils::get_current_timestamp(),
+    );
+    Ok(pool)
+}
+
+/// Refund an expired pool's contributors now instead of waiting out the
+/// reallocation window.
+#[ic_cdk::update]
+pub fn refund_expired_pool(pool_id: String) -> Result<ContributionPool> {
+    let mut pool = get_own_expired_pool(&pool_id)?;
+
+    match pool.settlement {
+        Some(PoolSettlement::AwaitingDecision { .. }) | Some(PoolSettlement::ReallocationRequested { .. }) => {}
+        _ => return Err("This pool's funds have already been settled".to_string()),
+    }
+
+    pool.settlement = Some(PoolSettlement::AwaitingDecision {
+        refund_after: utils::get_current_timestamp(),
+    });
+    storage::update_contribution_pool(&pool_id, pool.clone());
+    Ok(pool)
+}
+
+/// Run the settlement sweep now rather than waiting for the timer.
+#[ic_cdk::update]
+pub async fn run_pool_settlement() -> Result<String> {
+    let _admin = require_admin_permission(AdminPermission::ManageSystem)?;
+    settle_expired_pools().await;
+    Ok("Pool settlement sweep finished".to_string())
+}
+
+/// Refunds and surplus transfers the ledger refused for good. They are not
+/// retried until an admin retries or writes them off.
+#[ic_cdk::query]
+pub fn get_failed_contribution_transfers() -> Result<Vec<Contribution>> {
+    let _admin = require_admin_permission(AdminPermission::ReviewCases)?;
+    Ok(storage::get_contributions_with_failed_transfer())
+}
+
+// Helper function to load a contribution with a refused refund or surplus transfer
+fn get_failed_contribution(contribution_id: &str) -> Result<Contribution> {
+    let contribution = storage::get_contribution(contribution_id)
+        .ok_or("Contribution not found".to_string())?;
+
+    if !contribution.has_failed_transfer() {
+        return Err("This contribution has no failed transfer".to_string());
+    }
+    Ok(contribution)
+}
+
+/// Hand a refused refund or surplus transfer back to the settlement sweep,
+/// once whatever made the ledger refuse it has been dealt with. A transfer
+/// the ledger could no longer confirm must first be looked up: the retry is
+/// a new transfer.
+#[ic_cdk::update]
+pub fn retry_contribution_transfer(contribution_id: String) -> Result<Contribution> {
+    let _admin = require_admin_permission(AdminPermission::ReviewCases)?;
+    let mut contribution = get_failed_contribution(&contribution_id)?;
+
+    if matches!(contribution.outcome, Some(ContributionOutcome::RefundRejected(_))) {
+        contribution.outcome = None;
+    }
+    if let Some(surplus) = contribution.surplus.as_mut().filter(|surplus| surplus.is_failed()) {
+        surplus.failed_at = None;
+        surplus.transfer_created_at = Some(utils::get_current_timestamp());
+    }
+    storage::store_contribution(contribution.clone());
+
+    storage::append_audit_entry(
+        caller().to_string(),
+        "retry_contribution_transfer",
+        contribution_id,
+        "Queued for the next settlement sweep".to_string(),
+        utils::get_current_timestamp(),
+    );
+    Ok(contribution)
+}
+
+/// Give up on a refused refund or surplus transfer. The tokens stay in the
+/// pool: a refund is recorded as not made, a surplus is held in the pool.
+#[ic_cdk::update]
+pub fn write_off_contribution_transfer(contribution_id: String, reason: String) -> Result<Contribution> {
+    let _admin = require_admin_permission(AdminPermission::ReviewCases)?;
+    let mut contribution = get_failed_contribution(&contribution_id)?;
+
+    if reason.trim().is_empty() {
+        return Err("A reason is required to write off a transfer".to_string());
+    }
+
+    if matches!(contribution.outcome, Some(ContributionOutcome::RefundRejected(_))) {
+        contribution.outcome = Some(ContributionOutcome::NotRefunded(reason.trim().to_string()));
+    }
+    if let Some(surplus) = contribution.surplus.as_mut().filter(|surplus| surplus.is_failed()) {
+        surplus.failed_at = None;
+        surplus.error = Some(reason.trim().to_string());
+        hold_surplus(&contribution.pool_id, surplus.amount);
+    }
+    storage::store_contribution(contribution.clone());
+
+    storage::append_audit_entry(
+        caller().to_string(),
+        "write_off_contribution_transfer",
+        contribution_id,
+        reason.trim().to_string(),
+        utils::get_current_timestamp(),
+    );
+    Ok(contribution)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shares_are_proportional_to_contributions() {
+        assert_eq!(pro_rata_share(900, 300, 900), 300);
+        assert_eq!(pro_rata_share(450, 300, 900), 150);
+        assert_eq!(pro_rata_share(900, 900, 900), 900);
+    }
+
+    #[test]
+    fn shares_round_down_so_they_never_exceed_the_total() {
+        let shares: Vec<u64> = [1, 1, 1].iter().map(|amount| pro_rata_share(100, *amount, 3)).collect();
+        assert_eq!(shares, vec![33, 33, 33]);
+        assert!(shares.iter().sum::<u64>() <= 100);
+    }
+
+    #[test]
+    fn shares_of_large_balances_do_not_overflow() {
+        assert_eq!(pro_rata_share(u64::MAX, u64::MAX / 2, u64::MAX), u64::MAX / 2);
+    }
+
+    #[test]
+    fn nothing_is_shared_without_contributions() {
+        assert_eq!(pro_rata_share(1_000, 0, 0), 0);
+    }
+
+    fn contribution(outcome: Option<ContributionOutcome>) -> Contribution {
+        Contribution {
+            id: "contrib_1".to_string(),
+            pool_id: "pool_1".to_string(),
+            contributor_principal: "aaaaa-aa".to_string(),
+            amount: 100,
+            message: None,
+            contributed_at: 0,
+            is_anonymous: false,
+            ledger_block_index: Some(1),
+            outcome,
+            surplus: None,
+            refund_created_at: None,
+        }
+    }
+
+    #[test]
+    fn only_refused_transfers_wait_for_an_admin() {
+        let rejected = contribution(Some(ContributionOutcome::RefundRejected("Insufficient funds (balance: 0)".to_string())));
+        assert!(rejected.has_failed_transfer());
+
+        let unknown = contribution(Some(ContributionOutcome::RefundFailed("Ledger call failed".to_string())));
+        assert!(!unknown.has_failed_transfer());
+
+        let mut surplus_refused = contribution(None);
+        surplus_refused.surplus = Some(ContributionSurplus {
+            amount: 50,
+            policy: SurplusPolicy::Return,
+            ledger_block_index: None,
+            error: Some("Insufficient funds (balance: 0)".to_string()),
+            ledger_fee: None,
+            transfer_created_at: None,
+            failed_at: Some(1),
+        });
+        assert!(surplus_refused.has_failed_transfer());
+    }
+
+    fn surplus(amount: u64, transfer_created_at: Option<u64>, failed_at: Option<u64>) -> Option<ContributionSurplus> {
+        Some(ContributionSurplus {
+            amount,
+            policy: SurplusPolicy::Return,
+            ledger_block_index: None,
+            error: None,
+            ledger_fee: None,
+            transfer_created_at,
+            failed_at,
+        })
+    }
+
+    #[test]
+    fn held_surplus_counts_towards_a_contributions_share() {
+        let mut held = contribution(None);
+        held.surplus = surplus(30, None, None);
+        assert_eq!(settlement_weight(&held), 130);
+
+        let mut pending = contribution(None);
+        pending.surplus = surplus(30, Some(1), None);
+        assert_eq!(settlement_weight(&pending), 100);
+    }
+
+    #[test]
+    fn pending_and_refused_surplus_is_owed_elsewhere() {
+        let mut pending = contribution(None);
+        pending.surplus = surplus(30, Some(1), None);
+        let mut refused = contribution(None);
+        refused.surplus = surplus(20, None, Some(2));
+        let mut held = contribution(None);
+        held.surplus = surplus(10, None, None);
+        let mut sent = contribution(None);
+        sent.surplus = surplus(5, Some(1), None);
+        sent.surplus.as_mut().unwrap().ledger_block_index = Some(9);
+
+        assert_eq!(surplus_owed(&[pending, refused, held, sent, contribution(None)]), 50);
+    }
+
+    #[test]
+    fn sweeps_do_not_overlap_and_release_the_flag_when_dropped() {
+        let guard = SweepGuard::start();
+        assert!(guard.is_some());
+        assert!(SweepGuard::start().is_none());
+
+        drop(guard);
+        assert!(SweepGuard::start().is_some());
+    }
+}
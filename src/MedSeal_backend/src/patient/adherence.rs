@@ -0,0 +1,412 @@
+use ic_cdk::api::caller;
+use crate::shared::types::*;
+use crate::shared::storage as storage;
+use crate::shared::utils as utils;
+use crate::patient::prescriptions::verify_prescription_party;
+use std::collections::HashMap;
+use std::time::Duration;
+
+const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+const REMINDER_SWEEP_INTERVAL: Duration = Duration::from_secs(5 * 60);
+const MAX_INBOX_SIZE: usize = 100;
+const MAX_NOTE_LENGTH: usize = 500;
+
+fn period_nanos(period: &FrequencyPeriod) -> u64 {
+    match period {
+        FrequencyPeriod::Day => NANOS_PER_DAY,
+        FrequencyPeriod::Week => 7 * NANOS_PER_DAY,
+        FrequencyPeriod::Month => 30 * NANOS_PER_DAY,
+    }
+}
+
+fn due_at(schedule: &DoseSchedule, dose_index: u64) -> u64 {
+    schedule.start_at.saturating_add(dose_index.saturating_mul(schedule.interval))
+}
+
+fn ends_at(schedule: &DoseSchedule) -> u64 {
+    due_at(schedule, schedule.total_doses)
+}
+
+// Number of scheduled doses whose due time has passed by `now`
+fn doses_due_by(schedule: &DoseSchedule, now: u64) -> u64 {
+    if now < schedule.start_at {
+        0
+    } else {
+        ((now - schedule.start_at) / schedule.interval + 1).min(schedule.total_doses)
+    }
+}
+
+fn reminder_id(schedule_id: &str, dose_index: u64) -> String {
+    format!("{}-{}", schedule_id, dose_index)
+}
+
+fn get_own_schedule(schedule_id: &str) -> Result<DoseSchedule> {
+    let schedule = storage::get_dose_schedule(schedule_id)
+        .ok_or("Dose schedule not found".to_string())?;
+
+    if schedule.patient_principal != caller().to_string() {
+        return Err("You can only manage your own dose schedules".to_string());
+    }
+
+    Ok(schedule)
+}
+
+/// Opt into reminders for a prescription linked to the caller. Every item
+/// with a structured dosage gets its own schedule, starting at `start_at`
+/// (default now).
+#[ic_cdk::update]
+pub fn start_dose_schedules(prescription_id: String, start_at: Option<u64>) -> Result<Vec<DoseSchedule>> {
+    let caller_principal = caller().to_string();
+
+    let prescription = storage::get_prescription(&prescription_id)
+        .ok_or("Prescription not found".to_string())?;
+
+    if prescription.patient_principal.as_deref() != Some(caller_principal.as_str()) {
+        return Err("Open the prescription with its code before scheduling doses".to_string());
+    }
+
+    match prescription.status {
+        PrescriptionStatus::Issued | PrescriptionStatus::PartiallyDispensed | PrescriptionStatus::Dispensed => {}
+        _ => return Err("Doses can only be scheduled for an active prescription".to_string()),
+    }
+
+    if storage::get_prescription_dose_schedules(&prescription.id).iter().any(|schedule| schedule.active) {
+        return Err("Dose schedules for this prescription are already running".to_string());
+    }
+
+    let now = utils::get_current_timestamp();
+    let start_at = start_at.unwrap_or(now);
+
+    let mut schedules = Vec::new();
+    for item in &prescription.medicines {
+        let medicine = storage::get_prescribed_medicine(item);
+        let spec = match item.custom_dosage.clone().or_else(|| medicine.as_ref().and_then(|m| m.dosage_spec.clone())) {
+            Some(spec) => spec,
+            None => continue,
+        };
+
+        let interval = period_nanos(&spec.frequency.period) / u64::from(spec.frequency.times.max(1));
+        let duration = u64::from(spec.duration_days) * NANOS_PER_DAY;
+        let mut schedule = DoseSchedule {
+            id: utils::generate_id("schedule")?,
+            prescription_id: prescription.id.clone(),
+            medicine_id: item.medicine_id.clone(),
+            medicine_name: medicine.map(|m| m.name).unwrap_or_else(|| item.medicine_id.clone()),
+            patient_principal: caller_principal.clone(),
+            start_at,
+            interval,
+            total_doses: duration.div_ceil(interval),
+            as_needed: spec.frequency.as_needed,
+            next_reminder_dose: 0,
+            active: true,
+            created_at: now,
+        };
+        // Doses already past when the schedule starts aren't reminded about
+        schedule.next_reminder_dose = doses_due_by(&schedule, now);
+
+        storage::store_dose_schedule(schedule.clone());
+        schedules.push(schedule);
+    }
+
+    if schedules.is_empty() {
+        return Err("None of the prescribed medicines has a structured dosage to schedule".to_string());
+    }
+
+    Ok(schedules)
+}
+
+#[ic_cdk::update]
+pub fn stop_dose_schedule(schedule_id: String) -> Result<DoseSchedule> {
+    let mut schedule = get_own_schedule(&schedule_id)?;
+
+    schedule.active = false;
+    storage::store_dose_schedule(schedule.clone());
+    Ok(schedule)
+}
+
+#[ic_cdk::query]
+pub fn get_my_dose_schedules() -> Vec<DoseSchedule> {
+    storage::get_patient_dose_schedules(&caller().to_string())
+}
+
+/// Record a dose as taken or skipped. Logging the same dose again replaces
+/// the earlier entry.
+#[ic_cdk::update]
+pub fn log_dose(schedule_id: String, dose_index: u64, status: DoseStatus, note: Option<String>) -> Result<DoseLog> {
+    let schedule = get_own_schedule(&schedule_id)?;
+    let now = utils::get_current_timestamp();
+
+    if dose_index >= schedule.total_doses {
+        return Err(format!("This schedule has {} doses", schedule.total_doses));
+    }
+
+    let due_at = if schedule.as_needed {
+        now
+    } else {
+        let due_at = due_at(&schedule, dose_index);
+        // Allow logging up to one interval early, e.g. a morning dose taken at night
+        if due_at > now.saturating_add(schedule.interval) {
+            return Err("This dose is not due yet".to_string());
+        }
+        due_at
+    };
+
+    let note = note.map(|note| note.trim().to_string()).filter(|note| !note.is_empty());
+    if note.as_ref().is_some_and(|note| note.len() > MAX_NOTE_LENGTH) {
+        return Err(format!("Notes are limited to {} characters", MAX_NOTE_LENGTH));
+    }
+
+    let log = DoseLog {
+        schedule_id: schedule.id.clone(),
+        dose_index,
+        due_at,
+        status,
+        note,
+        logged_at: now,
+    };
+    storage::store_dose_log(log.clone());
+    storage::remove_dose_reminder(&schedule.patient_principal, &reminder_id(&schedule.id, dose_index));
+    Ok(log)
+}
+
+#[ic_cdk::query]
+pub fn get_dose_logs(schedule_id: String) -> Result<Vec<DoseLog>> {
+    let schedule = storage::get_dose_schedule(&schedule_id)
+        .ok_or("Dose schedule not found".to_string())?;
+    let prescription = storage::get_prescription(&schedule.prescription_id)
+        .ok_or("Prescription not found".to_string())?;
+
+    verify_prescription_party(&prescription)?;
+    Ok(storage::get_dose_logs(&schedule.id))
+}
+
+/// The caller's reminder inbox, newest first.
+#[ic_cdk::query]
+pub fn get_my_reminders(unread_only: bool) -> Vec<DoseReminder> {
+    let mut reminders: Vec<DoseReminder> = storage::get_dose_reminders(&caller().to_string())
+        .into_iter()
+        .filter(|reminder| !unread_only || !reminder.read)
+        .collect();
+    reminders.sort_by_key(|reminder| std::cmp::Reverse(reminder.due_at));
+    reminders
+}
+
+/// Mark reminders as read. Returns how many were found.
+#[ic_cdk::update]
+pub fn mark_reminders_read(reminder_ids: Vec<String>) -> u64 {
+    let caller_principal = caller().to_string();
+    let mut marked = 0;
+
+    for reminder_id in reminder_ids {
+        if let Some(mut reminder) = storage::get_dose_reminder(&caller_principal, &reminder_id) {
+            reminder.read = true;
+            storage::store_dose_reminder(reminder);
+            marked += 1;
+        }
+    }
+
+    marked
+}
+
+// A prescription restarted after `stop_dose_schedule` has a stopped and a new
+// schedule for the same medicine; only the latest start counts
+fn latest_schedules(schedules: Vec<DoseSchedule>) -> Vec<DoseSchedule> {
+    let mut latest_start: HashMap<String, u64> = HashMap::new();
+    for schedule in &schedules {
+        let entry = latest_start.entry(schedule.medicine_id.clone()).or_insert(schedule.created_at);
+        *entry = (*entry).max(schedule.created_at);
+    }
+    schedules
+        .into_iter()
+        .filter(|schedule| latest_start.get(&schedule.medicine_id) == Some(&schedule.created_at))
+        .collect()
+}
+
+fn schedule_adherence(schedule: &DoseSchedule, now: u64) -> ScheduleAdherence {
+    let doses_due = doses_due_by(schedule, now);
+    let logs: Vec<DoseLog> = storage::get_dose_logs(&schedule.id)
+        .into_iter()
+        .filter(|log| schedule.as_needed || log.dose_index < doses_due)
+        .collect();
+    let taken = logs.iter().filter(|log| log.status == DoseStatus::Taken).count() as u64;
+    let skipped = logs.len() as u64 - taken;
+
+    // As-needed doses have no due times, so only what was logged counts
+    let (doses_due, missed, adherence_percent) = if schedule.as_needed {
+        (taken + skipped, 0, None)
+    } else {
+        let percent = (doses_due > 0).then(|| taken as f64 * 100.0 / doses_due as f64);
+        (doses_due, doses_due.saturating_sub(taken + skipped), percent)
+    };
+
+    ScheduleAdherence {
+        schedule_id: schedule.id.clone(),
+        medicine_id: schedule.medicine_id.clone(),
+        medicine_name: schedule.medicine_name.clone(),
+        doses_due,
+        taken,
+        skipped,
+        missed,
+        adherence_percent,
+    }
+}
+
+/// Adherence per scheduled medicine and overall, for the linked patient or
+/// the prescribing doctor.
+#[ic_cdk::query]
+pub fn get_prescription_adherence(prescription_id: String) -> Result<PrescriptionAdherence> {
+    let prescription = storage::get_prescription(&prescription_id)
+        .ok_or("Prescription not found".to_string())?;
+
+    verify_prescription_party(&prescription)?;
+
+    let now = utils::get_current_timestamp();
+    let schedules: Vec<ScheduleAdherence> = latest_schedules(storage::get_prescription_dose_schedules(&prescription.id))
+        .iter()
+        .map(|schedule| schedule_adherence(schedule, now))
+        .collect();
+
+    let scheduled: Vec<&ScheduleAdherence> = schedules.iter().filter(|s| s.adherence_percent.is_some()).collect();
+    let scheduled_due: u64 = scheduled.iter().map(|s| s.doses_due).sum();
+    let scheduled_taken: u64 = scheduled.iter().map(|s| s.taken).sum();
+
+    Ok(PrescriptionAdherence {
+        prescription_id: prescription.id,
+        doses_due: schedules.iter().map(|s| s.doses_due).sum(),
+        taken: schedules.iter().map(|s| s.taken).sum(),
+        skipped: schedules.iter().map(|s| s.skipped).sum(),
+        missed: schedules.iter().map(|s| s.missed).sum(),
+        adherence_percent: (scheduled_due > 0).then(|| scheduled_taken as f64 * 100.0 / scheduled_due as f64),
+        schedules,
+    })
+}
+
+// Drop the oldest reminders once a patient's inbox is full
+fn trim_inbox(patient_principal: &str) {
+    let mut reminders = storage::get_dose_reminders(patient_principal);
+    if reminders.len() <= MAX_INBOX_SIZE {
+        return;
+    }
+
+    reminders.sort_by_key(|reminder| reminder.due_at);
+    let excess = reminders.len() - MAX_INBOX_SIZE;
+    for reminder in &reminders[..excess] {
+        storage::remove_dose_reminder(patient_principal, &reminder.id);
+    }
+}
+
+/// Puts a reminder in the inbox for the latest due dose of every active
+/// schedule, and closes schedules that have run their course or whose
+/// prescription was cancelled or expired. Returns how many reminders were sent.
+pub fn send_due_reminders() -> u64 {
+    let now = utils::get_current_timestamp();
+    let mut sent = 0;
+
+    for mut schedule in storage::get_active_dose_schedules() {
+        let prescription_ended = storage::get_prescription(&schedule.prescription_id)
+            .map(|prescription| matches!(prescription.status, PrescriptionStatus::Cancelled | PrescriptionStatus::Expired))
+            .unwrap_or(true);
+        if prescription_ended || now >= ends_at(&schedule) {
+            schedule.active = false;
+            storage::store_dose_schedule(schedule);
+            continue;
+        }
+
+        let doses_due = doses_due_by(&schedule, now);
+        if schedule.as_needed || doses_due <= schedule.next_reminder_dose {
+            continue;
+        }
+
+        // Only the latest dose is reminded about; older ones show up as missed
+        let dose_index = doses_due - 1;
+        let already_logged = storage::get_dose_logs(&schedule.id)
+            .iter()
+            .any(|log| log.dose_index == dose_index);
+        if !already_logged {
+            storage::store_dose_reminder(DoseReminder {
+                id: reminder_id(&schedule.id, dose_index),
+                patient_principal: schedule.patient_principal.clone(),
+                schedule_id: schedule.id.clone(),
+                prescription_id: schedule.prescription_id.clone(),
+                medicine_name: schedule.medicine_name.clone(),
+                dose_index,
+                due_at: due_at(&schedule, dose_index),
+                created_at: now,
+                read: false,
+            });
+            trim_inbox(&schedule.patient_principal);
+            sent += 1;
+        }
+
+        schedule.next_reminder_dose = doses_due;
+        storage::store_dose_schedule(schedule);
+    }
+
+    sent
+}
+
+/// Starts the periodic reminder sweep; like the expiry sweep it has to be
+/// re-armed after every upgrade.
+pub fn schedule_dose_reminders() {
+    ic_cdk_timers::set_timer_interval(REMINDER_SWEEP_INTERVAL, || {
+        send_due_reminders();
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: u64 = 60 * 60 * 1_000_000_000;
+
+    fn schedule(medicine_id: &str, created_at: u64) -> DoseSchedule {
+        DoseSchedule {
+            id: format!("schedule_{}_{}", medicine_id, created_at),
+            prescription_id: "prescription_1".to_string(),
+            medicine_id: medicine_id.to_string(),
+            medicine_name: medicine_id.to_string(),
+            patient_principal: "patient".to_string(),
+            start_at: 1_000,
+            interval: 8 * HOUR,
+            total_doses: 6,
+            as_needed: false,
+            next_reminder_dose: 0,
+            active: true,
+            created_at,
+        }
+    }
+
+    #[test]
+    fn no_doses_due_before_start() {
+        let schedule = schedule("med_1", 0);
+        assert_eq!(doses_due_by(&schedule, 0), 0);
+        assert_eq!(doses_due_by(&schedule, 999), 0);
+    }
+
+    #[test]
+    fn first_dose_is_due_at_start() {
+        let schedule = schedule("med_1", 0);
+        assert_eq!(doses_due_by(&schedule, 1_000), 1);
+        assert_eq!(doses_due_by(&schedule, 1_000 + 8 * HOUR - 1), 1);
+        assert_eq!(doses_due_by(&schedule, 1_000 + 8 * HOUR), 2);
+    }
+
+    #[test]
+    fn doses_due_is_capped_at_total() {
+        let schedule = schedule("med_1", 0);
+        assert_eq!(doses_due_by(&schedule, 1_000 + 100 * 8 * HOUR), 6);
+        assert_eq!(ends_at(&schedule), 1_000 + 6 * 8 * HOUR);
+    }
+
+    #[test]
+    fn restarted_schedules_replace_stopped_ones() {
+        let mut stopped = schedule("med_1", 10);
+        stopped.active = false;
+        let restarted = schedule("med_1", 20);
+        let other = schedule("med_2", 10);
+
+        let latest = latest_schedules(vec![stopped, restarted.clone(), other.clone()]);
+        let ids: Vec<&str> = latest.iter().map(|schedule| schedule.id.as_str()).collect();
+        assert_eq!(ids, vec![restarted.id.as_str(), other.id.as_str()]);
+    }
+}
@@ -1 +1,3 @@
-pub mod prescriptions;
\ No newline at end of file
+pub mod prescriptions;
+pub mod profile;
+pub mod adherence;
@@ -1,32 +1,84 @@
+use candid::Principal;
 use ic_cdk::api::caller;
 use crate::shared::types::*;
 use crate::shared::storage as storage;
 use crate::shared::utils as utils;
+use crate::shared::auth::verify_registered_user;
+use crate::doctor::prescriptions::is_past_expiry;
 
-#[ic_cdk::update]
-pub fn get_prescription(prescription_id: String, verification_code: String) -> Result<Prescription> {
+// Helper function to refuse prescriptions a patient can no longer use
+fn ensure_prescription_usable(prescription: &Prescription) -> Result<()> {
+    match prescription.status {
+        PrescriptionStatus::Draft => Err("Prescription not found".to_string()),
+        PrescriptionStatus::Cancelled => Err("This prescription has been cancelled by the doctor".to_string()),
+        PrescriptionStatus::Expired => Err("This prescription has expired".to_string()),
+        _ if is_past_expiry(prescription, utils::get_current_timestamp()) => {
+            Err("This prescription has expired".to_string())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The linked patient or the issuing doctor.
+pub fn verify_prescription_party(prescription: &Prescription) -> Result<()> {
     let caller_principal = caller().to_string();
-    
+
+    if prescription.patient_principal.as_deref() == Some(caller_principal.as_str()) {
+        return Ok(());
+    }
+
+    match storage::get_user_by_principal(&caller_principal) {
+        Some(user) if user.role == UserRole::Doctor && user.id == prescription.doctor_id => Ok(()),
+        _ => Err("Only the patient and the issuing doctor can view this".to_string()),
+    }
+}
+
+/// Until a prescription is claimed anyone holding its code may read it.
+/// Afterwards only the claiming patient, principals they shared it with and
+/// the issuing doctor can.
+fn ensure_claim_access(prescription: &Prescription) -> Result<()> {
+    if prescription.patient_principal.is_none()
+        || verify_prescription_party(prescription).is_ok()
+        || storage::has_prescription_share(&prescription.id, &caller().to_string())
+    {
+        Ok(())
+    } else {
+        Err("This prescription has been claimed by another patient".to_string())
+    }
+}
+
+// Resolves a prescription from its id and code, logging wrong codes against it
+fn find_prescription(prescription_id: &str, verification_code: &str, endpoint: &str) -> Result<Prescription> {
+    utils::validate_prescription_code(verification_code)?;
+
     // Try to find prescription by the combined code format first
     let combined_code = format!("{}-{}", prescription_id, verification_code);
-    
-    let prescription = if let Some(prescription) = storage::get_prescription_by_code(&combined_code) {
-        prescription
-    } else if let Some(prescription) = storage::get_prescription(&prescription_id) {
+
+    if let Some(prescription) = storage::get_prescription_by_code(&combined_code) {
+        Ok(prescription)
+    } else if let Some(prescription) = storage::get_prescription(prescription_id) {
         // Verify the verification code matches
         if prescription.prescription_code != verification_code {
+            storage::record_prescription_access(&prescription.id, endpoint, AccessOutcome::InvalidCode);
             return Err("Invalid prescription code".to_string());
         }
-        prescription
+        Ok(prescription)
     } else {
-        return Err("Prescription not found".to_string());
-    };
+        Err("Prescription not found".to_string())
+    }
+}
 
-    // Update access time and patient principal
-    let current_time = utils::get_current_timestamp();
-    
-    // Update storage record
-    if !storage::update_prescription_access(&prescription.id, current_time, Some(caller_principal)) {
+#[ic_cdk::update]
+pub fn get_prescription(prescription_id: String, verification_code: String) -> Result<Prescription> {
+    verify_registered_user()?;
+    let prescription = find_prescription(&prescription_id, &verification_code, "get_prescription")?;
+
+    let result = ensure_prescription_usable(&prescription).and_then(|_| ensure_claim_access(&prescription));
+    storage::record_prescription_access(&prescription.id, "get_prescription", AccessOutcome::of(&result));
+    result?;
+
+    // Reading never links the caller; that takes an explicit claim
+    if !storage::update_prescription_access(&prescription.id, utils::get_current_timestamp()) {
         return Err("Failed to update prescription access".to_string());
     }
 
@@ -38,24 +90,148 @@ pub fn get_prescription(prescription_id: String, verification_code: String) -> R
     }
 }
 
+/// Bind a prescription to the calling patient. Only the first claim
+/// succeeds; a doctor can re-issue the code if it went to the wrong person.
+#[ic_cdk::update]
+pub fn claim_prescription(prescription_id: String, verification_code: String) -> Result<Prescription> {
+    let caller_principal = caller().to_string();
+    let user = verify_registered_user().map_err(|_| "Register as a patient to claim prescriptions".to_string())?;
+    let mut prescription = find_prescription(&prescription_id, &verification_code, "claim_prescription")?;
+
+    let result = match user.role {
+        UserRole::Patient => ensure_prescription_usable(&prescription),
+        _ => Err("Only patients can claim prescriptions".to_string()),
+    }
+    .and_then(|_| match prescription.patient_principal.as_deref() {
+        None => Ok(()),
+        Some(owner) if owner == caller_principal => Ok(()),
+        Some(_) => Err("This prescription has already been claimed".to_string()),
+    });
+    storage::record_prescription_access(&prescription.id, "claim_prescription", AccessOutcome::of(&result));
+    result?;
+
+    if prescription.patient_principal.is_none() {
+        let now = utils::get_current_timestamp();
+        prescription.patient_principal = Some(caller_principal);
+        prescription.claimed_at = Some(now);
+        prescription.accessed_at = Some(now);
+        storage::update_prescription(prescription.clone());
+    }
+
+    Ok(prescription)
+}
+
+// Helper function to load a prescription the caller has claimed
+fn get_claimed_prescription(prescription_id: &str) -> Result<Prescription> {
+    let prescription = storage::get_prescription(prescription_id)
+        .ok_or("Prescription not found".to_string())?;
+
+    if prescription.patient_principal.as_deref() != Some(caller().to_string().as_str()) {
+        return Err("Only the patient who claimed this prescription can manage who sees it".to_string());
+    }
+
+    Ok(prescription)
+}
+
+/// Let another principal, such as a caregiver, read a claimed prescription.
+#[ic_cdk::update]
+pub fn share_prescription_access(prescription_id: String, principal: String) -> Result<Vec<PrescriptionShare>> {
+    let prescription = get_claimed_prescription(&prescription_id)?;
+
+    let principal = Principal::from_text(principal.trim())
+        .map_err(|_| "Invalid principal".to_string())?
+        .to_string();
+    if prescription.patient_principal.as_deref() == Some(principal.as_str()) {
+        return Err("You already have access to this prescription".to_string());
+    }
+
+    storage::store_prescription_share(PrescriptionShare {
+        prescription_id: prescription.id.clone(),
+        principal,
+        granted_at: utils::get_current_timestamp(),
+    });
+    Ok(storage::get_prescription_shares(&prescription.id))
+}
+
+#[ic_cdk::update]
+pub fn revoke_prescription_access(prescription_id: String, principal: String) -> Result<Vec<PrescriptionShare>> {
+    let prescription = get_claimed_prescription(&prescription_id)?;
+
+    if !storage::remove_prescription_share(&prescription.id, principal.trim()) {
+        return Err("This principal has no access to the prescription".to_string());
+    }
+    Ok(storage::get_prescription_shares(&prescription.id))
+}
+
 #[ic_cdk::query]
+pub fn get_prescription_shares(prescription_id: String) -> Result<Vec<PrescriptionShare>> {
+    let prescription = get_claimed_prescription(&prescription_id)?;
+    Ok(storage::get_prescription_shares(&prescription.id))
+}
+
+// An update rather than a query so the lookup is recorded in the access log
+#[ic_cdk::update]
 pub fn get_prescription_by_code(prescription_code: String) -> Option<Prescription> {
-    storage::get_prescription_by_code(&prescription_code)
+    verify_registered_user().ok()?;
+    let prescription = storage::get_prescription_by_code(&prescription_code)?;
+    let result = ensure_prescription_usable(&prescription).and_then(|_| ensure_claim_access(&prescription));
+    storage::record_prescription_access(&prescription.id, "get_prescription_by_code", AccessOutcome::of(&result));
+    result.ok().map(|_| prescription)
+}
+
+/// The medicines exactly as they were when the prescription was issued.
+/// Items issued before revisions were pinned are left out.
+#[ic_cdk::update]
+pub fn get_prescription_medicine_revisions(prescription_code: String) -> Result<Vec<MedicineRevision>> {
+    verify_registered_user()?;
+    utils::validate_prescription_code(&prescription_code)?;
+
+    let prescription = storage::get_prescription_by_code(&prescription_code)
+        .ok_or("Prescription not found".to_string())?;
+    let result = ensure_prescription_usable(&prescription).and_then(|_| ensure_claim_access(&prescription));
+    storage::record_prescription_access(&prescription.id, "get_prescription_medicine_revisions", AccessOutcome::of(&result));
+    result?;
+
+    Ok(prescription.medicines
+        .iter()
+        .filter_map(|item| {
+            item.medicine_revision
+                .and_then(|revision| storage::get_medicine_revision(&item.medicine_id, revision))
+        })
+        .collect())
+}
+
+/// Opens a prescription by code and the patient's contact for a logged read:
+/// refused once cancelled or expired, or claimed by someone else.
+pub(crate) fn open_prescription_with_contact(prescription_code: &str, patient_contact: &str, endpoint: &str) -> Result<Prescription> {
+    verify_registered_user()?;
+    utils::validate_prescription_code(prescription_code)?;
+
+    let prescription = storage::get_prescription_by_code(prescription_code)
+        .ok_or("Prescription not found".to_string())?;
+    let result = if prescription.patient_contact != patient_contact {
+        Err("Invalid prescription code or patient contact".to_string())
+    } else {
+        ensure_prescription_usable(&prescription).and_then(|_| ensure_claim_access(&prescription))
+    };
+    storage::record_prescription_access(&prescription.id, endpoint, AccessOutcome::of(&result));
+    result.map(|_| prescription)
 }
 
 // Keep the old function for compatibility during transition
-#[ic_cdk::query]
+#[ic_cdk::update]
 pub fn get_prescription_legacy(prescription_code: String, patient_contact: String) -> Result<Prescription> {
-    match storage::get_prescription_by_code(&prescription_code) {
-        Some(prescription) => {
-            // Verify patient contact matches
-            if prescription.patient_contact != patient_contact {
-                return Err("Invalid prescription code or patient contact".to_string());
-            }
-            Ok(prescription)
-        },
-        None => Err("Prescription not found".to_string()),
-    }
+    open_prescription_with_contact(&prescription_code, &patient_contact, "get_prescription_legacy")
+}
+
+/// Every recorded attempt to open or act on a prescription, oldest first.
+#[ic_cdk::query]
+pub fn get_prescription_access_log(prescription_id: String) -> Result<Vec<PrescriptionAccessEntry>> {
+    let prescription = storage::get_prescription(&prescription_id)
+        .ok_or("Prescription not found".to_string())?;
+
+    verify_prescription_party(&prescription)?;
+    Ok(storage::get_prescription_access_log(&prescription.id))
 }
 
 // NOTE: Avoid naming collisions with doctor::prescriptions::get_doctor_prescriptions
@@ -70,9 +246,24 @@ pub fn get_prescription_legacy(prescription_code: String, patient_contact: Strin
 // Remove this duplicate import line:
 // use crate::shared::types::Prescription;
 
-// Return prescriptions for a patient (by principal, contact or name)
+// Return prescriptions for a patient (by principal, contact or name).
+// Drafts were never issued and cancelled or expired ones can't be used, so
+// only usable prescriptions are listed. Codes of unclaimed prescriptions are
+// left out: with the id they are all it takes to claim one, and a name or
+// contact is easy to guess.
 #[ic_cdk::query]
-pub fn get_patient_prescriptions(patient_id: String) -> Vec<Prescription> {
+pub fn get_patient_prescriptions(patient_id: String) -> Result<Vec<Prescription>> {
+	verify_registered_user()?;
 	// patient_id can be a principal, contact, or name (best-effort match)
-	storage::get_patient_prescriptions(&patient_id)
-}
\ No newline at end of file
+	Ok(storage::get_patient_prescriptions(&patient_id)
+		.into_iter()
+		.filter(|prescription| ensure_prescription_usable(prescription).is_ok())
+		.filter(|prescription| ensure_claim_access(prescription).is_ok())
+		.map(|mut prescription| {
+			if prescription.patient_principal.is_none() {
+				prescription.prescription_code = String::new();
+			}
+			prescription
+		})
+		.collect())
+}
@@ -0,0 +1,147 @@
+use ic_cdk::api::caller;
+use crate::shared::types::*;
+use crate::shared::storage as storage;
+use crate::shared::utils as utils;
+use crate::doctor::medicines::clean_name_list;
+
+const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+const MAX_PROFILE_ENTRIES: usize = 50;
+
+fn verify_patient() -> Result<User> {
+    let user = storage::get_user_by_principal(&caller().to_string())
+        .ok_or("User not found".to_string())?;
+
+    match user.role {
+        UserRole::Patient => Ok(user),
+        _ => Err("Only patients have a health profile".to_string()),
+    }
+}
+
+fn empty_profile() -> HealthProfile {
+    HealthProfile {
+        allergies: Vec::new(),
+        chronic_conditions: Vec::new(),
+        pregnancy_status: PregnancyStatus::Unknown,
+        external_medications: Vec::new(),
+        updated_at: 0,
+    }
+}
+
+/// Whether `doctor_id` currently holds the patient's consent to read their profile.
+pub fn has_profile_access(patient_id: &str, doctor_id: &str) -> bool {
+    match storage::get_profile_consent(patient_id, doctor_id) {
+        Some(ProfileConsent { expires_at: Some(expires_at), .. }) => utils::get_current_timestamp() < expires_at,
+        Some(_) => true,
+        None => false,
+    }
+}
+
+/// The patient's profile as seen by a doctor, refused without live consent.
+pub fn shared_health_profile(patient_id: &str, doctor_id: &str) -> Result<HealthProfile> {
+    let patient = storage::get_user(patient_id)
+        .filter(|user| user.role == UserRole::Patient)
+        .ok_or("Patient not found".to_string())?;
+
+    if !has_profile_access(&patient.id, doctor_id) {
+        return Err("The patient has not shared their health profile with you".to_string());
+    }
+
+    Ok(patient.health_profile.unwrap_or_else(empty_profile))
+}
+
+#[ic_cdk::query]
+pub fn get_my_health_profile() -> Result<HealthProfile> {
+    let patient = verify_patient()?;
+    Ok(patient.health_profile.unwrap_or_else(empty_profile))
+}
+
+/// Replace the caller's health profile.
+#[ic_cdk::update]
+pub fn update_health_profile(input: HealthProfileInput) -> Result<HealthProfile> {
+    let mut patient = verify_patient()?;
+
+    let allergies: Vec<Allergy> = input.allergies
+        .into_iter()
+        .filter(|allergy| !allergy.substance.trim().is_empty())
+        .map(|allergy| Allergy {
+            substance: allergy.substance.trim().to_string(),
+            reaction: allergy.reaction.trim().to_string(),
+        })
+        .collect();
+
+    let external_medications: Vec<ExternalMedication> = input.external_medications
+        .into_iter()
+        .filter(|medication| !medication.name.trim().is_empty())
+        .map(|medication| ExternalMedication {
+            name: medication.name.trim().to_string(),
+            active_ingredients: clean_name_list(medication.active_ingredients),
+            notes: medication.notes.trim().to_string(),
+        })
+        .collect();
+
+    let chronic_conditions = clean_name_list(input.chronic_conditions);
+
+    if allergies.len() > MAX_PROFILE_ENTRIES
+        || chronic_conditions.len() > MAX_PROFILE_ENTRIES
+        || external_medications.len() > MAX_PROFILE_ENTRIES
+    {
+        return Err(format!("Each profile list is limited to {} entries", MAX_PROFILE_ENTRIES));
+    }
+
+    let profile = HealthProfile {
+        allergies,
+        chronic_conditions,
+        pregnancy_status: input.pregnancy_status,
+        external_medications,
+        updated_at: utils::get_current_timestamp(),
+    };
+
+    patient.health_profile = Some(profile.clone());
+    storage::store_user(patient);
+    Ok(profile)
+}
+
+/// Let a doctor read the caller's health profile, optionally for a limited
+/// number of days. Granting again replaces the previous window.
+#[ic_cdk::update]
+pub fn grant_profile_access(doctor_id: String, days: Option<u64>) -> Result<ProfileConsent> {
+    let patient = verify_patient()?;
+
+    match storage::get_user(&doctor_id) {
+        Some(doctor) if doctor.role == UserRole::Doctor => {}
+        _ => return Err("Doctor not found".to_string()),
+    }
+
+    let now = utils::get_current_timestamp();
+    let expires_at = match days {
+        Some(0) => return Err("Access must last at least one day".to_string()),
+        Some(days) => Some(now.saturating_add(days.saturating_mul(NANOS_PER_DAY))),
+        None => None,
+    };
+
+    let consent = ProfileConsent {
+        patient_id: patient.id,
+        doctor_id,
+        granted_at: now,
+        expires_at,
+    };
+    storage::store_profile_consent(consent.clone());
+    Ok(consent)
+}
+
+#[ic_cdk::update]
+pub fn revoke_profile_access(doctor_id: String) -> Result<String> {
+    let patient = verify_patient()?;
+
+    if storage::remove_profile_consent(&patient.id, &doctor_id) {
+        Ok("Access revoked".to_string())
+    } else {
+        Err("This doctor has no access to your profile".to_string())
+    }
+}
+
+#[ic_cdk::query]
+pub fn get_my_profile_consents() -> Result<Vec<ProfileConsent>> {
+    let patient = verify_patient()?;
+    Ok(storage::get_patient_profile_consents(&patient.id))
+}
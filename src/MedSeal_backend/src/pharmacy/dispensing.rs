@@ -0,0 +1,254 @@
+use ic_cdk::api::caller;
+use std::collections::HashMap;
+use crate::shared::types::*;
+use crate::shared::storage as storage;
+use crate::shared::utils as utils;
+use crate::shared::auth::verify_registered_user;
+use crate::doctor::prescriptions::{is_past_expiry, transition_prescription};
+
+// Helper function to verify an approved pharmacy
+fn verify_pharmacy() -> Result<User> {
+    let caller_principal = caller().to_string();
+    
+    match storage::get_user_by_principal(&caller_principal) {
+        Some(user) => {
+            match user.role {
+                UserRole::Pharmacy => {
+                    if user.verification_status != VerificationStatus::Approved {
+                        return Err("Pharmacy must be verified to dispense prescriptions".to_string());
+                    }
+                    Ok(user)
+                }
+                _ => Err("Only pharmacies can dispense prescriptions".to_string()),
+            }
+        },
+        None => Err("User not found".to_string()),
+    }
+}
+
+// Total quantity already handed over per medicine
+fn dispensed_quantities(records: &[DispensingRecord]) -> Result<HashMap<String, u32>> {
+    let mut totals = HashMap::new();
+    for item in records.iter().flat_map(|record| record.items.iter()) {
+        let total = totals.entry(item.medicine_id.clone()).or_insert(0u32);
+        *total = total.checked_add(item.quantity)
+            .ok_or(format!("Dispensed quantity of medicine {} overflows", item.medicine_id))?;
+    }
+    Ok(totals)
+}
+
+// Checks `quantity` more of an item can be handed over after `already`,
+// returning the new total
+fn add_dispensed_quantity(medicine: &PrescriptionMedicine, already: u32, quantity: u32) -> Result<u32> {
+    if quantity == 0 {
+        return Err(format!("Quantity for medicine {} must be positive", medicine.medicine_id));
+    }
+
+    if is_item_complete(medicine, already) {
+        return Err(format!("Medicine {} has already been fully dispensed", medicine.medicine_id));
+    }
+
+    let total = already.checked_add(quantity)
+        .ok_or(format!("Quantity for medicine {} is too large", medicine.medicine_id))?;
+    if let Some(limit) = medicine.quantity {
+        if total > limit {
+            return Err(format!(
+                "Cannot dispense {} of medicine {}: only {} remaining",
+                quantity, medicine.medicine_id, limit - already
+            ));
+        }
+    }
+
+    Ok(total)
+}
+
+fn is_item_complete(medicine: &PrescriptionMedicine, dispensed: u32) -> bool {
+    match medicine.quantity {
+        Some(prescribed) => dispensed >= prescribed,
+        None => dispensed > 0,
+    }
+}
+
+#[ic_cdk::update]
+pub fn dispense_prescription(request: DispensePrescriptionRequest) -> Result<DispensingRecord> {
+    verify_registered_user()?;
+    utils::validate_prescription_code(&request.prescription_code)?;
+
+    let mut prescription = storage::get_prescription_by_code(&request.prescription_code)
+        .ok_or("Prescription not found".to_string())?;
+
+    let result = verify_pharmacy().and_then(|pharmacy| dispense_current_fill(pharmacy, &mut prescription, request));
+    storage::record_prescription_access(&prescription.id, "dispense_prescription", AccessOutcome::of(&result));
+    result
+}
+
+fn dispense_current_fill(pharmacy: User, prescription: &mut Prescription, request: DispensePrescriptionRequest) -> Result<DispensingRecord> {
+    match prescription.status {
+        PrescriptionStatus::Issued | PrescriptionStatus::PartiallyDispensed => {}
+        PrescriptionStatus::Dispensed => return Err("Prescription has already been fully dispensed".to_string()),
+        PrescriptionStatus::Cancelled => return Err("Prescription has been cancelled".to_string()),
+        PrescriptionStatus::Expired => return Err("Prescription has expired".to_string()),
+        PrescriptionStatus::Draft => return Err("Prescription not found".to_string()),
+    }
+
+    let now = utils::get_current_timestamp();
+    if is_past_expiry(prescription, now) {
+        return Err("Prescription has expired".to_string());
+    }
+
+    // Only the current fill counts; earlier fills of a repeat prescription are done
+    let current_fill = prescription.refills.as_ref().map(|r| r.current_fill).unwrap_or(0);
+    let previous_records: Vec<DispensingRecord> = storage::get_dispensing_records_by_prescription(&prescription.id)
+        .into_iter()
+        .filter(|record| record.fill_number.unwrap_or(0) == current_fill)
+        .collect();
+
+    // A partially dispensed prescription can only be completed where it was started
+    if let Some(first) = previous_records.first() {
+        if first.pharmacy_id != pharmacy.id {
+            return Err("Prescription is being dispensed by another pharmacy".to_string());
+        }
+    }
+
+    if request.items.is_empty() {
+        return Err("At least one item must be dispensed".to_string());
+    }
+
+    let mut dispensed = dispensed_quantities(&previous_records)?;
+    let mut seen = Vec::new();
+
+    for item in &request.items {
+        if seen.contains(&item.medicine_id) {
+            return Err(format!("Medicine {} is listed more than once", item.medicine_id));
+        }
+        seen.push(item.medicine_id.clone());
+
+        let prescribed = prescription.medicines.iter()
+            .find(|medicine| medicine.medicine_id == item.medicine_id)
+            .ok_or(format!("Medicine {} is not on this prescription", item.medicine_id))?;
+
+        let already = dispensed.get(&item.medicine_id).copied().unwrap_or(0);
+        let total = add_dispensed_quantity(prescribed, already, item.quantity)?;
+        dispensed.insert(item.medicine_id.clone(), total);
+    }
+
+    let fully_dispensed = prescription.medicines.iter()
+        .all(|medicine| is_item_complete(medicine, dispensed.get(&medicine.medicine_id).copied().unwrap_or(0)));
+
+    let next_status = if fully_dispensed {
+        PrescriptionStatus::Dispensed
+    } else {
+        PrescriptionStatus::PartiallyDispensed
+    };
+    transition_prescription(prescription, next_status)?;
+
+    let record = DispensingRecord {
+        id: utils::generate_id("dispense")?,
+        prescription_id: prescription.id.clone(),
+        prescription_code: prescription.prescription_code.clone(),
+        pharmacy_id: pharmacy.id,
+        pharmacy_name: pharmacy.name,
+        fill_number: Some(current_fill),
+        items: request.items,
+        notes: request.notes,
+        dispensed_at: now,
+    };
+
+    storage::store_dispensing_record(record.clone());
+    storage::update_prescription(prescription.clone());
+    Ok(record)
+}
+
+#[ic_cdk::query]
+pub fn get_prescription_dispensing_records(prescription_id: String) -> Result<Vec<DispensingRecord>> {
+    let caller_principal = caller().to_string();
+    let user = storage::get_user_by_principal(&caller_principal)
+        .ok_or("User not found".to_string())?;
+
+    let prescription = storage::get_prescription(&prescription_id)
+        .ok_or("Prescription not found".to_string())?;
+
+    let records = storage::get_dispensing_records_by_prescription(&prescription_id);
+
+    let allowed = match user.role {
+        UserRole::Doctor => prescription.doctor_id == user.id,
+        UserRole::Patient => prescription.patient_principal.as_deref() == Some(caller_principal.as_str()),
+        UserRole::Pharmacy => records.iter().any(|record| record.pharmacy_id == user.id),
+        _ => false,
+    };
+
+    if !allowed {
+        return Err("Access denied".to_string());
+    }
+
+    Ok(records)
+}
+
+#[ic_cdk::query]
+pub fn get_my_dispensing_records() -> Result<Vec<DispensingRecord>> {
+    let pharmacy = verify_pharmacy()?;
+    Ok(storage::get_dispensing_records_by_pharmacy(&pharmacy.id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn medicine(quantity: Option<u32>) -> PrescriptionMedicine {
+        PrescriptionMedicine {
+            medicine_id: "med_1".to_string(),
+            custom_dosage: None,
+            custom_instructions: String::new(),
+            quantity,
+            medicine_revision: None,
+        }
+    }
+
+    fn record(quantities: &[u32]) -> DispensingRecord {
+        DispensingRecord {
+            id: "dispense_1".to_string(),
+            prescription_id: "prescription_1".to_string(),
+            prescription_code: "RX123456".to_string(),
+            pharmacy_id: "user_1".to_string(),
+            pharmacy_name: "Pharmacy".to_string(),
+            fill_number: None,
+            items: quantities
+                .iter()
+                .map(|quantity| DispensedItem { medicine_id: "med_1".to_string(), quantity: *quantity })
+                .collect(),
+            notes: None,
+            dispensed_at: 0,
+        }
+    }
+
+    #[test]
+    fn dispenses_up_to_the_prescribed_quantity() {
+        let item = medicine(Some(10));
+        assert_eq!(add_dispensed_quantity(&item, 0, 4), Ok(4));
+        assert_eq!(add_dispensed_quantity(&item, 4, 6), Ok(10));
+        assert!(add_dispensed_quantity(&item, 4, 7).is_err());
+        assert!(add_dispensed_quantity(&item, 10, 1).is_err());
+    }
+
+    #[test]
+    fn rejects_zero_and_overflowing_quantities() {
+        assert!(add_dispensed_quantity(&medicine(Some(10)), 0, 0).is_err());
+        assert!(add_dispensed_quantity(&medicine(Some(10)), 5, u32::MAX).is_err());
+        assert!(add_dispensed_quantity(&medicine(None), 0, u32::MAX).is_ok());
+    }
+
+    #[test]
+    fn items_without_quantity_complete_on_first_dispense() {
+        let item = medicine(None);
+        assert!(!is_item_complete(&item, 0));
+        assert!(is_item_complete(&item, 1));
+        assert!(add_dispensed_quantity(&item, 1, 1).is_err());
+    }
+
+    #[test]
+    fn sums_dispensed_quantities_with_overflow_check() {
+        let totals = dispensed_quantities(&[record(&[3, 4]), record(&[2])]).unwrap();
+        assert_eq!(totals.get("med_1"), Some(&9));
+        assert!(dispensed_quantities(&[record(&[u32::MAX]), record(&[1])]).is_err());
+    }
+}
@@ -0,0 +1,5 @@
+pub mod dispensing;
+pub mod refills;
+
+pub use dispensing::*;
+pub use refills::*;
@@ -0,0 +1,35 @@
+use ic_cdk::api::caller;
+use crate::shared::types::*;
+use crate::shared::storage as storage;
+use crate::shared::utils as utils;
+use crate::shared::auth::verify_registered_user;
+use crate::doctor::prescriptions::begin_refill;
+
+/// Request the next fill of a repeat prescription. Open to verified
+/// pharmacies and to the patient the prescription is linked to.
+#[ic_cdk::update]
+pub fn request_refill(prescription_code: String) -> Result<Prescription> {
+    let caller_principal = caller().to_string();
+    let user = verify_registered_user()?;
+
+    utils::validate_prescription_code(&prescription_code)?;
+
+    let mut prescription = storage::get_prescription_by_code(&prescription_code)
+        .ok_or("Prescription not found".to_string())?;
+
+    let result = match user.role {
+        UserRole::Pharmacy if user.verification_status != VerificationStatus::Approved => {
+            Err("Pharmacy must be verified to request refills".to_string())
+        }
+        UserRole::Patient if prescription.patient_principal.as_deref() != Some(caller_principal.as_str()) => {
+            Err("You can only request refills for your own prescriptions".to_string())
+        }
+        UserRole::Pharmacy | UserRole::Patient => begin_refill(&mut prescription),
+        _ => Err("Only pharmacies and patients can request refills".to_string()),
+    };
+    storage::record_prescription_access(&prescription.id, "request_refill", AccessOutcome::of(&result));
+    result?;
+
+    storage::update_prescription(prescription.clone());
+    Ok(prescription)
+}
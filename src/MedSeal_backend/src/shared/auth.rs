@@ -1,10 +1,147 @@
+use std::time::Duration;
+use candid::Principal;
 use ic_cdk::api::caller;
 use crate::shared::types::*;
 use crate::shared::storage as storage;
 use crate::shared::utils as utils;
 
+const ADMIN_SELF_REGISTRATION_ERROR: &str = "Admin accounts can only be created by an existing admin";
+
+/// Creates the first admin account from the init (or upgrade) arguments,
+/// falling back to the principal installing the canister, once the id
+/// generator is seeded. Does nothing once an admin exists.
+pub fn bootstrap_admin(args: Option<InitArgs>) {
+    if storage::admin_exists() {
+        return;
+    }
+
+    // Canisters deployed before bootstrapping existed may already hold an admin
+    if !storage::get_all_admins().is_empty() {
+        storage::set_admin_exists(true);
+        return;
+    }
+
+    let args = args.unwrap_or_default();
+    let admin_principal = args.admin_principal.unwrap_or_else(caller);
+    if admin_principal == Principal::anonymous() {
+        return;
+    }
+
+    if storage::principal_has_account(&admin_principal.to_string()) {
+        ic_cdk::trap("Bootstrap admin principal already has a non-admin account");
+    }
+
+    // The admin's id comes from the seeded generator, and raw_rand can't be
+    // awaited from init/post_upgrade, so the account is created a round later
+    ic_cdk_timers::set_timer(Duration::ZERO, move || {
+        ic_cdk::spawn(async move {
+            match utils::ensure_rng_seeded().await {
+                Ok(()) => create_bootstrap_admin(admin_principal, args),
+                Err(message) => ic_cdk::println!("Bootstrap admin not created: {}", message),
+            }
+        })
+    });
+}
+
+fn create_bootstrap_admin(admin_principal: Principal, args: InitArgs) {
+    let principal_text = admin_principal.to_string();
+    if storage::admin_exists() || storage::principal_has_account(&principal_text) {
+        return;
+    }
+
+    let now = utils::get_current_timestamp();
+    let admin_id = match utils::generate_user_id() {
+        Ok(admin_id) => admin_id,
+        Err(message) => {
+            ic_cdk::println!("Bootstrap admin not created: {}", message);
+            return;
+        }
+    };
+    let admin = User {
+        id: admin_id.clone(),
+        name: args.admin_name.unwrap_or_else(|| "System Administrator".to_string()),
+        email: args.admin_email.unwrap_or_default(),
+        role: UserRole::Admin,
+        license_number: String::new(),
+        user_principal: principal_text,
+        created_at: now,
+        verification_status: VerificationStatus::Approved,
+        verification_request: None,
+        last_active: Some(now),
+        total_prescriptions: 0,
+        total_medicines: 0,
+        health_profile: None,
+    };
+
+    storage::store_user(admin);
+    storage::store_admin_permissions(AdminPermissions {
+        admin_id,
+        permissions: AdminPermission::all(),
+        updated_at: now,
+        updated_by: None,
+    });
+    storage::set_admin_exists(true);
+}
+
+/// Admins created before permissions existed could do everything; keep it
+/// that way until someone with `ManageUsers` narrows their grant.
+pub fn backfill_admin_permissions() {
+    for admin in storage::get_all_admins() {
+        if storage::get_admin_permissions(&admin.id).is_none() {
+            storage::store_admin_permissions(AdminPermissions {
+                admin_id: admin.id,
+                permissions: AdminPermission::all(),
+                updated_at: utils::get_current_timestamp(),
+                updated_by: None,
+            });
+        }
+    }
+}
+
+/// Returns the calling user if they are an admin, regardless of permissions.
+pub fn verify_admin() -> Result<User> {
+    let caller_principal = caller().to_string();
+
+    match storage::get_user_by_principal(&caller_principal) {
+        Some(user) => {
+            match user.role {
+                UserRole::Admin => Ok(user),
+                _ => Err("Admin access required".to_string()),
+            }
+        },
+        None => Err("User not found".to_string()),
+    }
+}
+
+/// Returns the caller's account, refusing anonymous and unregistered callers.
+/// Endpoints that write to the prescription access log check this first so
+/// the log can't be flooded by callers nobody can attribute.
+pub fn verify_registered_user() -> Result<User> {
+    let caller_principal = caller();
+    if caller_principal == Principal::anonymous() {
+        return Err("Sign in to look up prescriptions".to_string());
+    }
+
+    storage::get_user_by_principal(&caller_principal.to_string())
+        .ok_or("Register an account to look up prescriptions".to_string())
+}
+
+/// Returns the calling admin if they hold `permission`.
+pub fn require_admin_permission(permission: AdminPermission) -> Result<User> {
+    let admin = verify_admin()?;
+
+    if storage::admin_has_permission(&admin.id, &permission) {
+        Ok(admin)
+    } else {
+        Err(format!("Admin permission required: {:?}", permission))
+    }
+}
+
 #[ic_cdk::update]
 pub fn register_user(request: RegisterUserRequest) -> Result<User> {
+    if caller() == Principal::anonymous() {
+        return Err("Sign in before registering an account".to_string());
+    }
     let caller_principal = caller().to_string();
 
     if storage::principal_has_account(&caller_principal) {
@@ -16,16 +153,16 @@ pub fn register_user(request: RegisterUserRequest) -> Result<User> {
         return Err("Invalid email address".to_string());
     }
 
-    // Check if this is first admin creation
     let verification_status = match request.role {
         UserRole::Doctor => VerificationStatus::Pending,
-        UserRole::Admin => VerificationStatus::Approved,
+        UserRole::Admin => return Err(ADMIN_SELF_REGISTRATION_ERROR.to_string()),
         UserRole::Patient => VerificationStatus::NotRequired,
         UserRole::NGO => VerificationStatus::Pending,
+        UserRole::Pharmacy => VerificationStatus::Pending,
     };
 
     let user = User {
-        id: utils::generate_user_id(),
+        id: utils::generate_user_id()?,
         name: request.name,
         email: request.email,
         role: request.role,
@@ -37,15 +174,28 @@ pub fn register_user(request: RegisterUserRequest) -> Result<User> {
         last_active: Some(utils::get_current_timestamp()),
         total_prescriptions: 0,
         total_medicines: 0,
+        health_profile: None,
     };
 
     storage::store_user(user.clone());
     Ok(user)
 }
 
+/// Creates an account bound to someone else's principal. Restricted to
+/// controllers and admins with `ManageUsers` for assisted onboarding; users
+/// signing up for themselves go through `register_user`.
 #[ic_cdk::update]
 pub fn register_user_with_principal(request: RegisterUserWithPrincipalRequest) -> Result<User> {
-    if storage::principal_has_account(&request.user_principal) {
+    let caller_principal = caller();
+    if !ic_cdk::api::is_controller(&caller_principal) {
+        require_admin_permission(AdminPermission::ManageUsers)?;
+    }
+
+    let user_principal = Principal::from_text(&request.user_principal)
+        .map_err(|_| "Invalid user principal".to_string())?
+        .to_string();
+
+    if storage::principal_has_account(&user_principal) {
         return Err("Principal already has an account".to_string());
     }
 
@@ -53,30 +203,38 @@ pub fn register_user_with_principal(request: RegisterUserWithPrincipalRequest) -
         return Err("Invalid email address".to_string());
     }
 
-    // Check if this is first admin creation
     let verification_status = match request.role {
         UserRole::Doctor => VerificationStatus::Pending,
-        UserRole::Admin => VerificationStatus::Approved,
+        UserRole::Admin => return Err(ADMIN_SELF_REGISTRATION_ERROR.to_string()),
         UserRole::Patient => VerificationStatus::NotRequired,
         UserRole::NGO => VerificationStatus::Pending,
+        UserRole::Pharmacy => VerificationStatus::Pending,
     };
 
     let user = User {
-        id: utils::generate_user_id(),
+        id: utils::generate_user_id()?,
         name: request.name,
         email: request.email,
         role: request.role,
         license_number: request.license_number,
-        user_principal: request.user_principal.clone(),
+        user_principal: user_principal.clone(),
         created_at: utils::get_current_timestamp(),
         verification_status,
         verification_request: None,
-        last_active: Some(utils::get_current_timestamp()),
+        last_active: None,
         total_prescriptions: 0,
         total_medicines: 0,
+        health_profile: None,
     };
 
     storage::store_user(user.clone());
+    storage::append_audit_entry(
+        caller_principal.to_string(),
+        "register_user_with_principal",
+        user.id.clone(),
+        format!("Registered {:?} account for principal {}", user.role, user_principal),
+        utils::get_current_timestamp(),
+    );
     Ok(user)
 }
 
@@ -86,15 +244,24 @@ pub fn register_user_simple(name: String, email: String, role: UserRole, license
     register_user(request)
 }
 
+/// Strips the health profile unless the caller owns the account. Doctors
+/// read profiles through the consent-checked endpoints instead.
+pub fn redact_user(mut user: User) -> User {
+    if user.user_principal != caller().to_string() {
+        user.health_profile = None;
+    }
+    user
+}
+
 #[ic_cdk::query]
 pub fn get_user(user_id: String) -> Option<User> {
-    storage::get_user(&user_id)
+    storage::get_user(&user_id).map(redact_user)
 }
 
 #[ic_cdk::query]
 pub fn get_user_by_principal(principal: String) -> Result<User> {
     match storage::get_user_by_principal(&principal) {
-        Some(user) => Ok(user),
+        Some(user) => Ok(redact_user(user)),
         None => Err("User not found for this principal".to_string()),
     }
 }
@@ -102,7 +269,7 @@ pub fn get_user_by_principal(principal: String) -> Result<User> {
 #[ic_cdk::query]
 pub fn get_user_by_principal_detailed(principal: String) -> Result<User> {
     match storage::get_user_by_principal(&principal) {
-        Some(user) => Ok(user),
+        Some(user) => Ok(redact_user(user)),
         None => Err(format!("No user found for principal: {}", principal)),
     }
 }
@@ -128,7 +295,7 @@ pub fn authenticate_user(email: String, license_number: String) -> Result<User>
         if user_email == &email {
             if let Some(user) = storage::get_user(user_id) {
                 if user.license_number == license_number {
-                    return Ok(user);
+                    return Ok(redact_user(user));
                 }
             }
         }
@@ -155,7 +322,7 @@ pub fn debug_all_users() -> Vec<PrincipalEntry> {
 
 // Utility functions re-exported
 #[ic_cdk::query]
-pub fn generate_user_id() -> String {
+pub fn generate_user_id() -> Result<String> {
     utils::generate_user_id()
 }
 
@@ -0,0 +1,304 @@
+use crate::shared::types::*;
+
+pub const MAX_DURATION_DAYS: u32 = 365;
+pub const MAX_TIMES_PER_PERIOD: u32 = 24;
+
+pub fn validate_dosage(spec: &DosageSpec) -> Result<()> {
+    if !spec.strength.is_finite() || spec.strength <= 0.0 {
+        return Err("Dosage strength must be a positive number".to_string());
+    }
+
+    if let DoseForm::Other(form) = &spec.form {
+        if form.trim().is_empty() {
+            return Err("Dose form must be named".to_string());
+        }
+    }
+
+    if let DoseRoute::Other(route) = &spec.route {
+        if route.trim().is_empty() {
+            return Err("Route must be named".to_string());
+        }
+    }
+
+    if spec.frequency.times == 0 || spec.frequency.times > MAX_TIMES_PER_PERIOD {
+        return Err(format!("Frequency must be between 1 and {} times per period", MAX_TIMES_PER_PERIOD));
+    }
+
+    if spec.duration_days == 0 || spec.duration_days > MAX_DURATION_DAYS {
+        return Err(format!("Duration must be between 1 and {} days", MAX_DURATION_DAYS));
+    }
+
+    Ok(())
+}
+
+pub fn unit_label(unit: &StrengthUnit) -> &'static str {
+    match unit {
+        StrengthUnit::Mg => "mg",
+        StrengthUnit::Mcg => "mcg",
+        StrengthUnit::G => "g",
+        StrengthUnit::Ml => "ml",
+        StrengthUnit::MgPerMl => "mg/ml",
+        StrengthUnit::IU => "IU",
+        StrengthUnit::Percent => "%",
+    }
+}
+
+pub fn form_label(form: &DoseForm) -> String {
+    match form {
+        DoseForm::Tablet => "tablet".to_string(),
+        DoseForm::Capsule => "capsule".to_string(),
+        DoseForm::Syrup => "syrup".to_string(),
+        DoseForm::Suspension => "suspension".to_string(),
+        DoseForm::Injection => "injection".to_string(),
+        DoseForm::Cream => "cream".to_string(),
+        DoseForm::Ointment => "ointment".to_string(),
+        DoseForm::Drops => "drops".to_string(),
+        DoseForm::Inhaler => "inhaler".to_string(),
+        DoseForm::Patch => "patch".to_string(),
+        DoseForm::Suppository => "suppository".to_string(),
+        DoseForm::Other(name) => name.trim().to_lowercase(),
+    }
+}
+
+pub fn route_label(route: &DoseRoute) -> String {
+    match route {
+        DoseRoute::Oral => "oral".to_string(),
+        DoseRoute::Sublingual => "sublingual".to_string(),
+        DoseRoute::Topical => "topical".to_string(),
+        DoseRoute::Intravenous => "intravenous".to_string(),
+        DoseRoute::Intramuscular => "intramuscular".to_string(),
+        DoseRoute::Subcutaneous => "subcutaneous".to_string(),
+        DoseRoute::Inhalation => "inhaled".to_string(),
+        DoseRoute::Ophthalmic => "eye".to_string(),
+        DoseRoute::Otic => "ear".to_string(),
+        DoseRoute::Nasal => "nasal".to_string(),
+        DoseRoute::Rectal => "rectal".to_string(),
+        DoseRoute::Other(name) => name.trim().to_lowercase(),
+    }
+}
+
+pub fn period_label(period: &FrequencyPeriod) -> &'static str {
+    match period {
+        FrequencyPeriod::Day => "day",
+        FrequencyPeriod::Week => "week",
+        FrequencyPeriod::Month => "month",
+    }
+}
+
+const UNITS: [StrengthUnit; 7] = [
+    StrengthUnit::Mg,
+    StrengthUnit::Mcg,
+    StrengthUnit::G,
+    StrengthUnit::Ml,
+    StrengthUnit::MgPerMl,
+    StrengthUnit::IU,
+    StrengthUnit::Percent,
+];
+
+const FORMS: [DoseForm; 11] = [
+    DoseForm::Tablet,
+    DoseForm::Capsule,
+    DoseForm::Syrup,
+    DoseForm::Suspension,
+    DoseForm::Injection,
+    DoseForm::Cream,
+    DoseForm::Ointment,
+    DoseForm::Drops,
+    DoseForm::Inhaler,
+    DoseForm::Patch,
+    DoseForm::Suppository,
+];
+
+const ROUTES: [DoseRoute; 11] = [
+    DoseRoute::Oral,
+    DoseRoute::Sublingual,
+    DoseRoute::Topical,
+    DoseRoute::Intravenous,
+    DoseRoute::Intramuscular,
+    DoseRoute::Subcutaneous,
+    DoseRoute::Inhalation,
+    DoseRoute::Ophthalmic,
+    DoseRoute::Otic,
+    DoseRoute::Nasal,
+    DoseRoute::Rectal,
+];
+
+// The parsers accept the labels above as well as the variant names, so text
+// rendered by this module reads back to the same value.
+
+pub fn parse_unit(text: &str) -> Option<StrengthUnit> {
+    let text = text.trim().to_lowercase();
+    UNITS.into_iter().find(|unit| {
+        unit_label(unit).to_lowercase() == text || format!("{:?}", unit).to_lowercase() == text
+    })
+}
+
+pub fn parse_form(text: &str) -> DoseForm {
+    let normalized = text.trim().to_lowercase();
+    FORMS.into_iter()
+        .find(|form| form_label(form) == normalized || format!("{:?}", form).to_lowercase() == normalized)
+        .unwrap_or_else(|| DoseForm::Other(text.trim().to_string()))
+}
+
+pub fn parse_route(text: &str) -> DoseRoute {
+    let normalized = text.trim().to_lowercase();
+    ROUTES.into_iter()
+        .find(|route| route_label(route) == normalized || format!("{:?}", route).to_lowercase() == normalized)
+        .unwrap_or_else(|| DoseRoute::Other(text.trim().to_string()))
+}
+
+pub fn parse_period(text: &str) -> Option<FrequencyPeriod> {
+    match text.trim().to_lowercase().as_str() {
+        "day" => Some(FrequencyPeriod::Day),
+        "week" => Some(FrequencyPeriod::Week),
+        "month" => Some(FrequencyPeriod::Month),
+        _ => None,
+    }
+}
+
+/// "500 mg tablet (oral)"
+pub fn render_strength(spec: &DosageSpec) -> String {
+    let separator = if spec.unit == StrengthUnit::Percent { "" } else { " " };
+    format!(
+        "{}{}{} {} ({})",
+        spec.strength, separator, unit_label(&spec.unit), form_label(&spec.form), route_label(&spec.route)
+    )
+}
+
+/// "twice a day", "3 times a week, as needed"
+pub fn render_frequency(frequency: &DoseFrequency) -> String {
+    let times = match frequency.times {
+        1 => "once".to_string(),
+        2 => "twice".to_string(),
+        n => format!("{} times", n),
+    };
+    let period = period_label(&frequency.period);
+
+    if frequency.as_needed {
+        format!("up to {} a {}, as needed", times, period)
+    } else {
+        format!("{} a {}", times, period)
+    }
+}
+
+/// "1 day", "14 days"
+pub fn render_duration(days: u32) -> String {
+    if days == 1 {
+        "1 day".to_string()
+    } else {
+        format!("{} days", days)
+    }
+}
+
+/// One-line summary, e.g. "250 mg capsule (oral), twice a day for 5 days"
+pub fn describe_dosage(spec: &DosageSpec) -> String {
+    format!(
+        "{}, {} for {}",
+        render_strength(spec),
+        render_frequency(&spec.frequency),
+        render_duration(spec.duration_days)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec() -> DosageSpec {
+        DosageSpec {
+            strength: 250.0,
+            unit: StrengthUnit::Mg,
+            form: DoseForm::Capsule,
+            route: DoseRoute::Oral,
+            frequency: DoseFrequency {
+                times: 2,
+                period: FrequencyPeriod::Day,
+                as_needed: false,
+            },
+            duration_days: 5,
+        }
+    }
+
+    #[test]
+    fn units_read_back_from_their_labels_and_names() {
+        for unit in UNITS {
+            assert_eq!(parse_unit(unit_label(&unit)), Some(unit.clone()));
+            assert_eq!(parse_unit(&format!("{:?}", unit)), Some(unit));
+        }
+        assert_eq!(parse_unit(" MG/ML "), Some(StrengthUnit::MgPerMl));
+        assert_eq!(parse_unit("grains"), None);
+    }
+
+    #[test]
+    fn forms_read_back_from_their_labels() {
+        for form in FORMS {
+            assert_eq!(parse_form(&form_label(&form)), form);
+        }
+        let other = DoseForm::Other("gel".to_string());
+        assert_eq!(parse_form(&form_label(&other)), other);
+    }
+
+    #[test]
+    fn routes_read_back_from_their_labels() {
+        for route in ROUTES {
+            assert_eq!(parse_route(&route_label(&route)), route);
+        }
+        assert_eq!(parse_route("Inhalation"), DoseRoute::Inhalation);
+    }
+
+    #[test]
+    fn periods_read_back_from_their_labels() {
+        for period in [FrequencyPeriod::Day, FrequencyPeriod::Week, FrequencyPeriod::Month] {
+            assert_eq!(parse_period(period_label(&period)), Some(period));
+        }
+        assert_eq!(parse_period("fortnight"), None);
+    }
+
+    #[test]
+    fn accepts_a_well_formed_dosage() {
+        assert!(validate_dosage(&spec()).is_ok());
+    }
+
+    #[test]
+    fn rejects_non_positive_or_non_finite_strengths() {
+        for strength in [0.0, -5.0, f64::NAN, f64::INFINITY] {
+            assert!(validate_dosage(&DosageSpec { strength, ..spec() }).is_err());
+        }
+    }
+
+    #[test]
+    fn rejects_unnamed_forms_and_routes() {
+        assert!(validate_dosage(&DosageSpec { form: DoseForm::Other("  ".to_string()), ..spec() }).is_err());
+        assert!(validate_dosage(&DosageSpec { route: DoseRoute::Other(String::new()), ..spec() }).is_err());
+    }
+
+    #[test]
+    fn rejects_frequencies_and_durations_out_of_range() {
+        let mut too_often = spec();
+        too_often.frequency.times = MAX_TIMES_PER_PERIOD + 1;
+        assert!(validate_dosage(&too_often).is_err());
+
+        let mut never = spec();
+        never.frequency.times = 0;
+        assert!(validate_dosage(&never).is_err());
+
+        assert!(validate_dosage(&DosageSpec { duration_days: 0, ..spec() }).is_err());
+        assert!(validate_dosage(&DosageSpec { duration_days: MAX_DURATION_DAYS + 1, ..spec() }).is_err());
+        assert!(validate_dosage(&DosageSpec { duration_days: MAX_DURATION_DAYS, ..spec() }).is_ok());
+    }
+
+    #[test]
+    fn renders_a_one_line_summary() {
+        assert_eq!(describe_dosage(&spec()), "250 mg capsule (oral), twice a day for 5 days");
+
+        let cream = DosageSpec {
+            strength: 1.0,
+            unit: StrengthUnit::Percent,
+            form: DoseForm::Cream,
+            route: DoseRoute::Topical,
+            frequency: DoseFrequency { times: 3, period: FrequencyPeriod::Week, as_needed: true },
+            duration_days: 1,
+        };
+        assert_eq!(describe_dosage(&cream), "1% cream (topical), up to 3 times a week, as needed for 1 day");
+    }
+}
@@ -0,0 +1,271 @@
+use candid::{CandidType, Deserialize, Nat, Principal};
+use sha2::{Digest, Sha256};
+use crate::shared::types::*;
+use crate::shared::storage as storage;
+
+// ICRC-1 / ICRC-2 ledger interface, limited to what the canister calls
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<Vec<u8>>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct TransferArg {
+    pub from_subaccount: Option<Vec<u8>>,
+    pub to: Account,
+    pub amount: Nat,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum TransferError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct TransferFromArgs {
+    pub spender_subaccount: Option<Vec<u8>>,
+    pub from: Account,
+    pub to: Account,
+    pub amount: Nat,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum TransferFromError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    InsufficientAllowance { allowance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+/// The ledger contributions are paid through, as configured in system settings.
+pub fn configured_ledger() -> Result<Principal> {
+    storage::get_system_settings()
+        .ledger_canister_id
+        .ok_or("No ledger is configured for contributions".to_string())
+}
+
+/// Each pool holds its funds in its own subaccount of this canister, derived
+/// from the pool id so it never has to be stored.
+pub fn pool_subaccount(pool_id: &str) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(b"medseal-pool:");
+    hasher.update(pool_id.as_bytes());
+    hasher.finalize().to_vec()
+}
+
+pub fn pool_account(pool_id: &str) -> Account {
+    Account {
+        owner: ic_cdk::api::id(),
+        subaccount: Some(pool_subaccount(pool_id)),
+    }
+}
+
+/// Each NGO's general fund, where pool surplus can be redirected to.
+pub fn ngo_fund_subaccount(ngo_id: &str) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(b"medseal-ngo-fund:");
+    hasher.update(ngo_id.as_bytes());
+    hasher.finalize().to_vec()
+}
+
+pub fn ngo_fund_account(ngo_id: &str) -> Account {
+    Account {
+        owner: ic_cdk::api::id(),
+        subaccount: Some(ngo_fund_subaccount(ngo_id)),
+    }
+}
+
+pub fn nat_to_u64(value: Nat) -> Result<u64> {
+    u64::try_from(value.0).map_err(|_| "Ledger returned a value that does not fit in 64 bits".to_string())
+}
+
+fn describe_transfer_from_error(error: TransferFromError) -> String {
+    match error {
+        TransferFromError::InsufficientAllowance { allowance } => {
+            format!("Approve this canister to spend the amount plus the ledger fee first (current allowance: {})", allowance)
+        }
+        TransferFromError::InsufficientFunds { balance } => format!("Insufficient funds (balance: {})", balance),
+        TransferFromError::BadFee { expected_fee } => format!("Unexpected ledger fee, expected {}", expected_fee),
+        TransferFromError::Duplicate { duplicate_of } => format!("Duplicate transfer of block {}", duplicate_of),
+        TransferFromError::TemporarilyUnavailable => "The ledger is temporarily unavailable, please retry".to_string(),
+        TransferFromError::GenericError { message, .. } => format!("Ledger error: {}", message),
+        other => format!("Ledger rejected the transfer: {:?}", other),
+    }
+}
+
+/// Pulls `amount` from `from` into `to` under an ICRC-2 approval held by this
+/// canister. Returns the ledger block index once the transfer is final.
+pub async fn transfer_from(
+    ledger: Principal,
+    from: Account,
+    to: Account,
+    amount: u64,
+    memo: Vec<u8>,
+    created_at_time: u64,
+) -> Result<u64> {
+    let args = TransferFromArgs {
+        spender_subaccount: None,
+        from,
+        to,
+        amount: Nat::from(amount),
+        fee: None,
+        memo: Some(memo),
+        created_at_time: Some(created_at_time),
+    };
+
+    let (result,): (std::result::Result<Nat, TransferFromError>,) =
+        ic_cdk::call(ledger, "icrc2_transfer_from", (args,))
+            .await
+            .map_err(|(code, message)| format!("Ledger call failed ({:?}): {}", code, message))?;
+
+    match result {
+        Ok(block_index) => nat_to_u64(block_index),
+        Err(error) => Err(describe_transfer_from_error(error)),
+    }
+}
+
+/// Why an outgoing transfer did not complete. `Rejected` is final: the ledger
+/// refused it and nothing moved. `Unknown` covers failed calls and refusals
+/// that don't settle whether an earlier attempt went through; retrying with
+/// the same memo and `created_at_time` is safe, as the ledger deduplicates.
+/// `TooOld` means the timestamp is past the deduplication window: retrying
+/// cannot settle anything, the transfer has to be looked up on the ledger.
+#[derive(Clone, Debug)]
+pub enum TransferFailure {
+    Rejected(String),
+    Unknown(String),
+    TooOld(String),
+}
+
+impl TransferFailure {
+    pub fn is_final(&self) -> bool {
+        matches!(self, TransferFailure::Rejected(_))
+    }
+
+    pub fn needs_lookup(&self) -> bool {
+        matches!(self, TransferFailure::TooOld(_))
+    }
+}
+
+impl From<TransferFailure> for String {
+    fn from(failure: TransferFailure) -> String {
+        match failure {
+            TransferFailure::Rejected(message)
+            | TransferFailure::Unknown(message)
+            | TransferFailure::TooOld(message) => message,
+        }
+    }
+}
+
+fn classify_transfer_error(error: TransferError) -> TransferFailure {
+    match error {
+        TransferError::InsufficientFunds { balance } => {
+            TransferFailure::Rejected(format!("Insufficient funds (balance: {})", balance))
+        }
+        TransferError::BadFee { expected_fee } => {
+            TransferFailure::Rejected(format!("Unexpected ledger fee, expected {}", expected_fee))
+        }
+        TransferError::TemporarilyUnavailable => {
+            TransferFailure::Unknown("The ledger is temporarily unavailable, please retry".to_string())
+        }
+        // Past the deduplication window the ledger can no longer say whether
+        // an earlier attempt with this timestamp went through
+        TransferError::TooOld => TransferFailure::TooOld(
+            "The ledger can no longer confirm whether this transfer went through".to_string()
+        ),
+        TransferError::GenericError { message, .. } => TransferFailure::Rejected(format!("Ledger error: {}", message)),
+        other => TransferFailure::Rejected(format!("Ledger rejected the transfer: {:?}", other)),
+    }
+}
+
+/// Sends `amount` out of one of this canister's subaccounts, paying `fee`
+/// from the same subaccount. Returns the ledger block index, including when
+/// the ledger reports the identical transfer as already done.
+pub async fn transfer(
+    ledger: Principal,
+    from_subaccount: Vec<u8>,
+    to: Account,
+    amount: u64,
+    fee: u64,
+    memo: Vec<u8>,
+    created_at_time: u64,
+) -> std::result::Result<u64, TransferFailure> {
+    let args = TransferArg {
+        from_subaccount: Some(from_subaccount),
+        to,
+        amount: Nat::from(amount),
+        fee: Some(Nat::from(fee)),
+        memo: Some(memo),
+        created_at_time: Some(created_at_time),
+    };
+
+    let (result,): (std::result::Result<Nat, TransferError>,) =
+        ic_cdk::call(ledger, "icrc1_transfer", (args,))
+            .await
+            .map_err(|(code, message)| TransferFailure::Unknown(format!("Ledger call failed ({:?}): {}", code, message)))?;
+
+    match result {
+        Ok(block_index) | Err(TransferError::Duplicate { duplicate_of: block_index }) => {
+            nat_to_u64(block_index).map_err(TransferFailure::Unknown)
+        }
+        Err(error) => Err(classify_transfer_error(error)),
+    }
+}
+
+pub async fn fee(ledger: Principal) -> Result<u64> {
+    let (fee,): (Nat,) = ic_cdk::call(ledger, "icrc1_fee", ())
+        .await
+        .map_err(|(code, message)| format!("Ledger call failed ({:?}): {}", code, message))?;
+    nat_to_u64(fee)
+}
+
+pub async fn balance_of(ledger: Principal, account: Account) -> Result<Nat> {
+    let (balance,): (Nat,) = ic_cdk::call(ledger, "icrc1_balance_of", (account,))
+        .await
+        .map_err(|(code, message)| format!("Ledger call failed ({:?}): {}", code, message))?;
+    Ok(balance)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refusals_that_settle_nothing_are_not_final() {
+        assert!(!classify_transfer_error(TransferError::TooOld).is_final());
+        assert!(!classify_transfer_error(TransferError::TemporarilyUnavailable).is_final());
+    }
+
+    #[test]
+    fn only_transfers_past_the_deduplication_window_need_a_lookup() {
+        assert!(classify_transfer_error(TransferError::TooOld).needs_lookup());
+        assert!(!classify_transfer_error(TransferError::TemporarilyUnavailable).needs_lookup());
+        assert!(!classify_transfer_error(TransferError::InsufficientFunds { balance: Nat::from(5u64) }).needs_lookup());
+    }
+
+    #[test]
+    fn ledger_refusals_are_final() {
+        assert!(classify_transfer_error(TransferError::InsufficientFunds { balance: Nat::from(5u64) }).is_final());
+        assert!(classify_transfer_error(TransferError::BadFee { expected_fee: Nat::from(10u64) }).is_final());
+    }
+}
@@ -0,0 +1,390 @@
+use candid::{CandidType, Deserialize};
+use serde::de::DeserializeOwned;
+use crate::shared::types::*;
+use crate::shared::storage as storage;
+use crate::shared::utils as utils;
+
+/// Records written before versioned envelopes existed are treated as this version.
+pub const LEGACY_SCHEMA_VERSION: u32 = 1;
+
+/// Version stamped on every versioned record written by this build.
+/// Bump it whenever `User`, `Prescription`, `PatientCase` or `Medicine` change shape, keep
+/// the previous shape in this file and teach the matching `upgrade_*` to convert it.
+/// `MedicineRevision` embeds a `Medicine`, so it has to follow medicine changes too.
+pub const CURRENT_SCHEMA_VERSION: u32 = 5;
+
+fn decode<T: CandidType + DeserializeOwned>(bytes: &[u8]) -> T {
+    candid::decode_one(bytes).expect("failed to decode stored record")
+}
+
+// Schema history:
+//   1 - bare candid records
+//   2 - versioned envelopes, record shapes unchanged
+//   3 - Prescription gains lifecycle status and expiry
+//   4 - Medicine joins the versioned records and gains active ingredients
+//   5 - prescribed custom dosages become structured
+// Optional fields added later (e.g. `Prescription.refills`) decode as
+// None from older records and don't need a version bump.
+
+/// `PrescriptionMedicine` as stored in schema versions 1-4.
+#[derive(CandidType, Deserialize)]
+struct PrescriptionMedicineV4 {
+    medicine_id: String,
+    custom_dosage: Option<String>,
+    custom_instructions: String,
+    quantity: Option<u32>,
+}
+
+impl From<PrescriptionMedicineV4> for PrescriptionMedicine {
+    // Free-text dosages can't be parsed reliably, so they move into the
+    // instructions where the patient and pharmacist still see them.
+    fn from(old: PrescriptionMedicineV4) -> Self {
+        let custom_instructions = match old.custom_dosage.as_deref().map(str::trim) {
+            Some(dosage) if !dosage.is_empty() => {
+                if old.custom_instructions.trim().is_empty() {
+                    format!("Dosage: {}", dosage)
+                } else {
+                    format!("Dosage: {}. {}", dosage, old.custom_instructions)
+                }
+            }
+            _ => old.custom_instructions,
+        };
+
+        PrescriptionMedicine {
+            medicine_id: old.medicine_id,
+            custom_dosage: None,
+            custom_instructions,
+            quantity: old.quantity,
+            medicine_revision: None,
+        }
+    }
+}
+
+/// `Prescription` as stored in schema versions 1-2.
+#[derive(CandidType, Deserialize)]
+struct PrescriptionV2 {
+    id: String,
+    prescription_code: String,
+    patient_name: String,
+    patient_contact: String,
+    patient_principal: Option<String>,
+    medicines: Vec<PrescriptionMedicineV4>,
+    additional_notes: String,
+    created_at: u64,
+    accessed_at: Option<u64>,
+    doctor_id: String,
+}
+
+impl From<PrescriptionV2> for PrescriptionV4 {
+    // Prescriptions issued before the lifecycle existed count as issued and
+    // never expire; they were handed out without a validity period.
+    fn from(old: PrescriptionV2) -> Self {
+        PrescriptionV4 {
+            id: old.id,
+            prescription_code: old.prescription_code,
+            patient_name: old.patient_name,
+            patient_contact: old.patient_contact,
+            patient_principal: old.patient_principal,
+            medicines: old.medicines,
+            additional_notes: old.additional_notes,
+            created_at: old.created_at,
+            accessed_at: old.accessed_at,
+            doctor_id: old.doctor_id,
+            status: PrescriptionStatus::Issued,
+            status_updated_at: old.created_at,
+            issued_at: Some(old.created_at),
+            expires_at: None,
+            cancellation_reason: None,
+            refills: None,
+            interaction_override_reason: None,
+        }
+    }
+}
+
+/// `Prescription` as stored in schema versions 3-4.
+#[derive(CandidType, Deserialize)]
+struct PrescriptionV4 {
+    id: String,
+    prescription_code: String,
+    patient_name: String,
+    patient_contact: String,
+    patient_principal: Option<String>,
+    medicines: Vec<PrescriptionMedicineV4>,
+    additional_notes: String,
+    created_at: u64,
+    accessed_at: Option<u64>,
+    doctor_id: String,
+    status: PrescriptionStatus,
+    status_updated_at: u64,
+    issued_at: Option<u64>,
+    expires_at: Option<u64>,
+    cancellation_reason: Option<String>,
+    refills: Option<PrescriptionRefills>,
+    interaction_override_reason: Option<String>,
+}
+
+impl From<PrescriptionV4> for Prescription {
+    fn from(old: PrescriptionV4) -> Self {
+        Prescription {
+            id: old.id,
+            prescription_code: old.prescription_code,
+            patient_name: old.patient_name,
+            patient_contact: old.patient_contact,
+            patient_principal: old.patient_principal,
+            medicines: old.medicines.into_iter().map(PrescriptionMedicine::from).collect(),
+            additional_notes: old.additional_notes,
+            created_at: old.created_at,
+            accessed_at: old.accessed_at,
+            doctor_id: old.doctor_id,
+            status: old.status,
+            status_updated_at: old.status_updated_at,
+            issued_at: old.issued_at,
+            expires_at: old.expires_at,
+            cancellation_reason: old.cancellation_reason,
+            refills: old.refills,
+            interaction_override_reason: old.interaction_override_reason,
+            claimed_at: None,
+        }
+    }
+}
+
+/// `Medicine` as stored in schema versions 1-3 (stored bare, without an envelope).
+#[derive(CandidType, Deserialize)]
+struct MedicineV3 {
+    id: String,
+    name: String,
+    dosage: String,
+    frequency: String,
+    duration: String,
+    side_effects: String,
+    guide_text: String,
+    guide_source: String,
+    description: String,
+    created_at: u64,
+    created_by: String,
+    doctor_id: String,
+    is_active: bool,
+}
+
+impl From<MedicineV3> for Medicine {
+    // Ingredients were never recorded; doctors fill them in on their next edit
+    fn from(old: MedicineV3) -> Self {
+        Medicine {
+            id: old.id,
+            name: old.name,
+            dosage: old.dosage,
+            frequency: old.frequency,
+            duration: old.duration,
+            side_effects: old.side_effects,
+            guide_text: old.guide_text,
+            guide_source: old.guide_source,
+            description: old.description,
+            created_at: old.created_at,
+            created_by: old.created_by,
+            doctor_id: old.doctor_id,
+            is_active: old.is_active,
+            active_ingredients: Vec::new(),
+            dosage_spec: None,
+            formulary: None,
+            current_revision: None,
+        }
+    }
+}
+
+pub fn upgrade_user(version: u32, bytes: &[u8]) -> User {
+    match version {
+        1..=5 => decode(bytes),
+        other => panic!("Unsupported user schema version {}", other),
+    }
+}
+
+pub fn upgrade_prescription(version: u32, bytes: &[u8]) -> Prescription {
+    match version {
+        1 | 2 => PrescriptionV4::from(decode::<PrescriptionV2>(bytes)).into(),
+        3 | 4 => decode::<PrescriptionV4>(bytes).into(),
+        5 => decode(bytes),
+        other => panic!("Unsupported prescription schema version {}", other),
+    }
+}
+
+pub fn upgrade_patient_case(version: u32, bytes: &[u8]) -> PatientCase {
+    match version {
+        1..=5 => decode(bytes),
+        other => panic!("Unsupported patient case schema version {}", other),
+    }
+}
+
+pub fn upgrade_medicine(version: u32, bytes: &[u8]) -> Medicine {
+    match version {
+        1..=3 => decode::<MedicineV3>(bytes).into(),
+        4 | 5 => decode(bytes),
+        other => panic!("Unsupported medicine schema version {}", other),
+    }
+}
+
+// Revisions were first written at schema version 5
+pub fn upgrade_medicine_revision(version: u32, bytes: &[u8]) -> MedicineRevision {
+    match version {
+        5 => decode(bytes),
+        other => panic!("Unsupported medicine revision schema version {}", other),
+    }
+}
+
+/// Brings every versioned record up to `CURRENT_SCHEMA_VERSION`. Runs in
+/// `post_upgrade`; trapping here rolls the upgrade back.
+pub fn run_migrations() {
+    let from_version = storage::get_schema_version();
+
+    if from_version > CURRENT_SCHEMA_VERSION {
+        panic!(
+            "Stored schema version {} is newer than this build ({}); refusing to downgrade",
+            from_version, CURRENT_SCHEMA_VERSION
+        );
+    }
+
+    if from_version == CURRENT_SCHEMA_VERSION {
+        return;
+    }
+
+    let record = MigrationRecord {
+        from_version,
+        to_version: CURRENT_SCHEMA_VERSION,
+        applied_at: utils::get_current_timestamp(),
+        users_migrated: storage::rewrite_users(),
+        prescriptions_migrated: storage::rewrite_prescriptions(),
+        patient_cases_migrated: storage::rewrite_patient_cases(),
+        medicines_migrated: Some(storage::rewrite_medicines()),
+        medicine_revisions_migrated: Some(storage::rewrite_medicine_revisions()),
+    };
+
+    storage::append_migration_record(record);
+    storage::set_schema_version(CURRENT_SCHEMA_VERSION);
+}
+
+#[ic_cdk::query]
+pub fn get_schema_info() -> SchemaInfo {
+    SchemaInfo {
+        current_version: storage::get_schema_version(),
+        migration_history: storage::get_migration_history(),
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn medicine_v4(custom_dosage: Option<&str>, custom_instructions: &str) -> PrescriptionMedicineV4 {
+        PrescriptionMedicineV4 {
+            medicine_id: "med_1".to_string(),
+            custom_dosage: custom_dosage.map(str::to_string),
+            custom_instructions: custom_instructions.to_string(),
+            quantity: Some(20),
+        }
+    }
+
+    fn prescription_v2() -> PrescriptionV2 {
+        PrescriptionV2 {
+            id: "prescription_1".to_string(),
+            prescription_code: "RX123456".to_string(),
+            patient_name: "Patient".to_string(),
+            patient_contact: "patient@example.com".to_string(),
+            patient_principal: None,
+            medicines: vec![medicine_v4(Some("500 mg twice daily"), "After meals")],
+            additional_notes: String::new(),
+            created_at: 1_000,
+            accessed_at: Some(2_000),
+            doctor_id: "user_1".to_string(),
+        }
+    }
+
+    #[test]
+    fn free_text_dosages_move_into_the_instructions() {
+        let item = PrescriptionMedicine::from(medicine_v4(Some(" 500 mg twice daily "), "After meals"));
+        assert_eq!(item.custom_instructions, "Dosage: 500 mg twice daily. After meals");
+        assert!(item.custom_dosage.is_none());
+        assert_eq!(item.quantity, Some(20));
+        assert!(item.medicine_revision.is_none());
+
+        let item = PrescriptionMedicine::from(medicine_v4(Some("1 tablet"), " "));
+        assert_eq!(item.custom_instructions, "Dosage: 1 tablet");
+    }
+
+    #[test]
+    fn blank_dosages_leave_the_instructions_alone() {
+        for dosage in [None, Some(""), Some("  ")] {
+            let item = PrescriptionMedicine::from(medicine_v4(dosage, "After meals"));
+            assert_eq!(item.custom_instructions, "After meals");
+        }
+    }
+
+    #[test]
+    fn version_2_prescriptions_count_as_issued_and_never_expire() {
+        let prescription = Prescription::from(PrescriptionV4::from(prescription_v2()));
+        assert_eq!(prescription.status, PrescriptionStatus::Issued);
+        assert_eq!(prescription.status_updated_at, 1_000);
+        assert_eq!(prescription.issued_at, Some(1_000));
+        assert!(prescription.expires_at.is_none());
+        assert!(prescription.claimed_at.is_none());
+        assert_eq!(prescription.accessed_at, Some(2_000));
+        assert_eq!(prescription.medicines[0].custom_instructions, "Dosage: 500 mg twice daily. After meals");
+    }
+
+    #[test]
+    fn version_4_prescriptions_keep_their_lifecycle() {
+        let mut old = PrescriptionV4::from(prescription_v2());
+        old.status = PrescriptionStatus::Cancelled;
+        old.cancellation_reason = Some("Wrong patient".to_string());
+        old.expires_at = Some(9_000);
+
+        let prescription = Prescription::from(old);
+        assert_eq!(prescription.status, PrescriptionStatus::Cancelled);
+        assert_eq!(prescription.cancellation_reason.as_deref(), Some("Wrong patient"));
+        assert_eq!(prescription.expires_at, Some(9_000));
+    }
+
+    #[test]
+    fn version_3_medicines_decode_without_ingredients_or_structured_dosage() {
+        let bytes = candid::encode_one(MedicineV3 {
+            id: "med_1".to_string(),
+            name: "Amoxicillin".to_string(),
+            dosage: "500mg".to_string(),
+            frequency: "3 times a day".to_string(),
+            duration: "7 days".to_string(),
+            side_effects: "Nausea".to_string(),
+            guide_text: String::new(),
+            guide_source: String::new(),
+            description: "Antibiotic".to_string(),
+            created_at: 1_000,
+            created_by: "user_1".to_string(),
+            doctor_id: "user_1".to_string(),
+            is_active: true,
+        })
+        .unwrap();
+
+        let medicine = upgrade_medicine(3, &bytes);
+        assert_eq!(medicine.name, "Amoxicillin");
+        assert_eq!(medicine.dosage, "500mg");
+        assert!(medicine.is_active);
+        assert!(medicine.active_ingredients.is_empty());
+        assert!(medicine.dosage_spec.is_none());
+        assert!(medicine.formulary.is_none());
+        assert!(medicine.current_revision.is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "Unsupported prescription schema version 6")]
+    fn prescriptions_from_a_newer_schema_are_refused() {
+        upgrade_prescription(CURRENT_SCHEMA_VERSION + 1, &[]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Unsupported medicine schema version 0")]
+    fn medicines_from_an_unknown_schema_are_refused() {
+        upgrade_medicine(0, &[]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Unsupported medicine revision schema version 4")]
+    fn revisions_predating_revision_history_are_refused() {
+        upgrade_medicine_revision(4, &[]);
+    }
+}
@@ -1,4 +1,8 @@
 pub mod types;
 pub mod storage;
 pub mod utils;
-pub mod auth;
\ No newline at end of file
+pub mod auth;
+pub mod migrations;
+pub mod dosage;
+pub mod search;
+pub mod ledger;
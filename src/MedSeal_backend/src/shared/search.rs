@@ -0,0 +1,218 @@
+use std::collections::{BTreeMap, HashMap};
+use crate::shared::types::*;
+use crate::shared::storage as storage;
+
+// Which fields a token was found in, stored as a bitmask per index entry
+pub const FIELD_NAME: u8 = 1;
+pub const FIELD_INGREDIENT: u8 = 2;
+pub const FIELD_DESCRIPTION: u8 = 4;
+pub const FIELD_SIDE_EFFECTS: u8 = 8;
+
+// Match tiers, multiplied by the field weight
+const EXACT_MATCH: u32 = 3;
+const PREFIX_MATCH: u32 = 2;
+const FUZZY_MATCH: u32 = 1;
+
+/// Lowercased alphanumeric words of at least two characters.
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|token| token.chars().count() >= 2)
+        .map(str::to_string)
+        .collect()
+}
+
+/// Every indexed token of a medicine with the fields it appears in.
+pub fn medicine_tokens(medicine: &Medicine) -> BTreeMap<String, u8> {
+    let mut tokens: BTreeMap<String, u8> = BTreeMap::new();
+    let mut add = |text: &str, field: u8| {
+        for token in tokenize(text) {
+            *tokens.entry(token).or_insert(0) |= field;
+        }
+    };
+
+    add(&medicine.name, FIELD_NAME);
+    if let Some(details) = &medicine.formulary {
+        for brand in &details.brand_names {
+            add(brand, FIELD_NAME);
+        }
+    }
+    for ingredient in &medicine.active_ingredients {
+        add(ingredient, FIELD_INGREDIENT);
+    }
+    add(&medicine.description, FIELD_DESCRIPTION);
+    add(&medicine.side_effects, FIELD_SIDE_EFFECTS);
+
+    tokens
+}
+
+fn field_weight(fields: u8) -> u32 {
+    if fields & FIELD_NAME != 0 {
+        4
+    } else if fields & FIELD_INGREDIENT != 0 {
+        3
+    } else {
+        1
+    }
+}
+
+// Short words must match exactly; longer ones tolerate one or two typos
+fn max_typos(token: &str) -> usize {
+    match token.chars().count() {
+        0..=3 => 0,
+        4..=7 => 1,
+        _ => 2,
+    }
+}
+
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.iter().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + if ca == cb { 0 } else { 1 };
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+// Whether an indexed token is close enough to a query token to count as a
+// typo of it. Extensions of the query already matched as prefixes.
+fn is_typo_of(token: &str, query_token: &str) -> bool {
+    let typos = max_typos(query_token);
+    if typos == 0 || token.starts_with(query_token) {
+        return false;
+    }
+    token.chars().count().abs_diff(query_token.chars().count()) <= typos
+        && edit_distance(token, query_token) <= typos
+}
+
+// Best score per medicine for a single query token
+fn score_token(query_token: &str) -> HashMap<String, u32> {
+    let mut scores: HashMap<String, u32> = HashMap::new();
+    let mut record = |medicine_id: String, score: u32| {
+        let entry = scores.entry(medicine_id).or_insert(0);
+        *entry = (*entry).max(score);
+    };
+
+    for (token, medicine_id, fields) in storage::search_index_with_prefix(query_token) {
+        let tier = if token == query_token { EXACT_MATCH } else { PREFIX_MATCH };
+        record(medicine_id, tier * field_weight(fields));
+    }
+
+    if max_typos(query_token) > 0 {
+        // Candidates share the first letter, which keeps the scan small
+        let first: String = query_token.chars().take(1).collect();
+        for token in storage::search_vocabulary_with_prefix(&first) {
+            if is_typo_of(&token, query_token) {
+                for (_, medicine_id, fields) in storage::search_index_with_prefix(&token)
+                    .into_iter()
+                    .filter(|(indexed, _, _)| indexed == &token)
+                {
+                    record(medicine_id, FUZZY_MATCH * field_weight(fields));
+                }
+            }
+        }
+    }
+
+    scores
+}
+
+/// Medicine ids matching every word of `query`, best first. A blank query
+/// matches nothing; callers decide what an empty search means.
+pub fn search_medicine_ids(query: &str) -> Vec<(String, u32)> {
+    let mut query_tokens = tokenize(query);
+    query_tokens.sort();
+    query_tokens.dedup();
+
+    rank_matches(query_tokens.iter().map(|query_token| score_token(query_token)))
+}
+
+// Sums each medicine's scores across the query tokens, keeping only those
+// every token matched, best first
+fn rank_matches(token_scores: impl Iterator<Item = HashMap<String, u32>>) -> Vec<(String, u32)> {
+    let mut totals: Option<HashMap<String, u32>> = None;
+    for scores in token_scores {
+        totals = Some(match totals {
+            None => scores,
+            Some(previous) => previous
+                .into_iter()
+                .filter_map(|(id, total)| scores.get(&id).map(|score| (id, total + score)))
+                .collect(),
+        });
+    }
+
+    let mut ranked: Vec<(String, u32)> = totals.unwrap_or_default().into_iter().collect();
+    ranked.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    ranked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scores(entries: &[(&str, u32)]) -> HashMap<String, u32> {
+        entries.iter().map(|(id, score)| (id.to_string(), *score)).collect()
+    }
+
+    #[test]
+    fn tokens_are_lowercased_words_of_two_or_more_characters() {
+        assert_eq!(tokenize("Amoxicillin 500mg, B-complex"), vec!["amoxicillin", "500mg", "complex"]);
+        assert!(tokenize("").is_empty());
+        assert!(tokenize(" - / ").is_empty());
+    }
+
+    #[test]
+    fn edit_distance_counts_insertions_deletions_and_substitutions() {
+        assert_eq!(edit_distance("ibuprofen", "ibuprofen"), 0);
+        assert_eq!(edit_distance("ibuprofen", "ibuprofin"), 1);
+        assert_eq!(edit_distance("ibuprofen", "ibuprfen"), 1);
+        assert_eq!(edit_distance("ibuprofen", "ibuprofenn"), 1);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+
+    #[test]
+    fn typo_tolerance_grows_with_the_word() {
+        // Up to three letters must match exactly
+        assert!(!is_typo_of("abc", "abd"));
+        // Four to seven letters allow one typo
+        assert!(is_typo_of("aspirin", "aspirn"));
+        assert!(!is_typo_of("aspirin", "asprn"));
+        // Eight or more allow two
+        assert!(is_typo_of("paracetamol", "paracetmal"));
+        assert!(!is_typo_of("paracetamol", "parcetmal"));
+    }
+
+    #[test]
+    fn prefixes_are_not_counted_as_typos() {
+        assert!(!is_typo_of("paracetamol", "paracet"));
+    }
+
+    #[test]
+    fn every_query_token_must_match() {
+        let ranked = rank_matches(vec![
+            scores(&[("med_1", 12), ("med_2", 3)]),
+            scores(&[("med_2", 8), ("med_3", 4)]),
+        ].into_iter());
+        assert_eq!(ranked, vec![("med_2".to_string(), 11)]);
+    }
+
+    #[test]
+    fn matches_are_ranked_by_score_then_id() {
+        let ranked = rank_matches(vec![scores(&[("med_b", 4), ("med_a", 4), ("med_c", 12)])].into_iter());
+        let ids: Vec<&str> = ranked.iter().map(|(id, _)| id.as_str()).collect();
+        assert_eq!(ids, vec!["med_c", "med_a", "med_b"]);
+    }
+
+    #[test]
+    fn blank_queries_match_nothing() {
+        assert!(search_medicine_ids("").is_empty());
+        assert!(search_medicine_ids("  , a ").is_empty());
+    }
+}
@@ -1,55 +1,260 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
 use std::cell::RefCell;
+use candid::{CandidType, Decode, Deserialize, Encode};
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap, StableCell, Storable};
 use crate::shared::types::*;
+use crate::shared::migrations;
+use crate::shared::search;
+use crate::shared::utils;
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+// Stable memory layout. Never reuse or reorder these ids: each one owns a
+// region of stable memory that survives upgrades.
+const USERS_MEMORY_ID: MemoryId = MemoryId::new(0);
+const PRINCIPAL_TO_USER_MEMORY_ID: MemoryId = MemoryId::new(1);
+const MEDICINES_MEMORY_ID: MemoryId = MemoryId::new(2);
+const PRESCRIPTIONS_MEMORY_ID: MemoryId = MemoryId::new(3);
+const PRESCRIPTION_CODES_MEMORY_ID: MemoryId = MemoryId::new(4);
+const VERIFICATION_REQUESTS_MEMORY_ID: MemoryId = MemoryId::new(5);
+const ADMIN_EXISTS_MEMORY_ID: MemoryId = MemoryId::new(6);
+const PATIENT_CASES_MEMORY_ID: MemoryId = MemoryId::new(7);
+const CONTRIBUTION_POOLS_MEMORY_ID: MemoryId = MemoryId::new(8);
+const CONTRIBUTIONS_MEMORY_ID: MemoryId = MemoryId::new(9);
+const SCHEMA_VERSION_MEMORY_ID: MemoryId = MemoryId::new(10);
+const MIGRATION_HISTORY_MEMORY_ID: MemoryId = MemoryId::new(11);
+const ADMIN_PERMISSIONS_MEMORY_ID: MemoryId = MemoryId::new(12);
+const AUDIT_LOG_MEMORY_ID: MemoryId = MemoryId::new(13);
+const SYSTEM_SETTINGS_MEMORY_ID: MemoryId = MemoryId::new(14);
+const DISPENSING_RECORDS_MEMORY_ID: MemoryId = MemoryId::new(15);
+const DRUG_INTERACTIONS_MEMORY_ID: MemoryId = MemoryId::new(16);
+const MEDICINE_OVERLAYS_MEMORY_ID: MemoryId = MemoryId::new(17);
+const FORMULARY_CODES_MEMORY_ID: MemoryId = MemoryId::new(18);
+const MEDICINE_SEARCH_INDEX_MEMORY_ID: MemoryId = MemoryId::new(19);
+const MEDICINE_SEARCH_VOCABULARY_MEMORY_ID: MemoryId = MemoryId::new(20);
+const MEDICINE_REVISIONS_MEMORY_ID: MemoryId = MemoryId::new(21);
+const PROFILE_CONSENTS_MEMORY_ID: MemoryId = MemoryId::new(22);
+const CONDITION_CONTRAINDICATIONS_MEMORY_ID: MemoryId = MemoryId::new(23);
+const DOSE_SCHEDULES_MEMORY_ID: MemoryId = MemoryId::new(24);
+const DOSE_LOGS_MEMORY_ID: MemoryId = MemoryId::new(25);
+const DOSE_REMINDERS_MEMORY_ID: MemoryId = MemoryId::new(26);
+const PRESCRIPTION_ACCESS_LOG_MEMORY_ID: MemoryId = MemoryId::new(27);
+const PRESCRIPTION_SHARES_MEMORY_ID: MemoryId = MemoryId::new(28);
+const DISBURSEMENTS_MEMORY_ID: MemoryId = MemoryId::new(29);
+
+// Records are stored candid-encoded, which keeps them readable by the same
+// types the canister exposes over its interface.
+macro_rules! impl_candid_storable {
+    ($($t:ty),* $(,)?) => {
+        $(
+            impl Storable for $t {
+                fn to_bytes(&self) -> Cow<'_, [u8]> {
+                    Cow::Owned(Encode!(self).expect("failed to encode stored record"))
+                }
+
+                fn from_bytes(bytes: Cow<[u8]>) -> Self {
+                    Decode!(bytes.as_ref(), Self).expect("failed to decode stored record")
+                }
+
+                const BOUND: Bound = Bound::Unbounded;
+            }
+        )*
+    };
+}
+
+impl_candid_storable!(
+    VerificationRequest,
+    ContributionPool,
+    Contribution,
+    MigrationRecord,
+    AdminPermissions,
+    AuditEntry,
+    SystemSettings,
+    DispensingRecord,
+    DrugInteraction,
+    MedicineOverlay,
+    ProfileConsent,
+    ConditionContraindication,
+    DoseSchedule,
+    DoseLog,
+    DoseReminder,
+    PrescriptionAccessEntry,
+    PrescriptionShare,
+    Disbursement,
+);
+
+// Envelope for record types whose shape changes between releases. The
+// version tells `migrations` which shape `data` was encoded with.
+#[derive(CandidType, Deserialize)]
+struct StoredRecord {
+    version: u32,
+    data: Vec<u8>,
+}
+
+macro_rules! impl_versioned_storable {
+    ($($t:ty => $upgrade:path),* $(,)?) => {
+        $(
+            impl Storable for $t {
+                fn to_bytes(&self) -> Cow<'_, [u8]> {
+                    let record = StoredRecord {
+                        version: migrations::CURRENT_SCHEMA_VERSION,
+                        data: Encode!(self).expect("failed to encode stored record"),
+                    };
+                    Cow::Owned(Encode!(&record).expect("failed to encode record envelope"))
+                }
+
+                fn from_bytes(bytes: Cow<[u8]>) -> Self {
+                    match Decode!(bytes.as_ref(), StoredRecord) {
+                        Ok(record) => $upgrade(record.version, &record.data),
+                        // Written before envelopes existed: a bare candid record
+                        Err(_) => $upgrade(migrations::LEGACY_SCHEMA_VERSION, bytes.as_ref()),
+                    }
+                }
+
+                const BOUND: Bound = Bound::Unbounded;
+            }
+        )*
+    };
+}
+
+impl_versioned_storable!(
+    User => migrations::upgrade_user,
+    Prescription => migrations::upgrade_prescription,
+    PatientCase => migrations::upgrade_patient_case,
+    Medicine => migrations::upgrade_medicine,
+    MedicineRevision => migrations::upgrade_medicine_revision,
+);
 
 thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+
     // User storage
-    static USERS: RefCell<HashMap<String, User>> = RefCell::new(HashMap::new());
-    static PRINCIPAL_TO_USER: RefCell<HashMap<String, String>> = RefCell::new(HashMap::new());
+    static USERS: RefCell<StableBTreeMap<String, User, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(USERS_MEMORY_ID)));
+    static PRINCIPAL_TO_USER: RefCell<StableBTreeMap<String, String, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(PRINCIPAL_TO_USER_MEMORY_ID)));
     
     // Medicine storage
-    static MEDICINES: RefCell<HashMap<String, Medicine>> = RefCell::new(HashMap::new());
+    static MEDICINES: RefCell<StableBTreeMap<String, Medicine, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(MEDICINES_MEMORY_ID)));
     
     // Prescription storage
-    static PRESCRIPTIONS: RefCell<HashMap<String, Prescription>> = RefCell::new(HashMap::new());
-    static PRESCRIPTION_CODES: RefCell<HashMap<String, String>> = RefCell::new(HashMap::new());
+    static PRESCRIPTIONS: RefCell<StableBTreeMap<String, Prescription, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(PRESCRIPTIONS_MEMORY_ID)));
+    static PRESCRIPTION_CODES: RefCell<StableBTreeMap<String, String, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(PRESCRIPTION_CODES_MEMORY_ID)));
     
     // Verification storage
-    static VERIFICATION_REQUESTS: RefCell<HashMap<String, VerificationRequest>> = RefCell::new(HashMap::new());
+    static VERIFICATION_REQUESTS: RefCell<StableBTreeMap<String, VerificationRequest, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(VERIFICATION_REQUESTS_MEMORY_ID)));
     
     // Admin settings
-    static ADMIN_EXISTS: RefCell<bool> = RefCell::new(false);
+    static ADMIN_EXISTS: RefCell<StableCell<bool, Memory>> = RefCell::new(
+        StableCell::init(memory(ADMIN_EXISTS_MEMORY_ID), false)
+            .expect("failed to initialize admin flag")
+    );
+    static ADMIN_PERMISSIONS: RefCell<StableBTreeMap<String, AdminPermissions, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(ADMIN_PERMISSIONS_MEMORY_ID)));
+    static AUDIT_LOG: RefCell<StableBTreeMap<u64, AuditEntry, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(AUDIT_LOG_MEMORY_ID)));
+    static DISPENSING_RECORDS: RefCell<StableBTreeMap<String, DispensingRecord, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(DISPENSING_RECORDS_MEMORY_ID)));
+    // Keyed by the normalized ingredient pair, see `interaction_key`
+    static DRUG_INTERACTIONS: RefCell<StableBTreeMap<String, DrugInteraction, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(DRUG_INTERACTIONS_MEMORY_ID)));
+    // Keyed by "doctor_id|medicine_id", see `overlay_key`
+    static MEDICINE_OVERLAYS: RefCell<StableBTreeMap<String, MedicineOverlay, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(MEDICINE_OVERLAYS_MEMORY_ID)));
+    // Formulary external code -> medicine id
+    static FORMULARY_CODES: RefCell<StableBTreeMap<String, String, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(FORMULARY_CODES_MEMORY_ID)));
+    // Medicine search: "token|medicine_id" -> field bitmask, and token -> number of medicines
+    static MEDICINE_SEARCH_INDEX: RefCell<StableBTreeMap<String, u8, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(MEDICINE_SEARCH_INDEX_MEMORY_ID)));
+    static MEDICINE_SEARCH_VOCABULARY: RefCell<StableBTreeMap<String, u32, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(MEDICINE_SEARCH_VOCABULARY_MEMORY_ID)));
+    // Keyed by "medicine_id|revision" with the revision zero-padded, so a
+    // medicine's history is one ordered range
+    static MEDICINE_REVISIONS: RefCell<StableBTreeMap<String, MedicineRevision, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(MEDICINE_REVISIONS_MEMORY_ID)));
+    // Keyed by "patient_id|doctor_id", see `consent_key`
+    static PROFILE_CONSENTS: RefCell<StableBTreeMap<String, ProfileConsent, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(PROFILE_CONSENTS_MEMORY_ID)));
+    // Keyed by "condition|ingredient", both normalized
+    static CONDITION_CONTRAINDICATIONS: RefCell<StableBTreeMap<String, ConditionContraindication, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(CONDITION_CONTRAINDICATIONS_MEMORY_ID)));
+
+    // Adherence: schedules by id, logs keyed "schedule_id|dose_index" and
+    // reminders keyed "patient_principal|reminder_id"
+    static DOSE_SCHEDULES: RefCell<StableBTreeMap<String, DoseSchedule, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(DOSE_SCHEDULES_MEMORY_ID)));
+    static DOSE_LOGS: RefCell<StableBTreeMap<String, DoseLog, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(DOSE_LOGS_MEMORY_ID)));
+    static DOSE_REMINDERS: RefCell<StableBTreeMap<String, DoseReminder, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(DOSE_REMINDERS_MEMORY_ID)));
+    // Keyed by "prescription_id|entry_id" with the id zero-padded, so one
+    // prescription's trail is an ordered range. Entries are never removed.
+    static PRESCRIPTION_ACCESS_LOG: RefCell<StableBTreeMap<String, PrescriptionAccessEntry, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(PRESCRIPTION_ACCESS_LOG_MEMORY_ID)));
+    // Keyed by "prescription_id|principal", see `share_key`
+    static PRESCRIPTION_SHARES: RefCell<StableBTreeMap<String, PrescriptionShare, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(PRESCRIPTION_SHARES_MEMORY_ID)));
+    static SYSTEM_SETTINGS: RefCell<StableCell<SystemSettings, Memory>> = RefCell::new(
+        StableCell::init(memory(SYSTEM_SETTINGS_MEMORY_ID), SystemSettings::default())
+            .expect("failed to initialize system settings")
+    );
 
     // Patient Case storage
-    static PATIENT_CASES: RefCell<HashMap<String, PatientCase>> = RefCell::new(HashMap::new());
+    static PATIENT_CASES: RefCell<StableBTreeMap<String, PatientCase, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(PATIENT_CASES_MEMORY_ID)));
     
     // Contribution storage
-    static CONTRIBUTION_POOLS: RefCell<HashMap<String, ContributionPool>> = RefCell::new(HashMap::new());
-    static CONTRIBUTIONS: RefCell<HashMap<String, Contribution>> = RefCell::new(HashMap::new());
+    static CONTRIBUTION_POOLS: RefCell<StableBTreeMap<String, ContributionPool, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(CONTRIBUTION_POOLS_MEMORY_ID)));
+    static CONTRIBUTIONS: RefCell<StableBTreeMap<String, Contribution, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(CONTRIBUTIONS_MEMORY_ID)));
+    static DISBURSEMENTS: RefCell<StableBTreeMap<String, Disbursement, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(DISBURSEMENTS_MEMORY_ID)));
+
+    // Schema bookkeeping. Canisters that predate this cell start at the
+    // legacy version so their first upgrade runs the migrations.
+    static SCHEMA_VERSION: RefCell<StableCell<u32, Memory>> = RefCell::new(
+        StableCell::init(memory(SCHEMA_VERSION_MEMORY_ID), migrations::LEGACY_SCHEMA_VERSION)
+            .expect("failed to initialize schema version")
+    );
+    static MIGRATION_HISTORY: RefCell<StableBTreeMap<u64, MigrationRecord, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(MIGRATION_HISTORY_MEMORY_ID)));
+}
+
+fn memory(id: MemoryId) -> Memory {
+    MEMORY_MANAGER.with(|manager| manager.borrow().get(id))
 }
 
 // User storage functions
 pub fn store_user(user: User) {
-    USERS.with(|users| {
-        users.borrow_mut().insert(user.id.clone(), user.clone());
-    });
-    
     PRINCIPAL_TO_USER.with(|mapping| {
         mapping.borrow_mut().insert(user.user_principal.clone(), user.id.clone());
     });
+
+    USERS.with(|users| {
+        users.borrow_mut().insert(user.id.clone(), user);
+    });
 }
 
 pub fn get_user(user_id: &str) -> Option<User> {
     USERS.with(|users| {
-        users.borrow().get(user_id).cloned()
+        users.borrow().get(&user_id.to_string())
     })
 }
 
 pub fn get_user_by_principal(principal: &str) -> Option<User> {
     PRINCIPAL_TO_USER.with(|mapping| {
-        mapping.borrow().get(principal).and_then(|user_id| {
+        mapping.borrow().get(&principal.to_string()).and_then(|user_id| {
             USERS.with(|users| {
-                users.borrow().get(user_id).cloned()
+                users.borrow().get(&user_id)
             })
         })
     })
@@ -57,7 +262,7 @@ pub fn get_user_by_principal(principal: &str) -> Option<User> {
 
 pub fn principal_has_account(principal: &str) -> bool {
     PRINCIPAL_TO_USER.with(|mapping| {
-        mapping.borrow().contains_key(principal)
+        mapping.borrow().contains_key(&principal.to_string())
     })
 }
 
@@ -69,11 +274,11 @@ pub fn list_user_principals() -> Vec<crate::shared::types::PrincipalEntry> {
             
             principal_map.iter()
                 .filter_map(|(principal, user_id)| {
-                    users_map.get(user_id).map(|user| {
+                    users_map.get(&user_id).map(|user| {
                         crate::shared::types::PrincipalEntry {
-                            principal_ent: principal.clone(),
-                            user_id: user_id.clone(),
-                            email: user.email.clone(),
+                            principal_ent: principal,
+                            user_id,
+                            email: user.email,
                         }
                     })
                 })
@@ -83,48 +288,282 @@ pub fn list_user_principals() -> Vec<crate::shared::types::PrincipalEntry> {
 }
 
 // Medicine storage functions
-pub fn store_medicine(medicine: Medicine) {
-    MEDICINES.with(|medicines| {
-        medicines.borrow_mut().insert(medicine.id.clone(), medicine);
+
+/// Stores a medicine as its next revision and returns it as stored.
+pub fn store_medicine(medicine: Medicine) -> Medicine {
+    let medicine = record_medicine_revision(medicine);
+    let previous = MEDICINES.with(|medicines| {
+        medicines.borrow_mut().insert(medicine.id.clone(), medicine.clone())
     });
+    if let Some(previous) = previous {
+        unindex_medicine(&previous);
+    }
+    index_medicine(&medicine);
+    medicine
 }
 
 pub fn get_medicine(medicine_id: &str) -> Option<Medicine> {
     MEDICINES.with(|medicines| {
-        medicines.borrow().get(medicine_id).cloned()
+        medicines.borrow().get(&medicine_id.to_string())
     })
 }
 
 pub fn get_all_medicines() -> Vec<Medicine> {
     MEDICINES.with(|medicines| {
-        medicines.borrow().values().cloned().collect()
+        medicines.borrow().iter().map(|(_, medicine)| medicine).collect()
     })
 }
 
 pub fn get_doctor_medicines(doctor_id: &str) -> Vec<Medicine> {
     MEDICINES.with(|medicines| {
         medicines.borrow()
-            .values()
+            .iter()
+            .map(|(_, medicine)| medicine)
             .filter(|medicine| medicine.doctor_id == doctor_id)
-            .cloned()
             .collect()
     })
 }
 
-// Update an existing medicine (returns true if updated, false if not found)
-pub fn update_medicine_in_storage(medicine_id: &str, updated: Medicine) -> bool {
-    MEDICINES.with(|meds| {
-        let mut map = meds.borrow_mut();
-        if map.contains_key(medicine_id) {
-            // Ensure key consistency
-            map.insert(medicine_id.to_string(), Medicine { id: medicine_id.to_string(), ..updated });
-            true
-        } else {
-            false
+pub fn get_formulary_medicines() -> Vec<Medicine> {
+    MEDICINES.with(|medicines| {
+        medicines.borrow()
+            .iter()
+            .map(|(_, medicine)| medicine)
+            .filter(|medicine| medicine.formulary.is_some())
+            .collect()
+    })
+}
+
+pub fn get_medicine_id_by_external_code(external_code: &str) -> Option<String> {
+    FORMULARY_CODES.with(|codes| codes.borrow().get(&external_code.to_string()))
+}
+
+pub fn set_external_code(external_code: &str, medicine_id: &str) {
+    FORMULARY_CODES.with(|codes| {
+        codes.borrow_mut().insert(external_code.to_string(), medicine_id.to_string());
+    });
+}
+
+pub fn remove_external_code(external_code: &str) {
+    FORMULARY_CODES.with(|codes| {
+        codes.borrow_mut().remove(&external_code.to_string());
+    });
+}
+
+fn overlay_key(doctor_id: &str, medicine_id: &str) -> String {
+    format!("{}|{}", doctor_id, medicine_id)
+}
+
+pub fn store_medicine_overlay(overlay: MedicineOverlay) {
+    let key = overlay_key(&overlay.doctor_id, &overlay.medicine_id);
+    MEDICINE_OVERLAYS.with(|overlays| {
+        overlays.borrow_mut().insert(key, overlay);
+    });
+}
+
+pub fn get_medicine_overlay(doctor_id: &str, medicine_id: &str) -> Option<MedicineOverlay> {
+    MEDICINE_OVERLAYS.with(|overlays| {
+        overlays.borrow().get(&overlay_key(doctor_id, medicine_id))
+    })
+}
+
+pub fn remove_medicine_overlay(doctor_id: &str, medicine_id: &str) -> bool {
+    MEDICINE_OVERLAYS.with(|overlays| {
+        overlays.borrow_mut().remove(&overlay_key(doctor_id, medicine_id)).is_some()
+    })
+}
+
+// Update an existing medicine as a new revision (returns the stored record, or None if not found)
+pub fn update_medicine_in_storage(medicine_id: &str, updated: Medicine) -> Option<Medicine> {
+    let key = medicine_id.to_string();
+    if !MEDICINES.with(|meds| meds.borrow().contains_key(&key)) {
+        return None;
+    }
+
+    // Ensure key consistency
+    let updated = record_medicine_revision(Medicine { id: key.clone(), ..updated });
+    let previous = MEDICINES.with(|meds| meds.borrow_mut().insert(key, updated.clone()));
+    if let Some(previous) = previous {
+        unindex_medicine(&previous);
+    }
+    index_medicine(&updated);
+    Some(updated)
+}
+
+// Medicine revision history
+
+fn revision_key(medicine_id: &str, revision: u32) -> String {
+    format!("{}|{:010}", medicine_id, revision)
+}
+
+fn latest_revision_number(medicine_id: &str) -> u32 {
+    let prefix = format!("{}|", medicine_id);
+    MEDICINE_REVISIONS.with(|revisions| {
+        revisions.borrow()
+            .range(prefix.clone()..)
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .map(|(_, revision)| revision.revision)
+            .last()
+            .unwrap_or(0)
+    })
+}
+
+fn insert_revision(medicine: &Medicine, revision: u32, recorded_at: u64, edited_by: Option<String>) {
+    MEDICINE_REVISIONS.with(|revisions| {
+        revisions.borrow_mut().insert(
+            revision_key(&medicine.id, revision),
+            MedicineRevision {
+                medicine_id: medicine.id.clone(),
+                revision,
+                medicine: medicine.clone(),
+                recorded_at,
+                edited_by,
+            },
+        );
+    });
+}
+
+// Stamps the next revision number on `medicine` and snapshots it
+fn record_medicine_revision(mut medicine: Medicine) -> Medicine {
+    let revision = latest_revision_number(&medicine.id) + 1;
+    medicine.current_revision = Some(revision);
+    insert_revision(
+        &medicine,
+        revision,
+        utils::get_current_timestamp(),
+        Some(ic_cdk::api::caller().to_string()),
+    );
+    medicine
+}
+
+pub fn get_medicine_revision(medicine_id: &str, revision: u32) -> Option<MedicineRevision> {
+    MEDICINE_REVISIONS.with(|revisions| revisions.borrow().get(&revision_key(medicine_id, revision)))
+}
+
+/// A medicine's revisions, oldest first.
+pub fn get_medicine_revisions(medicine_id: &str) -> Vec<MedicineRevision> {
+    let prefix = format!("{}|", medicine_id);
+    MEDICINE_REVISIONS.with(|revisions| {
+        revisions.borrow()
+            .range(prefix.clone()..)
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .map(|(_, revision)| revision)
+            .collect()
+    })
+}
+
+/// The medicine as the prescription was issued with it, falling back to the
+/// current record for items issued before revisions were pinned.
+pub fn get_prescribed_medicine(item: &PrescriptionMedicine) -> Option<Medicine> {
+    item.medicine_revision
+        .and_then(|revision| get_medicine_revision(&item.medicine_id, revision))
+        .map(|revision| revision.medicine)
+        .or_else(|| get_medicine(&item.medicine_id))
+}
+
+/// Gives medicines stored before revision history existed their first
+/// revision. Runs in `post_upgrade`; a no-op once every medicine has one.
+pub fn ensure_medicine_revisions() {
+    for mut medicine in get_all_medicines() {
+        if medicine.current_revision.is_some() {
+            continue;
+        }
+        medicine.current_revision = Some(1);
+        insert_revision(&medicine, 1, medicine.created_at, None);
+        MEDICINES.with(|medicines| {
+            medicines.borrow_mut().insert(medicine.id.clone(), medicine);
+        });
+    }
+}
+
+/// Formulary entries created before external codes were indexed have no
+/// code mapping and export under their medicine id; map them so importing
+/// that export updates them instead of creating duplicates.
+pub fn ensure_formulary_codes() {
+    for medicine in get_all_medicines() {
+        let Some(details) = medicine.formulary.as_ref() else {
+            continue;
+        };
+        let code = details.external_code.clone().unwrap_or_else(|| medicine.id.clone());
+        if get_medicine_id_by_external_code(&code).is_none() {
+            set_external_code(&code, &medicine.id);
+        }
+    }
+}
+
+// Medicine search index, kept in step by `store_medicine` and
+// `update_medicine_in_storage`
+
+fn search_key(token: &str, medicine_id: &str) -> String {
+    format!("{}|{}", token, medicine_id)
+}
+
+fn index_medicine(medicine: &Medicine) {
+    for (token, fields) in search::medicine_tokens(medicine) {
+        let is_new = MEDICINE_SEARCH_INDEX.with(|index| {
+            index.borrow_mut().insert(search_key(&token, &medicine.id), fields).is_none()
+        });
+        if is_new {
+            MEDICINE_SEARCH_VOCABULARY.with(|vocabulary| {
+                let mut vocabulary = vocabulary.borrow_mut();
+                let count = vocabulary.get(&token).unwrap_or(0);
+                vocabulary.insert(token, count + 1);
+            });
+        }
+    }
+}
+
+fn unindex_medicine(medicine: &Medicine) {
+    for (token, _) in search::medicine_tokens(medicine) {
+        let removed = MEDICINE_SEARCH_INDEX.with(|index| {
+            index.borrow_mut().remove(&search_key(&token, &medicine.id)).is_some()
+        });
+        if removed {
+            MEDICINE_SEARCH_VOCABULARY.with(|vocabulary| {
+                let mut vocabulary = vocabulary.borrow_mut();
+                match vocabulary.get(&token).unwrap_or(0) {
+                    0 | 1 => { vocabulary.remove(&token); }
+                    count => { vocabulary.insert(token, count - 1); }
+                }
+            });
         }
+    }
+}
+
+/// Index entries whose token starts with `prefix`, as (token, medicine id, fields).
+pub fn search_index_with_prefix(prefix: &str) -> Vec<(String, String, u8)> {
+    MEDICINE_SEARCH_INDEX.with(|index| {
+        index.borrow()
+            .range(prefix.to_string()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .filter_map(|(key, fields)| {
+                key.split_once('|').map(|(token, id)| (token.to_string(), id.to_string(), fields))
+            })
+            .collect()
     })
 }
 
+pub fn search_vocabulary_with_prefix(prefix: &str) -> Vec<String> {
+    MEDICINE_SEARCH_VOCABULARY.with(|vocabulary| {
+        vocabulary.borrow()
+            .range(prefix.to_string()..)
+            .take_while(|(token, _)| token.starts_with(prefix))
+            .map(|(token, _)| token)
+            .collect()
+    })
+}
+
+/// Indexes medicines stored before the search index existed. Runs in
+/// `post_upgrade`; a no-op once the index is populated.
+pub fn ensure_medicine_search_index() {
+    let index_empty = MEDICINE_SEARCH_INDEX.with(|index| index.borrow().is_empty());
+    if index_empty {
+        for medicine in get_all_medicines() {
+            index_medicine(&medicine);
+        }
+    }
+}
+
 // Prescription storage functions
 pub fn store_prescription(prescription: Prescription) {
     let prescription_id = prescription.id.clone();
@@ -139,11 +578,17 @@ pub fn store_prescription(prescription: Prescription) {
     });
 }
 
+pub fn prescription_code_exists(code: &str) -> bool {
+    PRESCRIPTION_CODES.with(|codes| {
+        codes.borrow().contains_key(&code.to_string())
+    })
+}
+
 pub fn get_prescription_by_code(code: &str) -> Option<Prescription> {
     PRESCRIPTION_CODES.with(|codes| {
-        codes.borrow().get(code).and_then(|prescription_id| {
+        codes.borrow().get(&code.to_string()).and_then(|prescription_id| {
             PRESCRIPTIONS.with(|prescriptions| {
-                prescriptions.borrow().get(prescription_id).cloned()
+                prescriptions.borrow().get(&prescription_id)
             })
         })
     })
@@ -151,29 +596,96 @@ pub fn get_prescription_by_code(code: &str) -> Option<Prescription> {
 
 pub fn get_prescription(prescription_id: &str) -> Option<Prescription> {
     PRESCRIPTIONS.with(|prescriptions| {
-        prescriptions.borrow().get(prescription_id).cloned()
+        prescriptions.borrow().get(&prescription_id.to_string())
     })
 }
 
 pub fn get_doctor_prescriptions(doctor_id: &str) -> Vec<Prescription> {
     PRESCRIPTIONS.with(|prescriptions| {
         prescriptions.borrow()
-            .values()
+            .iter()
+            .map(|(_, prescription)| prescription)
             .filter(|prescription| prescription.doctor_id == doctor_id)
-            .cloned()
             .collect()
     })
 }
 
-pub fn update_prescription_access(prescription_id: &str, accessed_at: u64, patient_principal: Option<String>) -> bool {
+pub fn update_prescription(prescription: Prescription) -> bool {
     PRESCRIPTIONS.with(|prescriptions| {
         let mut prescriptions_map = prescriptions.borrow_mut();
-        if let Some(mut prescription) = prescriptions_map.get(prescription_id).cloned() {
+        if prescriptions_map.contains_key(&prescription.id) {
+            prescriptions_map.insert(prescription.id.clone(), prescription);
+            true
+        } else {
+            false
+        }
+    })
+}
+
+/// Moves a prescription to a new code; the old code stops resolving.
+pub fn replace_prescription_code(old_code: &str, prescription: Prescription) {
+    PRESCRIPTION_CODES.with(|codes| {
+        let mut codes = codes.borrow_mut();
+        codes.remove(&old_code.to_string());
+        codes.insert(prescription.prescription_code.clone(), prescription.id.clone());
+    });
+
+    PRESCRIPTIONS.with(|prescriptions| {
+        prescriptions.borrow_mut().insert(prescription.id.clone(), prescription);
+    });
+}
+
+fn share_key(prescription_id: &str, principal: &str) -> String {
+    format!("{}|{}", prescription_id, principal)
+}
+
+pub fn store_prescription_share(share: PrescriptionShare) {
+    let key = share_key(&share.prescription_id, &share.principal);
+    PRESCRIPTION_SHARES.with(|shares| {
+        shares.borrow_mut().insert(key, share);
+    });
+}
+
+pub fn remove_prescription_share(prescription_id: &str, principal: &str) -> bool {
+    PRESCRIPTION_SHARES.with(|shares| {
+        shares.borrow_mut().remove(&share_key(prescription_id, principal)).is_some()
+    })
+}
+
+pub fn has_prescription_share(prescription_id: &str, principal: &str) -> bool {
+    PRESCRIPTION_SHARES.with(|shares| {
+        shares.borrow().contains_key(&share_key(prescription_id, principal))
+    })
+}
+
+pub fn get_prescription_shares(prescription_id: &str) -> Vec<PrescriptionShare> {
+    let prefix = format!("{}|", prescription_id);
+    PRESCRIPTION_SHARES.with(|shares| {
+        shares.borrow()
+            .range(prefix.clone()..)
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .map(|(_, share)| share)
+            .collect()
+    })
+}
+
+pub fn get_prescriptions_by_status(status: PrescriptionStatus) -> Vec<Prescription> {
+    PRESCRIPTIONS.with(|prescriptions| {
+        prescriptions.borrow()
+            .iter()
+            .map(|(_, prescription)| prescription)
+            .filter(|prescription| prescription.status == status)
+            .collect()
+    })
+}
+
+pub fn update_prescription_access(prescription_id: &str, accessed_at: u64) -> bool {
+    PRESCRIPTIONS.with(|prescriptions| {
+        let mut prescriptions_map = prescriptions.borrow_mut();
+        let key = prescription_id.to_string();
+        if let Some(mut prescription) = prescriptions_map.get(&key) {
             prescription.accessed_at = Some(accessed_at);
-            if let Some(principal) = patient_principal {
-                prescription.patient_principal = Some(principal);
-            }
-            prescriptions_map.insert(prescription_id.to_string(), prescription);
+            prescriptions_map.insert(key, prescription);
             true
         } else {
             false
@@ -181,31 +693,362 @@ pub fn update_prescription_access(prescription_id: &str, accessed_at: u64, patie
     })
 }
 
+/// Appends an access attempt by the caller to the prescription's trail.
+pub fn record_prescription_access(prescription_id: &str, endpoint: &str, outcome: AccessOutcome) -> u64 {
+    PRESCRIPTION_ACCESS_LOG.with(|log| {
+        let mut log = log.borrow_mut();
+        let id = log.len();
+        log.insert(format!("{}|{:020}", prescription_id, id), PrescriptionAccessEntry {
+            id,
+            prescription_id: prescription_id.to_string(),
+            principal: ic_cdk::api::caller().to_string(),
+            endpoint: endpoint.to_string(),
+            outcome,
+            timestamp: utils::get_current_timestamp(),
+        });
+        id
+    })
+}
+
+pub fn get_prescription_access_log(prescription_id: &str) -> Vec<PrescriptionAccessEntry> {
+    let prefix = format!("{}|", prescription_id);
+    PRESCRIPTION_ACCESS_LOG.with(|log| {
+        log.borrow()
+            .range(prefix.clone()..)
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .map(|(_, entry)| entry)
+            .collect()
+    })
+}
+
 // Admin functions
 pub fn admin_exists() -> bool {
-    ADMIN_EXISTS.with(|exists| *exists.borrow())
+    ADMIN_EXISTS.with(|exists| *exists.borrow().get())
 }
 
 pub fn set_admin_exists(exists: bool) {
     ADMIN_EXISTS.with(|admin_flag| {
-        *admin_flag.borrow_mut() = exists;
+        admin_flag.borrow_mut().set(exists).expect("failed to persist admin flag");
+    });
+}
+
+pub fn store_admin_permissions(permissions: AdminPermissions) {
+    ADMIN_PERMISSIONS.with(|map| {
+        map.borrow_mut().insert(permissions.admin_id.clone(), permissions);
     });
 }
 
+pub fn get_admin_permissions(admin_id: &str) -> Option<AdminPermissions> {
+    ADMIN_PERMISSIONS.with(|map| {
+        map.borrow().get(&admin_id.to_string())
+    })
+}
+
+pub fn admin_has_permission(admin_id: &str, permission: &AdminPermission) -> bool {
+    get_admin_permissions(admin_id)
+        .map(|entry| entry.permissions.contains(permission))
+        .unwrap_or(false)
+}
+
+// Dispensing functions
+pub fn store_dispensing_record(record: DispensingRecord) {
+    DISPENSING_RECORDS.with(|records| {
+        records.borrow_mut().insert(record.id.clone(), record);
+    });
+}
+
+pub fn get_dispensing_records_by_prescription(prescription_id: &str) -> Vec<DispensingRecord> {
+    let mut records: Vec<DispensingRecord> = DISPENSING_RECORDS.with(|records| {
+        records.borrow()
+            .iter()
+            .map(|(_, record)| record)
+            .filter(|record| record.prescription_id == prescription_id)
+            .collect()
+    });
+    records.sort_by_key(|record| record.dispensed_at);
+    records
+}
+
+pub fn get_dispensing_records_by_pharmacy(pharmacy_id: &str) -> Vec<DispensingRecord> {
+    DISPENSING_RECORDS.with(|records| {
+        records.borrow()
+            .iter()
+            .map(|(_, record)| record)
+            .filter(|record| record.pharmacy_id == pharmacy_id)
+            .collect()
+    })
+}
+
+// Drug interaction functions
+pub fn normalize_ingredient(ingredient: &str) -> String {
+    ingredient.trim().to_lowercase()
+}
+
+// Order-independent key so (a, b) and (b, a) find the same entry
+fn interaction_key(ingredient_a: &str, ingredient_b: &str) -> String {
+    let a = normalize_ingredient(ingredient_a);
+    let b = normalize_ingredient(ingredient_b);
+    if a <= b {
+        format!("{}|{}", a, b)
+    } else {
+        format!("{}|{}", b, a)
+    }
+}
+
+pub fn store_drug_interaction(interaction: DrugInteraction) {
+    let key = interaction_key(&interaction.ingredient_a, &interaction.ingredient_b);
+    DRUG_INTERACTIONS.with(|interactions| {
+        interactions.borrow_mut().insert(key, interaction);
+    });
+}
+
+pub fn get_drug_interaction(ingredient_a: &str, ingredient_b: &str) -> Option<DrugInteraction> {
+    DRUG_INTERACTIONS.with(|interactions| {
+        interactions.borrow().get(&interaction_key(ingredient_a, ingredient_b))
+    })
+}
+
+pub fn remove_drug_interaction(ingredient_a: &str, ingredient_b: &str) -> bool {
+    DRUG_INTERACTIONS.with(|interactions| {
+        interactions.borrow_mut().remove(&interaction_key(ingredient_a, ingredient_b)).is_some()
+    })
+}
+
+pub fn get_all_drug_interactions() -> Vec<DrugInteraction> {
+    DRUG_INTERACTIONS.with(|interactions| {
+        interactions.borrow().iter().map(|(_, interaction)| interaction).collect()
+    })
+}
+
+// Health profile consent functions
+fn consent_key(patient_id: &str, doctor_id: &str) -> String {
+    format!("{}|{}", patient_id, doctor_id)
+}
+
+pub fn store_profile_consent(consent: ProfileConsent) {
+    let key = consent_key(&consent.patient_id, &consent.doctor_id);
+    PROFILE_CONSENTS.with(|consents| {
+        consents.borrow_mut().insert(key, consent);
+    });
+}
+
+pub fn get_profile_consent(patient_id: &str, doctor_id: &str) -> Option<ProfileConsent> {
+    PROFILE_CONSENTS.with(|consents| {
+        consents.borrow().get(&consent_key(patient_id, doctor_id))
+    })
+}
+
+pub fn remove_profile_consent(patient_id: &str, doctor_id: &str) -> bool {
+    PROFILE_CONSENTS.with(|consents| {
+        consents.borrow_mut().remove(&consent_key(patient_id, doctor_id)).is_some()
+    })
+}
+
+pub fn get_patient_profile_consents(patient_id: &str) -> Vec<ProfileConsent> {
+    let prefix = format!("{}|", patient_id);
+    PROFILE_CONSENTS.with(|consents| {
+        consents.borrow()
+            .range(prefix.clone()..)
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .map(|(_, consent)| consent)
+            .collect()
+    })
+}
+
+pub fn get_doctor_profile_consents(doctor_id: &str) -> Vec<ProfileConsent> {
+    PROFILE_CONSENTS.with(|consents| {
+        consents.borrow()
+            .iter()
+            .map(|(_, consent)| consent)
+            .filter(|consent| consent.doctor_id == doctor_id)
+            .collect()
+    })
+}
+
+// Condition contraindication functions
+fn contraindication_key(condition: &str, ingredient: &str) -> String {
+    format!("{}|{}", normalize_ingredient(condition), normalize_ingredient(ingredient))
+}
+
+pub fn store_condition_contraindication(entry: ConditionContraindication) {
+    let key = contraindication_key(&entry.condition, &entry.ingredient);
+    CONDITION_CONTRAINDICATIONS.with(|entries| {
+        entries.borrow_mut().insert(key, entry);
+    });
+}
+
+pub fn get_condition_contraindication(condition: &str, ingredient: &str) -> Option<ConditionContraindication> {
+    CONDITION_CONTRAINDICATIONS.with(|entries| {
+        entries.borrow().get(&contraindication_key(condition, ingredient))
+    })
+}
+
+pub fn remove_condition_contraindication(condition: &str, ingredient: &str) -> bool {
+    CONDITION_CONTRAINDICATIONS.with(|entries| {
+        entries.borrow_mut().remove(&contraindication_key(condition, ingredient)).is_some()
+    })
+}
+
+pub fn get_all_condition_contraindications() -> Vec<ConditionContraindication> {
+    CONDITION_CONTRAINDICATIONS.with(|entries| {
+        entries.borrow().iter().map(|(_, entry)| entry).collect()
+    })
+}
+
+// Adherence functions
+pub fn store_dose_schedule(schedule: DoseSchedule) {
+    DOSE_SCHEDULES.with(|schedules| {
+        schedules.borrow_mut().insert(schedule.id.clone(), schedule);
+    });
+}
+
+pub fn get_dose_schedule(schedule_id: &str) -> Option<DoseSchedule> {
+    DOSE_SCHEDULES.with(|schedules| schedules.borrow().get(&schedule_id.to_string()))
+}
+
+pub fn get_patient_dose_schedules(patient_principal: &str) -> Vec<DoseSchedule> {
+    DOSE_SCHEDULES.with(|schedules| {
+        schedules.borrow()
+            .iter()
+            .map(|(_, schedule)| schedule)
+            .filter(|schedule| schedule.patient_principal == patient_principal)
+            .collect()
+    })
+}
+
+pub fn get_prescription_dose_schedules(prescription_id: &str) -> Vec<DoseSchedule> {
+    DOSE_SCHEDULES.with(|schedules| {
+        schedules.borrow()
+            .iter()
+            .map(|(_, schedule)| schedule)
+            .filter(|schedule| schedule.prescription_id == prescription_id)
+            .collect()
+    })
+}
+
+pub fn get_active_dose_schedules() -> Vec<DoseSchedule> {
+    DOSE_SCHEDULES.with(|schedules| {
+        schedules.borrow()
+            .iter()
+            .map(|(_, schedule)| schedule)
+            .filter(|schedule| schedule.active)
+            .collect()
+    })
+}
+
+fn dose_log_key(schedule_id: &str, dose_index: u64) -> String {
+    format!("{}|{:020}", schedule_id, dose_index)
+}
+
+pub fn store_dose_log(log: DoseLog) {
+    let key = dose_log_key(&log.schedule_id, log.dose_index);
+    DOSE_LOGS.with(|logs| {
+        logs.borrow_mut().insert(key, log);
+    });
+}
+
+pub fn get_dose_logs(schedule_id: &str) -> Vec<DoseLog> {
+    let prefix = format!("{}|", schedule_id);
+    DOSE_LOGS.with(|logs| {
+        logs.borrow()
+            .range(prefix.clone()..)
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .map(|(_, log)| log)
+            .collect()
+    })
+}
+
+fn reminder_key(patient_principal: &str, reminder_id: &str) -> String {
+    format!("{}|{}", patient_principal, reminder_id)
+}
+
+pub fn store_dose_reminder(reminder: DoseReminder) {
+    let key = reminder_key(&reminder.patient_principal, &reminder.id);
+    DOSE_REMINDERS.with(|reminders| {
+        reminders.borrow_mut().insert(key, reminder);
+    });
+}
+
+pub fn get_dose_reminder(patient_principal: &str, reminder_id: &str) -> Option<DoseReminder> {
+    DOSE_REMINDERS.with(|reminders| {
+        reminders.borrow().get(&reminder_key(patient_principal, reminder_id))
+    })
+}
+
+pub fn remove_dose_reminder(patient_principal: &str, reminder_id: &str) -> bool {
+    DOSE_REMINDERS.with(|reminders| {
+        reminders.borrow_mut().remove(&reminder_key(patient_principal, reminder_id)).is_some()
+    })
+}
+
+pub fn get_dose_reminders(patient_principal: &str) -> Vec<DoseReminder> {
+    let prefix = format!("{}|", patient_principal);
+    DOSE_REMINDERS.with(|reminders| {
+        reminders.borrow()
+            .range(prefix.clone()..)
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .map(|(_, reminder)| reminder)
+            .collect()
+    })
+}
+
+// System settings functions
+pub fn get_system_settings() -> SystemSettings {
+    SYSTEM_SETTINGS.with(|settings| settings.borrow().get().clone())
+}
+
+pub fn set_system_settings(settings: SystemSettings) {
+    SYSTEM_SETTINGS.with(|cell| {
+        cell.borrow_mut().set(settings).expect("failed to persist system settings");
+    });
+}
+
+// Audit log functions (append-only)
+pub fn append_audit_entry(actor_principal: String, action: &str, target: String, details: String, timestamp: u64) -> u64 {
+    AUDIT_LOG.with(|log| {
+        let mut log = log.borrow_mut();
+        let id = log.len();
+        log.insert(id, AuditEntry {
+            id,
+            actor_principal,
+            action: action.to_string(),
+            target,
+            details,
+            timestamp,
+        });
+        id
+    })
+}
+
+pub fn get_audit_log() -> Vec<AuditEntry> {
+    AUDIT_LOG.with(|log| {
+        log.borrow().iter().map(|(_, entry)| entry).collect()
+    })
+}
+
+pub fn get_all_admins() -> Vec<User> {
+    USERS.with(|users| {
+        users.borrow().iter()
+            .map(|(_, user)| user)
+            .filter(|user| matches!(user.role, UserRole::Admin))
+            .collect()
+    })
+}
+
 pub fn get_all_doctors() -> Vec<User> {
     USERS.with(|users| {
-        users.borrow().values()
+        users.borrow().iter()
+            .map(|(_, user)| user)
             .filter(|user| matches!(user.role, UserRole::Doctor))
-            .cloned()
             .collect()
     })
 }
 
 pub fn get_all_patients() -> Vec<User> {
     USERS.with(|users| {
-        users.borrow().values()
+        users.borrow().iter()
+            .map(|(_, user)| user)
             .filter(|user| matches!(user.role, UserRole::Patient))
-            .cloned()
             .collect()
     })
 }
@@ -213,9 +1056,10 @@ pub fn get_all_patients() -> Vec<User> {
 pub fn update_user_activity(user_id: &str, timestamp: u64) -> bool {
     USERS.with(|users| {
         let mut users_map = users.borrow_mut();
-        if let Some(mut user) = users_map.get(user_id).cloned() {
+        let key = user_id.to_string();
+        if let Some(mut user) = users_map.get(&key) {
             user.last_active = Some(timestamp);
-            users_map.insert(user_id.to_string(), user);
+            users_map.insert(key, user);
             true
         } else {
             false
@@ -226,10 +1070,11 @@ pub fn update_user_activity(user_id: &str, timestamp: u64) -> bool {
 pub fn update_user_stats(user_id: &str, prescription_count: u64, medicine_count: u64) -> bool {
     USERS.with(|users| {
         let mut users_map = users.borrow_mut();
-        if let Some(mut user) = users_map.get(user_id).cloned() {
+        let key = user_id.to_string();
+        if let Some(mut user) = users_map.get(&key) {
             user.total_prescriptions = prescription_count;
             user.total_medicines = medicine_count;
-            users_map.insert(user_id.to_string(), user);
+            users_map.insert(key, user);
             true
         } else {
             false
@@ -246,21 +1091,21 @@ pub fn store_verification_request(request: VerificationRequest) {
 
 pub fn get_verification_request(request_id: &str) -> Option<VerificationRequest> {
     VERIFICATION_REQUESTS.with(|requests| {
-        requests.borrow().get(request_id).cloned()
+        requests.borrow().get(&request_id.to_string())
     })
 }
 
 pub fn get_all_verification_requests() -> Vec<VerificationRequest> {
     VERIFICATION_REQUESTS.with(|requests| {
-        requests.borrow().values().cloned().collect()
+        requests.borrow().iter().map(|(_, req)| req).collect()
     })
 }
 
 pub fn get_pending_verification_requests() -> Vec<VerificationRequest> {
     VERIFICATION_REQUESTS.with(|requests| {
-        requests.borrow().values()
+        requests.borrow().iter()
+            .map(|(_, req)| req)
             .filter(|req| matches!(req.status, VerificationStatus::Pending))
-            .cloned()
             .collect()
     })
 }
@@ -268,8 +1113,9 @@ pub fn get_pending_verification_requests() -> Vec<VerificationRequest> {
 pub fn update_verification_request(request_id: &str, updated_request: VerificationRequest) -> bool {
     VERIFICATION_REQUESTS.with(|requests| {
         let mut requests_map = requests.borrow_mut();
-        if requests_map.contains_key(request_id) {
-            requests_map.insert(request_id.to_string(), updated_request);
+        let key = request_id.to_string();
+        if requests_map.contains_key(&key) {
+            requests_map.insert(key, updated_request);
             true
         } else {
             false
@@ -280,9 +1126,10 @@ pub fn update_verification_request(request_id: &str, updated_request: Verificati
 pub fn update_user_verification_status(user_id: &str, status: VerificationStatus) -> bool {
     USERS.with(|users| {
         let mut users_map = users.borrow_mut();
-        if let Some(mut user) = users_map.get(user_id).cloned() {
+        let key = user_id.to_string();
+        if let Some(mut user) = users_map.get(&key) {
             user.verification_status = status;
-            users_map.insert(user_id.to_string(), user);
+            users_map.insert(key, user);
             true
         } else {
             false
@@ -299,7 +1146,7 @@ pub fn store_patient_case(case: PatientCase) {
 
 pub fn get_patient_case(case_id: &str) -> Option<PatientCase> {
     PATIENT_CASES.with(|cases| {
-        cases.borrow().get(case_id).cloned()
+        cases.borrow().get(&case_id.to_string())
     })
 }
 
@@ -311,16 +1158,16 @@ pub fn update_patient_case(case_id: &str, case: PatientCase) {
 
 pub fn get_all_patient_cases() -> Vec<PatientCase> {
     PATIENT_CASES.with(|cases| {
-        cases.borrow().values().cloned().collect()
+        cases.borrow().iter().map(|(_, case)| case).collect()
     })
 }
 
 pub fn get_patient_cases_by_status(status: CaseStatus) -> Vec<PatientCase> {
     PATIENT_CASES.with(|cases| {
         cases.borrow()
-            .values()
+            .iter()
+            .map(|(_, case)| case)
             .filter(|case| std::mem::discriminant(&case.status) == std::mem::discriminant(&status))
-            .cloned()
             .collect()
     })
 }
@@ -328,9 +1175,9 @@ pub fn get_patient_cases_by_status(status: CaseStatus) -> Vec<PatientCase> {
 pub fn get_patient_cases_by_patient(patient_id: &str) -> Vec<PatientCase> {
     PATIENT_CASES.with(|cases| {
         cases.borrow()
-            .values()
+            .iter()
+            .map(|(_, case)| case)
             .filter(|case| case.patient_id == patient_id)
-            .cloned()
             .collect()
     })
 }
@@ -344,7 +1191,7 @@ pub fn store_contribution_pool(pool: ContributionPool) {
 
 pub fn get_contribution_pool(pool_id: &str) -> Option<ContributionPool> {
     CONTRIBUTION_POOLS.with(|pools| {
-        pools.borrow().get(pool_id).cloned()
+        pools.borrow().get(&pool_id.to_string())
     })
 }
 
@@ -356,16 +1203,16 @@ pub fn update_contribution_pool(pool_id: &str, pool: ContributionPool) {
 
 pub fn get_all_contribution_pools() -> Vec<ContributionPool> {
     CONTRIBUTION_POOLS.with(|pools| {
-        pools.borrow().values().cloned().collect()
+        pools.borrow().iter().map(|(_, pool)| pool).collect()
     })
 }
 
 pub fn get_active_contribution_pools() -> Vec<ContributionPool> {
     CONTRIBUTION_POOLS.with(|pools| {
         pools.borrow()
-            .values()
+            .iter()
+            .map(|(_, pool)| pool)
             .filter(|pool| pool.is_active && !pool.is_completed)
-            .cloned()
             .collect()
     })
 }
@@ -373,9 +1220,9 @@ pub fn get_active_contribution_pools() -> Vec<ContributionPool> {
 pub fn get_contribution_pools_by_ngo(ngo_id: &str) -> Vec<ContributionPool> {
     CONTRIBUTION_POOLS.with(|pools| {
         pools.borrow()
-            .values()
+            .iter()
+            .map(|(_, pool)| pool)
             .filter(|pool| pool.ngo_id == ngo_id)
-            .cloned()
             .collect()
     })
 }
@@ -383,9 +1230,9 @@ pub fn get_contribution_pools_by_ngo(ngo_id: &str) -> Vec<ContributionPool> {
 pub fn get_pool_by_case_id(case_id: &str) -> Option<ContributionPool> {
     CONTRIBUTION_POOLS.with(|pools| {
         pools.borrow()
-            .values()
+            .iter()
+            .map(|(_, pool)| pool)
             .find(|pool| pool.case_id == case_id)
-            .cloned()
     })
 }
 
@@ -396,12 +1243,18 @@ pub fn store_contribution(contribution: Contribution) {
     });
 }
 
+pub fn get_contribution(contribution_id: &str) -> Option<Contribution> {
+    CONTRIBUTIONS.with(|contributions| {
+        contributions.borrow().get(&contribution_id.to_string())
+    })
+}
+
 pub fn get_contributions_by_pool(pool_id: &str) -> Vec<Contribution> {
     CONTRIBUTIONS.with(|contributions| {
         contributions.borrow()
-            .values()
+            .iter()
+            .map(|(_, contrib)| contrib)
             .filter(|contrib| contrib.pool_id == pool_id)
-            .cloned()
             .collect()
     })
 }
@@ -409,9 +1262,72 @@ pub fn get_contributions_by_pool(pool_id: &str) -> Vec<Contribution> {
 pub fn get_contributions_by_user(user_principal: &str) -> Vec<Contribution> {
     CONTRIBUTIONS.with(|contributions| {
         contributions.borrow()
-            .values()
+            .iter()
+            .map(|(_, contrib)| contrib)
             .filter(|contrib| contrib.contributor_principal == user_principal)
-            .cloned()
+            .collect()
+    })
+}
+
+pub fn get_contributions_with_pending_surplus() -> Vec<Contribution> {
+    CONTRIBUTIONS.with(|contributions| {
+        contributions.borrow()
+            .iter()
+            .map(|(_, contrib)| contrib)
+            .filter(|contrib| contrib.surplus.as_ref().is_some_and(ContributionSurplus::is_pending))
+            .collect()
+    })
+}
+
+pub fn get_contributions_with_failed_transfer() -> Vec<Contribution> {
+    CONTRIBUTIONS.with(|contributions| {
+        contributions.borrow()
+            .iter()
+            .map(|(_, contrib)| contrib)
+            .filter(Contribution::has_failed_transfer)
+            .collect()
+    })
+}
+
+// Disbursement functions
+pub fn store_disbursement(disbursement: Disbursement) {
+    DISBURSEMENTS.with(|disbursements| {
+        disbursements.borrow_mut().insert(disbursement.id.clone(), disbursement);
+    });
+}
+
+pub fn get_disbursement(disbursement_id: &str) -> Option<Disbursement> {
+    DISBURSEMENTS.with(|disbursements| {
+        disbursements.borrow().get(&disbursement_id.to_string())
+    })
+}
+
+pub fn get_disbursements_by_case(case_id: &str) -> Vec<Disbursement> {
+    DISBURSEMENTS.with(|disbursements| {
+        disbursements.borrow()
+            .iter()
+            .map(|(_, disbursement)| disbursement)
+            .filter(|disbursement| disbursement.case_id == case_id)
+            .collect()
+    })
+}
+
+pub fn get_disbursements_by_pool(pool_id: &str) -> Vec<Disbursement> {
+    DISBURSEMENTS.with(|disbursements| {
+        disbursements.borrow()
+            .iter()
+            .map(|(_, disbursement)| disbursement)
+            .filter(|disbursement| disbursement.pool_id == pool_id)
+            .collect()
+    })
+}
+
+pub fn get_disbursements_by_status(matches: impl Fn(&DisbursementStatus) -> bool) -> Vec<Disbursement> {
+    DISBURSEMENTS.with(|disbursements| {
+        disbursements.borrow()
+            .iter()
+            .map(|(_, disbursement)| disbursement)
+            .filter(|disbursement| matches(&disbursement.status))
             .collect()
     })
 }
@@ -420,7 +1336,8 @@ pub fn get_patient_prescriptions(patient_id_or_key: &str) -> Vec<Prescription> {
     // Supports matching against: patient_principal (preferred), patient_contact, or patient_name
     PRESCRIPTIONS.with(|pres| {
         pres.borrow()
-            .values()
+            .iter()
+            .map(|(_, p)| p)
             .filter(|p| {
                 // principal exact match
                 if let Some(pr) = &p.patient_principal {
@@ -438,7 +1355,62 @@ pub fn get_patient_prescriptions(patient_id_or_key: &str) -> Vec<Prescription> {
                 }
                 false
             })
-            .cloned()
             .collect()
     })
+}
+
+// Schema functions
+pub fn get_schema_version() -> u32 {
+    SCHEMA_VERSION.with(|version| *version.borrow().get())
+}
+
+pub fn set_schema_version(version: u32) {
+    SCHEMA_VERSION.with(|cell| {
+        cell.borrow_mut().set(version).expect("failed to persist schema version");
+    });
+}
+
+pub fn append_migration_record(record: MigrationRecord) {
+    MIGRATION_HISTORY.with(|history| {
+        let mut history = history.borrow_mut();
+        let next_index = history.len();
+        history.insert(next_index, record);
+    });
+}
+
+pub fn get_migration_history() -> Vec<MigrationRecord> {
+    MIGRATION_HISTORY.with(|history| {
+        history.borrow().iter().map(|(_, record)| record).collect()
+    })
+}
+
+// Re-encode every record of a map with the current schema version. Reading a
+// record runs it through its upgrade path, so writing it back is the migration.
+fn rewrite_all<V: Storable>(map: &mut StableBTreeMap<String, V, Memory>) -> u64 {
+    let entries: Vec<(String, V)> = map.iter().collect();
+    let count = entries.len() as u64;
+    for (key, value) in entries {
+        map.insert(key, value);
+    }
+    count
+}
+
+pub fn rewrite_users() -> u64 {
+    USERS.with(|users| rewrite_all(&mut users.borrow_mut()))
+}
+
+pub fn rewrite_prescriptions() -> u64 {
+    PRESCRIPTIONS.with(|prescriptions| rewrite_all(&mut prescriptions.borrow_mut()))
+}
+
+pub fn rewrite_patient_cases() -> u64 {
+    PATIENT_CASES.with(|cases| rewrite_all(&mut cases.borrow_mut()))
+}
+
+pub fn rewrite_medicines() -> u64 {
+    MEDICINES.with(|medicines| rewrite_all(&mut medicines.borrow_mut()))
+}
+
+pub fn rewrite_medicine_revisions() -> u64 {
+    MEDICINE_REVISIONS.with(|revisions| rewrite_all(&mut revisions.borrow_mut()))
 }
\ No newline at end of file
@@ -1,4 +1,4 @@
-use candid::{CandidType, Deserialize};
+use candid::{CandidType, Deserialize, Principal};
 
 #[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
 pub enum UserRole {
@@ -6,6 +6,7 @@ pub enum UserRole {
     Patient,
     Admin,
     NGO,
+    Pharmacy,
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
@@ -16,6 +17,37 @@ pub enum VerificationStatus {
     NotRequired,
 }
 
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq, Eq)]
+pub enum AdminPermission {
+    ManageUsers,
+    ReviewDoctors,
+    ReviewNGOs,
+    ReviewCases,
+    ManageSystem,
+    ViewAnalytics,
+}
+
+impl AdminPermission {
+    pub fn all() -> Vec<AdminPermission> {
+        vec![
+            AdminPermission::ManageUsers,
+            AdminPermission::ReviewDoctors,
+            AdminPermission::ReviewNGOs,
+            AdminPermission::ReviewCases,
+            AdminPermission::ManageSystem,
+            AdminPermission::ViewAnalytics,
+        ]
+    }
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct AdminPermissions {
+    pub admin_id: String,
+    pub permissions: Vec<AdminPermission>,
+    pub updated_at: u64,
+    pub updated_by: Option<String>,
+}
+
 #[derive(Clone, Debug, CandidType, Deserialize)]
 pub struct User {
     pub id: String,
@@ -30,6 +62,55 @@ pub struct User {
     pub last_active: Option<u64>,
     pub total_prescriptions: u64,
     pub total_medicines: u64,
+    pub health_profile: Option<HealthProfile>,
+}
+
+// Patient health profile, kept on the patient's own account and shared
+// with doctors only by consent
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub enum PregnancyStatus {
+    Unknown,
+    NotPregnant,
+    Pregnant,
+    Breastfeeding,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct Allergy {
+    pub substance: String,
+    pub reaction: String,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ExternalMedication {
+    pub name: String,
+    pub active_ingredients: Vec<String>,
+    pub notes: String,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct HealthProfile {
+    pub allergies: Vec<Allergy>,
+    pub chronic_conditions: Vec<String>,
+    pub pregnancy_status: PregnancyStatus,
+    pub external_medications: Vec<ExternalMedication>,
+    pub updated_at: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct HealthProfileInput {
+    pub allergies: Vec<Allergy>,
+    pub chronic_conditions: Vec<String>,
+    pub pregnancy_status: PregnancyStatus,
+    pub external_medications: Vec<ExternalMedication>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ProfileConsent {
+    pub patient_id: String,
+    pub doctor_id: String,
+    pub granted_at: u64,
+    pub expires_at: Option<u64>,
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize)]
@@ -49,10 +130,79 @@ pub struct VerificationRequest {
     pub status: VerificationStatus,
 }
 
+// Structured dosage
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub enum StrengthUnit {
+    Mg,
+    Mcg,
+    G,
+    Ml,
+    MgPerMl,
+    IU,
+    Percent,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub enum DoseForm {
+    Tablet,
+    Capsule,
+    Syrup,
+    Suspension,
+    Injection,
+    Cream,
+    Ointment,
+    Drops,
+    Inhaler,
+    Patch,
+    Suppository,
+    Other(String),
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub enum DoseRoute {
+    Oral,
+    Sublingual,
+    Topical,
+    Intravenous,
+    Intramuscular,
+    Subcutaneous,
+    Inhalation,
+    Ophthalmic,
+    Otic,
+    Nasal,
+    Rectal,
+    Other(String),
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub enum FrequencyPeriod {
+    Day,
+    Week,
+    Month,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct DoseFrequency {
+    pub times: u32,
+    pub period: FrequencyPeriod,
+    pub as_needed: bool,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct DosageSpec {
+    pub strength: f64,
+    pub unit: StrengthUnit,
+    pub form: DoseForm,
+    pub route: DoseRoute,
+    pub frequency: DoseFrequency,
+    pub duration_days: u32,
+}
+
 #[derive(Clone, Debug, CandidType, Deserialize)]
 pub struct Medicine {
     pub id: String,
     pub name: String,
+    // Human-readable text rendered from `dosage_spec`; legacy medicines only have these
     pub dosage: String,
     pub frequency: String,
     pub duration: String,
@@ -64,13 +214,77 @@ pub struct Medicine {
     pub created_by: String,
     pub doctor_id: String,
     pub is_active: bool,
+    pub active_ingredients: Vec<String>,
+    // None for medicines added before dosages were structured
+    pub dosage_spec: Option<DosageSpec>,
+    // Set on platform formulary entries, which no doctor owns
+    pub formulary: Option<FormularyDetails>,
+    // Latest entry in the medicine's revision history
+    pub current_revision: Option<u32>,
+}
+
+// Immutable snapshot of a medicine as of one write
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct MedicineRevision {
+    pub medicine_id: String,
+    pub revision: u32,
+    pub medicine: Medicine,
+    pub recorded_at: u64,
+    // Principal behind the write; None for snapshots backfilled from before history was kept
+    pub edited_by: Option<String>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct FormularyDetails {
+    pub generic_name: String,
+    pub brand_names: Vec<String>,
+    pub atc_code: String,
+    // Stable key used by bulk import to upsert; defaults to the medicine id
+    pub external_code: Option<String>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub enum MedicineSource {
+    Formulary,
+    Private,
+}
+
+// A doctor's own guide for a formulary medicine
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct MedicineOverlay {
+    pub doctor_id: String,
+    pub medicine_id: String,
+    pub guide_text: String,
+    pub guide_source: String,
+    pub updated_at: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct MedicineListing {
+    pub medicine: Medicine,
+    pub source: MedicineSource,
+    pub overlay: Option<MedicineOverlay>,
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize)]
 pub struct PrescriptionMedicine {
     pub medicine_id: String,
-    pub custom_dosage: Option<String>,
+    pub custom_dosage: Option<DosageSpec>,
     pub custom_instructions: String,
+    // Units to hand over; when absent any dispensed quantity completes the item
+    pub quantity: Option<u32>,
+    // Medicine revision pinned when the prescription was issued
+    pub medicine_revision: Option<u32>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub enum PrescriptionStatus {
+    Draft,
+    Issued,
+    PartiallyDispensed,
+    Dispensed,
+    Cancelled,
+    Expired,
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize)]
@@ -85,6 +299,276 @@ pub struct Prescription {
     pub created_at: u64,
     pub accessed_at: Option<u64>,
     pub doctor_id: String,
+    pub status: PrescriptionStatus,
+    pub status_updated_at: u64,
+    pub issued_at: Option<u64>,
+    pub expires_at: Option<u64>,
+    pub cancellation_reason: Option<String>,
+    pub refills: Option<PrescriptionRefills>,
+    // Set when the doctor prescribed despite a contraindicated interaction
+    pub interaction_override_reason: Option<String>,
+    // When `patient_principal` was bound by an explicit claim. Prescriptions
+    // linked before claims existed have a principal but no claim time.
+    pub claimed_at: Option<u64>,
+}
+
+// Another principal the claiming patient lets read their prescription
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct PrescriptionShare {
+    pub prescription_id: String,
+    pub principal: String,
+    pub granted_at: u64,
+}
+
+// Repeat-prescription allowance; fill 0 is the original dispensing
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct PrescriptionRefills {
+    pub refills_allowed: u32,
+    pub refills_remaining: u32,
+    pub min_interval_days: u64,
+    pub current_fill: u32,
+    pub current_fill_started_at: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct DispensedItem {
+    pub medicine_id: String,
+    pub quantity: u32,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct DispensingRecord {
+    pub id: String,
+    pub prescription_id: String,
+    pub prescription_code: String,
+    pub pharmacy_id: String,
+    pub pharmacy_name: String,
+    // None for records written before refills existed (the original fill)
+    pub fill_number: Option<u32>,
+    pub items: Vec<DispensedItem>,
+    pub notes: Option<String>,
+    pub dispensed_at: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum PrescriptionItemIssue {
+    UnknownMedicine,
+    InactiveMedicine,
+    NotPrescribable,
+    DuplicateEntry,
+    InvalidQuantity,
+    InvalidDosage,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct PrescriptionItemError {
+    pub index: u32,
+    pub medicine_id: String,
+    pub issue: PrescriptionItemIssue,
+    pub message: String,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum CreatePrescriptionError {
+    Rejected(String),
+    InvalidItems(Vec<PrescriptionItemError>),
+    ContraindicatedInteractions(Vec<InteractionWarning>),
+}
+
+impl From<String> for CreatePrescriptionError {
+    fn from(message: String) -> Self {
+        CreatePrescriptionError::Rejected(message)
+    }
+}
+
+// Append-only record of every attempt to read or act on a prescription
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub enum AccessOutcome {
+    Granted,
+    InvalidCode,
+    Refused(String),
+}
+
+impl AccessOutcome {
+    pub fn of<T>(result: &Result<T>) -> Self {
+        match result {
+            Ok(_) => AccessOutcome::Granted,
+            Err(message) => AccessOutcome::Refused(message.clone()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct PrescriptionAccessEntry {
+    pub id: u64,
+    pub prescription_id: String,
+    pub principal: String,
+    pub endpoint: String,
+    pub outcome: AccessOutcome,
+    pub timestamp: u64,
+}
+
+// Medication adherence. A schedule spreads one prescribed item's doses
+// evenly over its dosage period; dose n is due at start_at + n * interval.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct DoseSchedule {
+    pub id: String,
+    pub prescription_id: String,
+    pub medicine_id: String,
+    pub medicine_name: String,
+    pub patient_principal: String,
+    pub start_at: u64,
+    pub interval: u64,
+    pub total_doses: u64,
+    // As-needed medicines are logged but never reminded about
+    pub as_needed: bool,
+    pub next_reminder_dose: u64,
+    pub active: bool,
+    pub created_at: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub enum DoseStatus {
+    Taken,
+    Skipped,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct DoseLog {
+    pub schedule_id: String,
+    pub dose_index: u64,
+    pub due_at: u64,
+    pub status: DoseStatus,
+    pub note: Option<String>,
+    pub logged_at: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct DoseReminder {
+    pub id: String,
+    pub patient_principal: String,
+    pub schedule_id: String,
+    pub prescription_id: String,
+    pub medicine_name: String,
+    pub dose_index: u64,
+    pub due_at: u64,
+    pub created_at: u64,
+    pub read: bool,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ScheduleAdherence {
+    pub schedule_id: String,
+    pub medicine_id: String,
+    pub medicine_name: String,
+    pub doses_due: u64,
+    pub taken: u64,
+    pub skipped: u64,
+    pub missed: u64,
+    // Taken doses as a percentage of doses due; None before the first dose is due
+    pub adherence_percent: Option<f64>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct PrescriptionAdherence {
+    pub prescription_id: String,
+    pub schedules: Vec<ScheduleAdherence>,
+    pub doses_due: u64,
+    pub taken: u64,
+    pub skipped: u64,
+    pub missed: u64,
+    pub adherence_percent: Option<f64>,
+}
+
+// Drug interaction knowledge base
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub enum InteractionSeverity {
+    Minor,
+    Moderate,
+    Major,
+    Contraindicated,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct DrugInteraction {
+    pub ingredient_a: String,
+    pub ingredient_b: String,
+    pub severity: InteractionSeverity,
+    pub explanation: String,
+    pub source: String,
+    pub updated_at: u64,
+    pub updated_by: String,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct DrugInteractionInput {
+    pub ingredient_a: String,
+    pub ingredient_b: String,
+    pub severity: InteractionSeverity,
+    pub explanation: String,
+    pub source: String,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct InteractionWarning {
+    pub medicine_a_id: String,
+    pub medicine_a_name: String,
+    pub medicine_b_id: String,
+    pub medicine_b_name: String,
+    pub ingredient_a: String,
+    pub ingredient_b: String,
+    pub severity: InteractionSeverity,
+    pub explanation: String,
+}
+
+// Ingredients to avoid for a condition. Pregnancy and breastfeeding are
+// matched as the conditions "pregnancy" and "breastfeeding".
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ConditionContraindication {
+    pub condition: String,
+    pub ingredient: String,
+    pub severity: InteractionSeverity,
+    pub explanation: String,
+    pub source: String,
+    pub updated_at: u64,
+    pub updated_by: String,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ConditionContraindicationInput {
+    pub condition: String,
+    pub ingredient: String,
+    pub severity: InteractionSeverity,
+    pub explanation: String,
+    pub source: String,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub enum ProfileWarningKind {
+    Allergy,
+    Condition,
+    Pregnancy,
+    ExternalMedication,
+    // No live consent, so the profile could not be checked at all
+    NotShared,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ProfileWarning {
+    pub medicine_id: String,
+    pub medicine_name: String,
+    pub ingredient: String,
+    pub kind: ProfileWarningKind,
+    // The allergy, condition or external medication that matched
+    pub matched: String,
+    pub severity: InteractionSeverity,
+    pub message: String,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct CreatePrescriptionResponse {
+    pub prescription_code: String,
+    pub profile_warnings: Vec<ProfileWarning>,
 }
 
 // Request structures
@@ -105,15 +589,99 @@ pub struct RegisterUserWithPrincipalRequest {
     pub user_principal: String,
 }
 
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct CreateAdminRequest {
+    pub name: String,
+    pub email: String,
+    pub user_principal: String,
+    pub permissions: Vec<AdminPermission>,
+}
+
 #[derive(Clone, Debug, CandidType, Deserialize)]
 pub struct CreateMedicineRequest {
     pub name: String,
-    pub dosage: String,
-    pub frequency: String,
-    pub duration: String,
+    pub dosage: DosageSpec,
     pub side_effects: String,
     pub guide_text: String,
     pub guide_source: String,
+    pub active_ingredients: Vec<String>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct MedicineSearchRequest {
+    pub query: String,
+    pub active_only: bool,
+    pub offset: u64,
+    pub limit: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct MedicineSearchHit {
+    pub listing: MedicineListing,
+    pub score: u32,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct MedicineSearchResults {
+    pub total: u64,
+    pub hits: Vec<MedicineSearchHit>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct FormularyMedicineRequest {
+    pub external_code: Option<String>,
+    pub generic_name: String,
+    pub brand_names: Vec<String>,
+    pub atc_code: String,
+    pub active_ingredients: Vec<String>,
+    pub dosage: DosageSpec,
+    pub side_effects: String,
+    pub guide_text: String,
+    pub guide_source: String,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub enum FormularyFormat {
+    Csv,
+    Json,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct FormularyImportRequest {
+    pub format: FormularyFormat,
+    // One chunk of rows; CSV chunks each start with the header line
+    pub data: String,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub enum ImportRowOutcome {
+    Created,
+    Updated,
+    Rejected,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ImportRowResult {
+    pub row: u32,
+    pub external_code: Option<String>,
+    pub medicine_id: Option<String>,
+    pub outcome: ImportRowOutcome,
+    pub message: Option<String>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct FormularyImportReport {
+    pub created: u32,
+    pub updated: u32,
+    pub rejected: u32,
+    pub rows: Vec<ImportRowResult>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct FormularyExportChunk {
+    pub data: String,
+    pub total: u64,
+    pub next_offset: Option<u64>,
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize)]
@@ -122,6 +690,19 @@ pub struct CreatePrescriptionRequest {
     pub patient_contact: String,
     pub medicines: Vec<PrescriptionMedicine>,
     pub additional_notes: String,
+    pub as_draft: Option<bool>,
+    pub refills: Option<u32>,
+    pub refill_interval_days: Option<u64>,
+    pub interaction_override_reason: Option<String>,
+    // Registered patient whose shared health profile is checked
+    pub patient_id: Option<String>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct DispensePrescriptionRequest {
+    pub prescription_code: String,
+    pub items: Vec<DispensedItem>,
+    pub notes: Option<String>,
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize)]
@@ -141,6 +722,61 @@ pub struct ProcessVerificationRequest {
     pub admin_notes: Vec<String>,
 }
 
+// Audit log for privileged actions
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct AuditEntry {
+    pub id: u64,
+    pub actor_principal: String,
+    pub action: String,
+    pub target: String,
+    pub details: String,
+    pub timestamp: u64,
+}
+
+// Canister init/upgrade arguments
+#[derive(Clone, Debug, Default, CandidType, Deserialize)]
+pub struct InitArgs {
+    pub admin_principal: Option<Principal>,
+    pub admin_name: Option<String>,
+    pub admin_email: Option<String>,
+}
+
+// Platform-wide settings, editable by admins with ManageSystem
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct SystemSettings {
+    pub prescription_validity_days: u64,
+    // ICRC-2 ledger that contributions are paid through
+    pub ledger_canister_id: Option<Principal>,
+}
+
+impl Default for SystemSettings {
+    fn default() -> Self {
+        SystemSettings {
+            prescription_validity_days: 30,
+            ledger_canister_id: None,
+        }
+    }
+}
+
+// Schema / migration types
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct MigrationRecord {
+    pub from_version: u32,
+    pub to_version: u32,
+    pub applied_at: u64,
+    pub users_migrated: u64,
+    pub prescriptions_migrated: u64,
+    pub patient_cases_migrated: u64,
+    pub medicines_migrated: Option<u64>,
+    pub medicine_revisions_migrated: Option<u64>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct SchemaInfo {
+    pub current_version: u32,
+    pub migration_history: Vec<MigrationRecord>,
+}
+
 // Result types
 pub type Result<T> = std::result::Result<T, String>;
 
@@ -235,6 +871,46 @@ pub struct ContributionPool {
     pub deadline: Option<u64>,
     pub is_active: bool,
     pub is_completed: bool,
+    // Set by the settlement sweep once the deadline passes unfunded
+    pub expired_at: Option<u64>,
+    pub settlement: Option<PoolSettlement>,
+    // None on pools created before limits existed: no limits, surplus returned
+    pub rules: Option<ContributionRules>,
+    // Surplus kept in the pool's subaccount under `SurplusPolicy::Hold`
+    pub held_surplus: Option<u64>,
+}
+
+// What happens to tokens that arrive after a pool has reached its target
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub enum SurplusPolicy {
+    Return,
+    RedirectToNgoFund,
+    Hold,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ContributionRules {
+    pub min_contribution: Option<u64>,
+    // Across all of one contributor's contributions to the pool
+    pub max_per_contributor: Option<u64>,
+    pub surplus_policy: SurplusPolicy,
+}
+
+// What happens to the funds of a pool that expired before reaching its target
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub enum PoolSettlement {
+    // The NGO can ask to move the funds to another case until `refund_after`
+    AwaitingDecision { refund_after: u64 },
+    ReallocationRequested { target_pool_id: String, requested_at: u64 },
+    // The approved transfer to the target pool is in flight, or its outcome
+    // is unknown; approving again resends it unchanged
+    Reallocating { target_pool_id: String, requested_at: u64, amount: u64, fee: u64, created_at_time: u64 },
+    Reallocated { target_pool_id: String, ledger_block_index: u64 },
+    // Each contribution is paid back its share of `distributable`, which is
+    // the pool balance less surplus owed elsewhere and the fees for every
+    // refund transfer. Shares count held surplus as part of the contribution.
+    Refunding { distributable: u64, contributed: u64, fee: u64 },
+    Refunded,
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize)]
@@ -246,6 +922,99 @@ pub struct Contribution {
     pub message: Option<String>,
     pub contributed_at: u64,
     pub is_anonymous: bool,
+    // Ledger block of the transfer; None for contributions recorded before
+    // contributions moved real tokens
+    pub ledger_block_index: Option<u64>,
+    // How the contribution was settled if its pool expired unfunded
+    pub outcome: Option<ContributionOutcome>,
+    // Set when the pool filled up while the transfer was in flight and part
+    // of it did not fit
+    pub surplus: Option<ContributionSurplus>,
+    // `created_at_time` of the refund transfer, reused on every retry so the
+    // ledger deduplicates them instead of refunding twice
+    pub refund_created_at: Option<u64>,
+}
+
+// A donor's own view of their giving
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct DonorSummary {
+    pub total_given: u64,
+    pub total_refunded: u64,
+    pub cases_supported: Vec<SupportedCase>,
+    pub contributions: Vec<Contribution>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct SupportedCase {
+    pub case_id: String,
+    pub case_title: String,
+    pub pool_id: String,
+    pub pool_title: String,
+    pub amount_given: u64,
+    pub is_completed: bool,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ContributionSurplus {
+    pub amount: u64,
+    pub policy: SurplusPolicy,
+    // Block of the transfer returning or redirecting it
+    pub ledger_block_index: Option<u64>,
+    pub error: Option<String>,
+    // Fee and `created_at_time` of the transfer, kept so a retry after a
+    // failure sends the identical transfer
+    pub ledger_fee: Option<u64>,
+    pub transfer_created_at: Option<u64>,
+    // Set once the ledger refused the transfer for good or could no longer
+    // confirm it; it is not retried again until an admin asks for it
+    pub failed_at: Option<u64>,
+}
+
+impl ContributionSurplus {
+    /// Still to be returned or redirected; the settlement sweep retries it.
+    pub fn is_pending(&self) -> bool {
+        self.ledger_block_index.is_none() && self.transfer_created_at.is_some() && self.failed_at.is_none()
+    }
+
+    /// Refused by the ledger and waiting for an admin.
+    pub fn is_failed(&self) -> bool {
+        self.ledger_block_index.is_none() && self.failed_at.is_some()
+    }
+
+    /// Kept in the pool's subaccount, under `SurplusPolicy::Hold` or because
+    /// it was too small to move.
+    pub fn is_held(&self) -> bool {
+        self.ledger_block_index.is_none() && self.transfer_created_at.is_none() && self.failed_at.is_none()
+    }
+}
+
+impl Contribution {
+    /// Has a refund or surplus transfer the ledger refused, waiting for an admin.
+    pub fn has_failed_transfer(&self) -> bool {
+        matches!(self.outcome, Some(ContributionOutcome::RefundRejected(_)))
+            || self.surplus.as_ref().is_some_and(ContributionSurplus::is_failed)
+    }
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum ContributionOutcome {
+    Refunded { amount: u64, ledger_block_index: u64 },
+    // Retried on the next settlement sweep
+    RefundFailed(String),
+    // The ledger refused the refund for good or could no longer confirm it;
+    // an admin has to retry it or write it off before the pool counts as
+    // refunded
+    RefundRejected(String),
+    NotRefunded(String),
+    Reallocated { pool_id: String, contribution_id: String },
+}
+
+// Where a pool's funds are held on the ledger
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct PoolAccount {
+    pub ledger_canister_id: Principal,
+    pub owner: Principal,
+    pub subaccount: Vec<u8>,
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize)]
@@ -265,6 +1034,7 @@ pub struct CreatePoolRequest {
     pub pool_title: String,
     pub pool_description: String,
     pub deadline_days: Option<u64>,
+    pub rules: Option<ContributionRules>,
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize)]
@@ -273,6 +1043,8 @@ pub struct ContributeRequest {
     pub amount: u64,
     pub message: Option<String>,
     pub is_anonymous: bool,
+    // Contribute only what is left of the target instead of being refused
+    pub allow_partial: Option<bool>,
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize)]
@@ -280,4 +1052,77 @@ pub struct ProcessCaseRequest {
     pub case_id: String,
     pub status: CaseStatus,
     pub admin_notes: Option<String>,
-}
\ No newline at end of file
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub enum PayeeKind {
+    Patient,
+    Hospital,
+    Pharmacy,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct Invoice {
+    pub number: String,
+    pub issuer: String,
+    pub amount: u64,
+    pub document: String,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub enum DisbursementStatus {
+    Proposed,
+    // The ledger transfer is in flight; the amount is held against the pool
+    Transferring,
+    Paid,
+    // The ledger refused the transfer and nothing moved; it can be approved again
+    Failed(String),
+    // The transfer may have gone through; the amount stays held until another
+    // approval retries it with the same ledger timestamp
+    Unconfirmed(String),
+    // The ledger can no longer say whether the transfer went through; the
+    // amount stays held until an admin looks it up and reconciles it
+    NeedsReconciliation(String),
+    Rejected,
+    Cancelled,
+}
+
+// A payout from a pool's escrow subaccount, proposed by the pool's NGO and
+// released only once an admin approves it
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct Disbursement {
+    pub id: String,
+    pub case_id: String,
+    pub pool_id: String,
+    pub ngo_id: String,
+    pub payee_kind: PayeeKind,
+    pub payee_owner: Principal,
+    pub payee_subaccount: Option<Vec<u8>>,
+    pub amount: u64,
+    pub invoices: Vec<Invoice>,
+    pub notes: Option<String>,
+    pub status: DisbursementStatus,
+    pub proposed_at: u64,
+    pub reviewed_by: Option<String>,
+    pub reviewed_at: Option<u64>,
+    pub review_notes: Option<String>,
+    // Fee the ledger charged the pool subaccount for the transfer
+    pub ledger_fee: Option<u64>,
+    // `created_at_time` of the transfer, reused on retries so the ledger
+    // deduplicates them instead of paying twice
+    pub transfer_created_at: Option<u64>,
+    pub ledger_block_index: Option<u64>,
+    pub paid_at: Option<u64>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ProposeDisbursementRequest {
+    pub pool_id: String,
+    pub payee_kind: PayeeKind,
+    // Ignored for patient payouts, which go to the case's patient
+    pub payee_owner: Option<Principal>,
+    pub payee_subaccount: Option<Vec<u8>>,
+    pub amount: u64,
+    pub invoices: Vec<Invoice>,
+    pub notes: Option<String>,
+}
@@ -1,9 +1,13 @@
 use ic_cdk::api::{caller, time};
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
+use ic_cdk::api::management_canister::main::raw_rand;
+use sha2::{Digest, Sha256};
+use std::cell::{Cell, RefCell};
+use std::time::Duration;
 
 thread_local! {
-    static RNG_COUNTER: std::cell::Cell<u64> = std::cell::Cell::new(0);
+    // Seed from the management canister's raw_rand; refreshed on every init/upgrade
+    static RNG_SEED: RefCell<Option<[u8; 32]>> = const { RefCell::new(None) };
+    static RNG_COUNTER: Cell<u64> = const { Cell::new(0) };
 }
 
 /// Returns current canister time in nanoseconds (u64)
@@ -11,68 +15,156 @@ pub fn get_current_timestamp() -> u64 {
     time()
 }
 
-/// Lightweight, deterministic-ish unique id generator (u64)
-/// Combines nanoseconds timestamp, caller principal text, and a local counter.
-pub fn generate_random_id() -> u64 {
-    let ts = time();
-    let caller_txt = caller().to_text();
+/// Fetches fresh randomness from the management canister and uses it as the id generator seed.
+pub async fn seed_rng() {
+    match raw_rand().await {
+        Ok((bytes,)) if bytes.len() >= 32 => {
+            let mut seed = [0u8; 32];
+            seed.copy_from_slice(&bytes[..32]);
+            RNG_SEED.with(|s| *s.borrow_mut() = Some(seed));
+        }
+        Ok(_) => ic_cdk::println!("raw_rand returned too few bytes"),
+        Err((code, msg)) => ic_cdk::println!("raw_rand failed: {:?} {}", code, msg),
+    }
+}
+
+/// `raw_rand` can't be awaited from init/post_upgrade, so seed on the next round.
+pub fn schedule_rng_seed() {
+    ic_cdk_timers::set_timer(Duration::ZERO, || ic_cdk::spawn(seed_rng()));
+}
+
+pub fn is_rng_seeded() -> bool {
+    RNG_SEED.with(|s| s.borrow().is_some())
+}
+
+const RNG_NOT_READY: &str = "Secure randomness is not available yet, please retry";
 
-    let mut hash = ts;
-    for b in caller_txt.as_bytes() {
-        // FNV-1a style mixing
-        hash = hash.wrapping_mul(1099511628211).wrapping_add(*b as u64);
+/// Seeds the generator if the post-install seeding hasn't landed yet.
+pub async fn ensure_rng_seeded() -> Result<(), String> {
+    if !is_rng_seeded() {
+        seed_rng().await;
     }
 
+    if is_rng_seeded() {
+        Ok(())
+    } else {
+        Err(RNG_NOT_READY.to_string())
+    }
+}
+
+/// Next 32 bytes of the hash-based generator: SHA-256 over the raw_rand seed
+/// and a monotonically increasing counter, so every call in a round differs.
+/// Refuses to run unseeded rather than hand out guessable ids.
+fn next_random_bytes() -> Result<[u8; 32], String> {
+    let seed = RNG_SEED.with(|seed| *seed.borrow()).ok_or(RNG_NOT_READY.to_string())?;
     let counter = RNG_COUNTER.with(|c| {
         let v = c.get().wrapping_add(1);
         c.set(v);
         v
     });
 
-    hash ^ counter
+    let mut hasher = Sha256::new();
+    hasher.update(seed);
+    hasher.update(counter.to_le_bytes());
+    hasher.update(time().to_le_bytes());
+    hasher.update(caller().as_slice());
+
+    let mut out = [0u8; 32];
+    out.copy_from_slice(&hasher.finalize());
+    Ok(out)
+}
+
+pub fn generate_random_id() -> Result<u64, String> {
+    let bytes = next_random_bytes()?;
+    let mut head = [0u8; 8];
+    head.copy_from_slice(&bytes[..8]);
+    Ok(u64::from_le_bytes(head))
+}
+
+pub fn generate_id(prefix: &str) -> Result<String, String> {
+    Ok(format!("{}_{}", prefix, generate_random_id()?))
 }
 
-pub fn generate_id(prefix: &str) -> String {
-    let timestamp = time();
-    let caller = ic_cdk::caller().to_string();
-    
-    let mut hasher = DefaultHasher::new();
-    timestamp.hash(&mut hasher);
-    caller.hash(&mut hasher);
-    let hash = hasher.finish();
-    
-    format!("{}_{}", prefix, hash)
+/// Id shaped like `generate_id`'s but derived from `seed`, for records that
+/// must get the same id however often they are written.
+pub fn derive_id(prefix: &str, seed: &str) -> String {
+    let digest = Sha256::digest(seed.as_bytes());
+    let mut head = [0u8; 8];
+    head.copy_from_slice(&digest[..8]);
+    format!("{}_{}", prefix, u64::from_le_bytes(head))
 }
 
-pub fn generate_user_id() -> String {
+pub fn generate_user_id() -> Result<String, String> {
     generate_id("user")
 }
 
-pub fn generate_medicine_id() -> String {
+pub fn generate_medicine_id() -> Result<String, String> {
     generate_id("med")
 }
 
-pub fn generate_prescription_id() -> String {
+pub fn generate_prescription_id() -> Result<String, String> {
     generate_id("prescription")
 }
 
-pub fn generate_prescription_code() -> String {
-    let timestamp = get_current_timestamp();
-    let random = timestamp % 999999;
-    format!("RX{:06}", random)
+const PRESCRIPTION_CODE_PREFIX: &str = "RX";
+const PRESCRIPTION_CODE_DIGITS: u32 = 10;
+// Codes issued before check digits were `RX` and six timestamp digits
+const LEGACY_PRESCRIPTION_CODE_DIGITS: usize = 6;
+
+/// Luhn check digit over a string of ASCII digits.
+fn luhn_check_digit(digits: &str) -> u32 {
+    let sum: u32 = digits
+        .chars()
+        .rev()
+        .filter_map(|c| c.to_digit(10))
+        .enumerate()
+        .map(|(i, d)| {
+            if i % 2 == 0 {
+                let doubled = d * 2;
+                if doubled > 9 { doubled - 9 } else { doubled }
+            } else {
+                d
+            }
+        })
+        .sum();
+    (10 - sum % 10) % 10
 }
 
-pub fn generate_verification_hash() -> String {
-    let timestamp = time();
-    let caller = ic_cdk::caller().to_string();
+/// Random prescription code: `RX`, ten random digits and a Luhn check digit (e.g. RX48213096574).
+pub fn generate_prescription_code() -> Result<String, String> {
+    let value = generate_random_id()? % 10u64.pow(PRESCRIPTION_CODE_DIGITS);
+    let digits = format!("{:0width$}", value, width = PRESCRIPTION_CODE_DIGITS as usize);
+    Ok(format!("{}{}{}", PRESCRIPTION_CODE_PREFIX, digits, luhn_check_digit(&digits)))
+}
+
+/// Rejects mistyped codes before they hit storage. Codes issued before check
+/// digits existed (`RX` and six digits) are passed through to the lookup.
+pub fn validate_prescription_code(code: &str) -> Result<(), String> {
+    let body = match code.strip_prefix(PRESCRIPTION_CODE_PREFIX) {
+        Some(body) if !body.is_empty() && body.chars().all(|c| c.is_ascii_digit()) => body,
+        _ => return Err("Invalid prescription code: codes start with RX followed by digits".to_string()),
+    };
+
+    if body.len() == LEGACY_PRESCRIPTION_CODE_DIGITS {
+        return Ok(());
+    }
+    if body.len() != PRESCRIPTION_CODE_DIGITS as usize + 1 {
+        return Err(format!(
+            "Invalid prescription code: expected RX followed by {} digits, please re-check the code",
+            PRESCRIPTION_CODE_DIGITS + 1
+        ));
+    }
 
-    let mut hasher = DefaultHasher::new();
-    timestamp.hash(&mut hasher);
-    caller.hash(&mut hasher);
-    "verification".hash(&mut hasher);
-    let hash = hasher.finish();
+    let (digits, check) = body.split_at(PRESCRIPTION_CODE_DIGITS as usize);
+    if check.parse::<u32>().ok() == Some(luhn_check_digit(digits)) {
+        Ok(())
+    } else {
+        Err("Invalid prescription code: check digit does not match, please re-check the code".to_string())
+    }
+}
 
-    format!("verifhash{}", hash % 10000000) // Generate hash like verifhash7654653
+pub fn generate_verification_hash() -> Result<String, String> {
+    Ok(format!("verifhash{}", generate_random_id()? % 10000000)) // Generate hash like verifhash7654653
 }
 
 pub fn validate_email(email: &str) -> bool {
@@ -81,4 +173,55 @@ pub fn validate_email(email: &str) -> bool {
 
 pub fn validate_user_role(role: &str) -> bool {
     matches!(role.to_lowercase().as_str(), "doctor" | "patient")
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn code_for(digits: &str) -> String {
+        format!("{}{}{}", PRESCRIPTION_CODE_PREFIX, digits, luhn_check_digit(digits))
+    }
+
+    #[test]
+    fn luhn_check_digit_matches_reference_value() {
+        assert_eq!(luhn_check_digit("7992739871"), 3);
+        assert_eq!(luhn_check_digit("0000000000"), 0);
+    }
+
+    #[test]
+    fn accepts_well_formed_and_legacy_codes() {
+        assert!(validate_prescription_code(&code_for("4821309657")).is_ok());
+        assert!(validate_prescription_code("RX123456").is_ok());
+    }
+
+    #[test]
+    fn rejects_wrong_check_digit() {
+        let code = code_for("4821309657");
+        let last = code.chars().last().unwrap().to_digit(10).unwrap();
+        let typo = format!("{}{}", &code[..code.len() - 1], (last + 1) % 10);
+        assert!(validate_prescription_code(&typo).is_err());
+    }
+
+    #[test]
+    fn rejects_dropped_or_added_digits() {
+        let code = code_for("4821309657");
+        assert!(validate_prescription_code(&code[..code.len() - 1]).is_err());
+        assert!(validate_prescription_code(&format!("{}0", code)).is_err());
+    }
+
+    #[test]
+    fn derived_ids_are_stable_and_fit_a_ledger_memo() {
+        let id = derive_id("contrib", "contrib_1:pool_2");
+        assert_eq!(id, derive_id("contrib", "contrib_1:pool_2"));
+        assert_ne!(id, derive_id("contrib", "contrib_1:pool_3"));
+        assert!(id.starts_with("contrib_"));
+        assert!(id.len() <= 32);
+    }
+
+    #[test]
+    fn rejects_codes_without_prefix_or_with_letters() {
+        assert!(validate_prescription_code("48213096574").is_err());
+        assert!(validate_prescription_code("RX").is_err());
+        assert!(validate_prescription_code("RX4821309A574").is_err());
+    }
+}
@@ -12,6 +12,7 @@ import { useFavicon } from './useFavicon';
 import PatientCaseSubmission from '../features/patient/components/PatientCaseSubmission';
 import FAIcon from './FAIcon';
 import FallbackView from '../shared/components/FallbackView';
+import { formatCustomDosage } from '../utils/dosage';
 
 function PatientDashboard({ user, showAlert }) {
   useFavicon('/favicon.png');
@@ -101,7 +102,9 @@ function PatientDashboard({ user, showAlert }) {
       setRedirectedAfterClaim(true);
       // Put in history tab so user sees claimed entry
       setActiveTab('history');
-      showAlert && showAlert('success', 'Prescription claimed and added to your history.');
+      showAlert && showAlert('success', prescription.patient_principal?.length
+        ? 'Prescription claimed and added to your history.'
+        : 'Prescription added to your history. It has not been claimed for your account.');
       if (user) sessionUtils.saveSession(user, 'history');
     }
   }, [prescription, redirectedAfterClaim, user, showAlert]);
@@ -255,7 +258,7 @@ ${(medicines || []).map(m => `- ${m.medicine?.name || 'Unknown'} (${m.custom_dos
               const medicine = await authenticatedActor.get_medicine(med.medicine_id);
               return medicine ? {
                 ...medicine,
-                custom_dosage: med.custom_dosage,
+                custom_dosage: formatCustomDosage(med.custom_dosage),
                 custom_instructions: med.custom_instructions
               } : null;
             } catch (error) {
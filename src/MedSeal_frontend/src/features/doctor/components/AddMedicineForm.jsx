@@ -2,19 +2,27 @@ import { useState } from 'react';
 import { extractTextFromPDF, isPDF, getFileSize } from '../../../utils/ocrUtils';
 import Card from '../../../shared/components/Card';
 import Button from '../../../shared/components/Button';
+import { STRENGTH_UNITS, DOSE_FORMS, DOSE_ROUTES, FREQUENCY_PERIODS, toDosageSpec } from '../../../utils/dosage';
+
+const EMPTY_FORM = {
+  name: '',
+  strength: '',
+  unit: 'Mg',
+  form: 'Tablet',
+  route: 'Oral',
+  times: '1',
+  period: 'Day',
+  as_needed: false,
+  duration_days: '',
+  side_effects: '',
+  guide_file: null,
+  guide_text: '',
+  extracting: false,
+  extraction_progress: ''
+};
 
 function AddMedicineForm({ onSubmit, onTabChange, loading, showAlert }) {
-  const [medicineForm, setMedicineForm] = useState({
-    name: '',
-    dosage: '',
-    frequency: '',
-    duration: '',
-    side_effects: '',
-    guide_file: null,
-    guide_text: '',
-    extracting: false,
-    extraction_progress: ''
-  });
+  const [medicineForm, setMedicineForm] = useState(EMPTY_FORM);
 
   const [formErrors, setFormErrors] = useState({});
   const [isSubmitting, setIsSubmitting] = useState(false);
@@ -69,16 +77,19 @@ function AddMedicineForm({ onSubmit, onTabChange, loading, showAlert }) {
       errors.name = 'Medicine name is required';
     }
     
-    if (!medicineForm.dosage.trim()) {
-      errors.dosage = 'Dosage is required';
+    const strength = Number(medicineForm.strength);
+    if (!medicineForm.strength || !Number.isFinite(strength) || strength <= 0) {
+      errors.strength = 'Strength must be a positive number';
     }
     
-    if (!medicineForm.frequency.trim()) {
-      errors.frequency = 'Frequency is required';
+    const times = Number(medicineForm.times);
+    if (!Number.isInteger(times) || times < 1 || times > 24) {
+      errors.times = 'Frequency must be between 1 and 24 times per period';
     }
     
-    if (!medicineForm.duration.trim()) {
-      errors.duration = 'Duration is required';
+    const days = Number(medicineForm.duration_days);
+    if (!Number.isInteger(days) || days < 1 || days > 365) {
+      errors.duration_days = 'Duration must be between 1 and 365 days';
     }
     
     if (!medicineForm.side_effects.trim()) {
@@ -132,9 +143,7 @@ function AddMedicineForm({ onSubmit, onTabChange, loading, showAlert }) {
     try {
       const medicineData = {
         name: medicineForm.name.trim(),
-        dosage: medicineForm.dosage.trim(),
-        frequency: medicineForm.frequency.trim(),
-        duration: medicineForm.duration.trim(),
+        dosage: toDosageSpec(medicineForm),
         side_effects: medicineForm.side_effects.trim(),
         guide_text: medicineForm.guide_text.trim() || "No guide available",
         guide_source: medicineForm.guide_file ? medicineForm.guide_file.name : "Manual entry"
@@ -148,17 +157,7 @@ function AddMedicineForm({ onSubmit, onTabChange, loading, showAlert }) {
       
       if (success) {
         console.log('LOG: Medicine added successfully, clearing form');
-        setMedicineForm({
-          name: '',
-          dosage: '',
-          frequency: '',
-          duration: '',
-          side_effects: '',
-          guide_file: null,
-          guide_text: '',
-          extracting: false,
-          extraction_progress: ''
-        });
+        setMedicineForm(EMPTY_FORM);
         setFormErrors({});
         showAlert('success', 'Medicine added successfully!');
         
@@ -182,17 +181,7 @@ function AddMedicineForm({ onSubmit, onTabChange, loading, showAlert }) {
   };
 
   const clearForm = () => {
-    setMedicineForm({
-      name: '',
-      dosage: '',
-      frequency: '',
-      duration: '',
-      side_effects: '',
-      guide_file: null,
-      guide_text: '',
-      extracting: false,
-      extraction_progress: ''
-    });
+    setMedicineForm(EMPTY_FORM);
   };
 
   return (
@@ -236,58 +225,130 @@ function AddMedicineForm({ onSubmit, onTabChange, loading, showAlert }) {
               
               <div>
                 <label className="block text-sm font-medium text-gray-700 mb-2">
-                  Dosage <span className="text-red-500">*</span>
+                  Strength <span className="text-red-500">*</span>
                 </label>
-                <input
-                  type="text"
-                  className={`w-full px-3 py-2 border rounded-lg focus:ring-2 focus:ring-blue-500 focus:border-blue-500 ${
-                    formErrors.dosage ? 'border-red-300 bg-red-50' : 'border-gray-300 bg-white'
+                <div className="flex gap-2">
+                  <input
+                    type="number"
+                    min="0"
+                    step="any"
+                    className={`w-full px-3 py-2 border rounded-lg focus:ring-2 focus:ring-blue-500 focus:border-blue-500 ${
+                    formErrors.strength ? 'border-red-300 bg-red-50' : 'border-gray-300 bg-white'
                   }`}
-                  value={medicineForm.dosage}
-                  onChange={(e) => handleInputChange('dosage', e.target.value)}
-                  placeholder="e.g., 500mg"
-                  disabled={loading || isSubmitting}
-                />
-                {formErrors.dosage && (
-                  <p className="text-red-500 text-sm mt-1">{formErrors.dosage}</p>
+                    value={medicineForm.strength}
+                    onChange={(e) => handleInputChange('strength', e.target.value)}
+                    placeholder="e.g., 500"
+                    disabled={loading || isSubmitting}
+                  />
+                  <select
+                    className="px-3 py-2 border border-gray-300 bg-white rounded-lg focus:ring-2 focus:ring-blue-500 focus:border-blue-500"
+                    value={medicineForm.unit}
+                    onChange={(e) => handleInputChange('unit', e.target.value)}
+                    disabled={loading || isSubmitting}
+                  >
+                    {STRENGTH_UNITS.map(unit => (
+                      <option key={unit.value} value={unit.value}>{unit.label}</option>
+                    ))}
+                  </select>
+                </div>
+                {formErrors.strength && (
+                  <p className="text-red-500 text-sm mt-1">{formErrors.strength}</p>
                 )}
               </div>
               
+              <div className="grid grid-cols-2 gap-2">
+                <div>
+                  <label className="block text-sm font-medium text-gray-700 mb-2">
+                    Form <span className="text-red-500">*</span>
+                  </label>
+                  <select
+                    className="w-full px-3 py-2 border border-gray-300 bg-white rounded-lg focus:ring-2 focus:ring-blue-500 focus:border-blue-500"
+                    value={medicineForm.form}
+                    onChange={(e) => handleInputChange('form', e.target.value)}
+                    disabled={loading || isSubmitting}
+                  >
+                    {DOSE_FORMS.map(form => (
+                      <option key={form} value={form}>{form}</option>
+                    ))}
+                  </select>
+                </div>
+                <div>
+                  <label className="block text-sm font-medium text-gray-700 mb-2">
+                    Route <span className="text-red-500">*</span>
+                  </label>
+                  <select
+                    className="w-full px-3 py-2 border border-gray-300 bg-white rounded-lg focus:ring-2 focus:ring-blue-500 focus:border-blue-500"
+                    value={medicineForm.route}
+                    onChange={(e) => handleInputChange('route', e.target.value)}
+                    disabled={loading || isSubmitting}
+                  >
+                    {DOSE_ROUTES.map(route => (
+                      <option key={route} value={route}>{route}</option>
+                    ))}
+                  </select>
+                </div>
+              </div>
+              
               <div>
                 <label className="block text-sm font-medium text-gray-700 mb-2">
                   Frequency <span className="text-red-500">*</span>
                 </label>
-                <input
-                  type="text"
-                  className={`w-full px-3 py-2 border rounded-lg focus:ring-2 focus:ring-blue-500 focus:border-blue-500 ${
-                    formErrors.frequency ? 'border-red-300 bg-red-50' : 'border-gray-300 bg-white'
+                <div className="flex gap-2 items-center">
+                  <input
+                    type="number"
+                    min="1"
+                    max="24"
+                    className={`w-full px-3 py-2 border rounded-lg focus:ring-2 focus:ring-blue-500 focus:border-blue-500 ${
+                    formErrors.times ? 'border-red-300 bg-red-50' : 'border-gray-300 bg-white'
                   }`}
-                  value={medicineForm.frequency}
-                  onChange={(e) => handleInputChange('frequency', e.target.value)}
-                  placeholder="e.g., Twice daily"
-                  disabled={loading || isSubmitting}
-                />
-                {formErrors.frequency && (
-                  <p className="text-red-500 text-sm mt-1">{formErrors.frequency}</p>
+                    value={medicineForm.times}
+                    onChange={(e) => handleInputChange('times', e.target.value)}
+                    disabled={loading || isSubmitting}
+                  />
+                  <span className="text-sm text-gray-600 whitespace-nowrap">times per</span>
+                  <select
+                    className="px-3 py-2 border border-gray-300 bg-white rounded-lg focus:ring-2 focus:ring-blue-500 focus:border-blue-500"
+                    value={medicineForm.period}
+                    onChange={(e) => handleInputChange('period', e.target.value)}
+                    disabled={loading || isSubmitting}
+                  >
+                    {FREQUENCY_PERIODS.map(period => (
+                      <option key={period} value={period}>{period.toLowerCase()}</option>
+                    ))}
+                  </select>
+                </div>
+                <label className="flex items-center gap-2 mt-2 text-sm text-gray-600">
+                  <input
+                    type="checkbox"
+                    checked={medicineForm.as_needed}
+                    onChange={(e) => handleInputChange('as_needed', e.target.checked)}
+                    disabled={loading || isSubmitting}
+                  />
+                  As needed
+                </label>
+                {formErrors.times && (
+                  <p className="text-red-500 text-sm mt-1">{formErrors.times}</p>
                 )}
               </div>
               
               <div>
                 <label className="block text-sm font-medium text-gray-700 mb-2">
-                  Duration <span className="text-red-500">*</span>
+                  Duration (days) <span className="text-red-500">*</span>
                 </label>
                 <input
-                  type="text"
+                  type="number"
+                  min="1"
+                  max="365"
                   className={`w-full px-3 py-2 border rounded-lg focus:ring-2 focus:ring-blue-500 focus:border-blue-500 ${
-                    formErrors.duration ? 'border-red-300 bg-red-50' : 'border-gray-300 bg-white'
+                    formErrors.duration_days ? 'border-red-300 bg-red-50' : 'border-gray-300 bg-white'
                   }`}
-                  value={medicineForm.duration}
-                  onChange={(e) => handleInputChange('duration', e.target.value)}
-                  placeholder="e.g., 7 days"
+                  value={medicineForm.duration_days}
+                  onChange={(e) => handleInputChange('duration_days', e.target.value)}
+                  placeholder="e.g., 7"
                   disabled={loading || isSubmitting}
                 />
-                {formErrors.duration && (
-                  <p className="text-red-500 text-sm mt-1">{formErrors.duration}</p>
+                {formErrors.duration_days && (
+                  <p className="text-red-500 text-sm mt-1">{formErrors.duration_days}</p>
                 )}
               </div>
             </div>
@@ -62,6 +62,11 @@ function MedicineRepository({
                     }`}>
                       {medicine.is_active ? 'Active' : 'Inactive'}
                     </span>
+                    {medicine.is_formulary && (
+                      <span className="ml-2 px-2 py-1 text-xs font-medium rounded-full bg-blue-100 text-blue-800">
+                        Formulary
+                      </span>
+                    )}
                   </div>
                   <div className="flex gap-2">
                     {medicine.guide_text && (
@@ -81,13 +86,15 @@ function MedicineRepository({
                     >
                       <i className="fa-solid fa-plus text-sm" aria-hidden="true" />
                     </button>
-                    <button
-                      onClick={() => onToggleStatus(medicine.id, medicine.is_active)}
-                      className="p-2 text-gray-500 hover:text-yellow-600 hover:bg-yellow-50 rounded transition-colors"
-                      title={medicine.is_active ? 'Deactivate' : 'Activate'}
-                    >
-                      <i className={`fa-solid ${medicine.is_active ? 'fa-pause' : 'fa-play'}`} aria-hidden="true" />
-                    </button>
+                    {!medicine.is_formulary && (
+                      <button
+                        onClick={() => onToggleStatus(medicine.id, medicine.is_active)}
+                        className="p-2 text-gray-500 hover:text-yellow-600 hover:bg-yellow-50 rounded transition-colors"
+                        title={medicine.is_active ? 'Deactivate' : 'Activate'}
+                      >
+                        <i className={`fa-solid ${medicine.is_active ? 'fa-pause' : 'fa-play'}`} aria-hidden="true" />
+                      </button>
+                    )}
                   </div>
                 </div>
                 <div className="p-6">
@@ -13,6 +13,7 @@ function PrescriptionForm({
   const [prescriptionForm, setPrescriptionForm] = useState({
     patient_name: '',
     patient_contact: '',
+    patient_id: '',
     additional_notes: ''
   });
   const [searchTerm, setSearchTerm] = useState('');
@@ -47,6 +48,7 @@ function PrescriptionForm({
       setPrescriptionForm({
         patient_name: '',
         patient_contact: '',
+        patient_id: '',
         additional_notes: ''
       });
     }
@@ -116,6 +118,16 @@ function PrescriptionForm({
                       required
                     />
                   </div>
+                  <div>
+                    <label className="block text-sm font-medium text-gray-700 mb-2">Patient ID</label>
+                    <input
+                      type="text"
+                      className="w-full px-3 py-2 border border-gray-300 rounded-lg focus:ring-2 focus:ring-blue-500 focus:border-blue-500"
+                      value={prescriptionForm.patient_id}
+                      onChange={(e) => setPrescriptionForm({...prescriptionForm, patient_id: e.target.value.trim()})}
+                      placeholder="Optional: check against the patient's shared health profile"
+                    />
+                  </div>
                   <div>
                     <label className="block text-sm font-medium text-gray-700 mb-2">Additional Notes</label>
                     <textarea
@@ -368,13 +380,41 @@ function PrescriptionForm({
                           <label className="block text-sm font-medium text-gray-700 mb-1">
                             Custom Dosage (optional)
                           </label>
-                          <input
-                            type="text"
-                            className="w-full px-3 py-2 text-sm border border-gray-300 rounded-lg focus:ring-2 focus:ring-blue-500 focus:border-blue-500"
-                            value={medicine.custom_dosage || ''}
-                            onChange={(e) => onUpdateMedicine(medicine.id, 'custom_dosage', e.target.value)}
-                            placeholder="e.g., 250mg (override default)"
-                          />
+                          {medicine.dosage_spec && medicine.dosage_spec.length > 0 ? (
+                            <div className="grid grid-cols-3 gap-2">
+                              <input
+                                type="number"
+                                min="0"
+                                step="any"
+                                className="w-full px-3 py-2 text-sm border border-gray-300 rounded-lg focus:ring-2 focus:ring-blue-500 focus:border-blue-500"
+                                value={medicine.custom_strength || ''}
+                                onChange={(e) => onUpdateMedicine(medicine.id, 'custom_strength', e.target.value)}
+                                placeholder={`Strength (${medicine.dosage_spec[0].strength})`}
+                              />
+                              <input
+                                type="number"
+                                min="1"
+                                max="24"
+                                className="w-full px-3 py-2 text-sm border border-gray-300 rounded-lg focus:ring-2 focus:ring-blue-500 focus:border-blue-500"
+                                value={medicine.custom_times || ''}
+                                onChange={(e) => onUpdateMedicine(medicine.id, 'custom_times', e.target.value)}
+                                placeholder={`Times (${medicine.dosage_spec[0].frequency.times})`}
+                              />
+                              <input
+                                type="number"
+                                min="1"
+                                max="365"
+                                className="w-full px-3 py-2 text-sm border border-gray-300 rounded-lg focus:ring-2 focus:ring-blue-500 focus:border-blue-500"
+                                value={medicine.custom_duration_days || ''}
+                                onChange={(e) => onUpdateMedicine(medicine.id, 'custom_duration_days', e.target.value)}
+                                placeholder={`Days (${medicine.dosage_spec[0].duration_days})`}
+                              />
+                            </div>
+                          ) : (
+                            <p className="text-xs text-gray-500">
+                              This medicine has no structured dosage; note any change in the instructions below.
+                            </p>
+                          )}
                         </div>
                         <div>
                           <label className="block text-sm font-medium text-gray-700 mb-1">
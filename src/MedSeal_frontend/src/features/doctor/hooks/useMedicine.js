@@ -1,6 +1,14 @@
 import { useState, useEffect } from 'react';
 import { useAuth } from '../../../hooks/useAuth';
 
+// Formulary listings flattened into the medicine shape the UI already uses;
+// the doctor's own guide text replaces the platform guide when present
+const fromFormularyListing = (listing) => ({
+  ...listing.medicine,
+  guide_text: listing.overlay.length > 0 ? listing.overlay[0].guide_text : listing.medicine.guide_text,
+  is_formulary: true
+});
+
 export const useMedicine = (user, showAlert) => {
   const [medicines, setMedicines] = useState([]);
   const [loading, setLoading] = useState(false);
@@ -23,10 +31,16 @@ export const useMedicine = (user, showAlert) => {
       setLoading(true);
       console.log('LOG: Fetching medicines for user:', user.id);
       
-      const result = await authenticatedActor.get_doctor_medicines(user.id);
+      const [result, formulary] = await Promise.all([
+        authenticatedActor.get_doctor_medicines(user.id),
+        authenticatedActor.get_formulary()
+      ]);
       console.log('LOG: Fetched medicines result:', result);
       
-      setMedicines(Array.isArray(result) ? result : []);
+      setMedicines([
+        ...(Array.isArray(result) ? result : []),
+        ...(Array.isArray(formulary) ? formulary.map(fromFormularyListing) : [])
+      ]);
       console.log('LOG: Medicines state updated, count:', result?.length || 0);
     } catch (error) {
       console.error('LOG: Error fetching medicines:', error);
@@ -53,8 +67,11 @@ export const useMedicine = (user, showAlert) => {
     }
 
     // Validate required fields
-    const requiredFields = ['name', 'dosage', 'frequency', 'duration', 'side_effects'];
+    const requiredFields = ['name', 'side_effects'];
     const missingFields = requiredFields.filter(field => !medicineData[field] || !medicineData[field].trim());
+    if (!medicineData.dosage) {
+      missingFields.push('dosage');
+    }
     
     if (missingFields.length > 0) {
       console.error('LOG: Missing required fields:', missingFields);
@@ -67,15 +84,16 @@ export const useMedicine = (user, showAlert) => {
       console.log('LOG: Attempting to add medicine...');
       console.log('LOG: Medicine data being sent:', medicineData);
       
-      // Ensure all fields are strings and properly formatted
+      // Trim text fields; dosage is already a structured DosageSpec
       const cleanedData = {
         name: medicineData.name.trim(),
-        dosage: medicineData.dosage.trim(),
-        frequency: medicineData.frequency.trim(),
-        duration: medicineData.duration.trim(),
+        dosage: medicineData.dosage,
         side_effects: medicineData.side_effects.trim(),
         guide_text: medicineData.guide_text?.trim() || "No guide available",
-        guide_source: medicineData.guide_source?.trim() || "Manual entry"
+        guide_source: medicineData.guide_source?.trim() || "Manual entry",
+        active_ingredients: Array.isArray(medicineData.active_ingredients)
+          ? medicineData.active_ingredients
+          : (medicineData.active_ingredients || '').split(',').map(i => i.trim()).filter(Boolean)
       };
       
       console.log('LOG: Cleaned medicine data:', cleanedData);
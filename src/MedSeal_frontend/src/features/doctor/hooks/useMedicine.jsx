@@ -1,6 +1,14 @@
 import { useState, useEffect } from 'react';
 import { useAuth } from '../../../hooks/useAuth';
 
+// Formulary listings flattened into the medicine shape the UI already uses;
+// the doctor's own guide text replaces the platform guide when present
+const fromFormularyListing = (listing) => ({
+  ...listing.medicine,
+  guide_text: listing.overlay.length > 0 ? listing.overlay[0].guide_text : listing.medicine.guide_text,
+  is_formulary: true
+});
+
 export function useMedicine(user, showAlert) {
   const [medicines, setMedicines] = useState([]);
   const [loading, setLoading] = useState(false);
@@ -22,10 +30,16 @@ export function useMedicine(user, showAlert) {
     try {
       console.log('LOG: Loading medicines for user:', user.id);
       
-      const result = await authenticatedActor.get_doctor_medicines(user.id);
+      const [result, formulary] = await Promise.all([
+        authenticatedActor.get_doctor_medicines(user.id),
+        authenticatedActor.get_formulary()
+      ]);
       console.log('LOG: Loaded medicines result:', result);
       
-      setMedicines(Array.isArray(result) ? result : []);
+      setMedicines([
+        ...(Array.isArray(result) ? result : []),
+        ...(Array.isArray(formulary) ? formulary.map(fromFormularyListing) : [])
+      ]);
     } catch (error) {
       console.error('LOG: Error loading medicines:', error);
       showAlert('error', 'Error loading medicines: ' + error.message);
@@ -43,7 +57,10 @@ export function useMedicine(user, showAlert) {
     try {
       console.log('LOG: Adding medicine with data:', medicineData);
       
-      const result = await authenticatedActor.add_medicine(medicineData);
+      const result = await authenticatedActor.add_medicine({
+        ...medicineData,
+        active_ingredients: medicineData.active_ingredients || []
+      });
       console.log('LOG: Add medicine result:', result);
       
       if ('Ok' in result) {
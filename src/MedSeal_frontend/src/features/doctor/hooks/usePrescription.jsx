@@ -1,6 +1,24 @@
 import { useState, useEffect } from 'react';
 import { useAuth } from '../../../hooks/useAuth';
 
+// Overrides the medicine's own DosageSpec with whatever the doctor changed
+const buildCustomDosage = (med) => {
+  const base = med.dosage_spec && med.dosage_spec[0];
+  if (!base || (!med.custom_strength && !med.custom_times && !med.custom_duration_days)) {
+    return [];
+  }
+
+  return [{
+    ...base,
+    strength: med.custom_strength ? Number(med.custom_strength) : base.strength,
+    frequency: {
+      ...base.frequency,
+      times: med.custom_times ? Number(med.custom_times) : base.frequency.times
+    },
+    duration_days: med.custom_duration_days ? Number(med.custom_duration_days) : base.duration_days
+  }];
+};
+
 export function usePrescription(user, showAlert) {
   const [prescriptions, setPrescriptions] = useState([]);
   const [selectedMedicines, setSelectedMedicines] = useState([]);
@@ -26,7 +44,12 @@ export function usePrescription(user, showAlert) {
       const result = await authenticatedActor.get_doctor_prescriptions(user.id);
       console.log('LOG: Loaded prescriptions result:', result);
       
-      setPrescriptions(Array.isArray(result) ? result : []);
+      if ('Ok' in result) {
+        setPrescriptions(result.Ok);
+      } else {
+        setPrescriptions([]);
+        showAlert('error', 'Error loading prescriptions: ' + result.Err);
+      }
     } catch (error) {
       console.error('LOG: Error loading prescriptions:', error);
       showAlert('error', 'Error loading prescriptions: ' + error.message);
@@ -55,9 +78,16 @@ export function usePrescription(user, showAlert) {
         ...prescriptionData,
         medicines: selectedMedicines.map(med => ({
           medicine_id: med.id,
-          custom_dosage: med.custom_dosage && med.custom_dosage.trim() ? [med.custom_dosage.trim()] : [],
-          custom_instructions: med.custom_instructions || ''
-        }))
+          custom_dosage: buildCustomDosage(med),
+          custom_instructions: med.custom_instructions || '',
+          quantity: med.quantity ? [Number(med.quantity)] : [],
+          medicine_revision: []
+        })),
+        as_draft: [],
+        refills: prescriptionData.refills ? [Number(prescriptionData.refills)] : [],
+        refill_interval_days: prescriptionData.refill_interval_days ? [BigInt(prescriptionData.refill_interval_days)] : [],
+        interaction_override_reason: prescriptionData.interaction_override_reason ? [prescriptionData.interaction_override_reason] : [],
+        patient_id: prescriptionData.patient_id ? [prescriptionData.patient_id] : []
       };
       
       console.log('LOG: Final prescription data:', dataWithMedicines);
@@ -69,12 +99,26 @@ export function usePrescription(user, showAlert) {
         setSelectedMedicines([]);
         await loadPrescriptions();
         
-        const prescriptionCode = result.Ok;
+        const { prescription_code: prescriptionCode, profile_warnings: profileWarnings } = result.Ok;
         showAlert('success', `Prescription created successfully! Share this code with your patient: ${prescriptionCode}`);
+        if (profileWarnings.length > 0) {
+          showAlert('warning', 'Patient profile alerts: ' + profileWarnings
+            .map(w => ('NotShared' in w.kind ? w.message : `${w.medicine_name}: ${w.message}`))
+            .join('; '));
+        }
         return true;
       } else {
         console.error('LOG: Failed to create prescription:', result.Err);
-        showAlert('error', 'Error: ' + result.Err);
+        const err = result.Err;
+        let message = err.Rejected;
+        if ('InvalidItems' in err) {
+          message = err.InvalidItems.map(item => `Medicine #${item.index + 1}: ${item.message}`).join('; ');
+        } else if ('ContraindicatedInteractions' in err) {
+          message = 'Contraindicated combination: ' + err.ContraindicatedInteractions
+            .map(w => `${w.medicine_a_name} + ${w.medicine_b_name} (${w.explanation})`)
+            .join('; ') + '. Provide an override reason to prescribe anyway.';
+        }
+        showAlert('error', 'Error: ' + message);
         return false;
       }
     } catch (error) {
@@ -95,7 +139,9 @@ export function usePrescription(user, showAlert) {
     if (!selectedMedicines.find(m => m.id === medicine.id)) {
       setSelectedMedicines([...selectedMedicines, {
         ...medicine,
-        custom_dosage: '',
+        custom_strength: '',
+        custom_times: '',
+        custom_duration_days: '',
         custom_instructions: ''
       }]);
     }
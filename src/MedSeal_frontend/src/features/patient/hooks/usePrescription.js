@@ -1,5 +1,6 @@
 import { useState, useEffect } from 'react';
 import { useAuth } from '../../../hooks/useAuth';
+import { formatCustomDosage } from '../../../utils/dosage';
 
 // Helper function to safely convert BigInt to Number for timestamps
 const convertBigIntTimestamp = (timestamp) => {
@@ -236,6 +237,26 @@ export const usePrescription = (showAlert) => {
         return false;
       }
       
+      // Bind an unclaimed prescription to this account; once claimed, other
+      // principals can only read it if the patient shares access. Claiming
+      // can't be undone by the patient, so ask first.
+      const confirmClaim = () => window.confirm(
+        `This prescription is for ${prescriptionData.patient_name}. Claim it for your account? ` +
+        'Once claimed, only you and the people you share it with can open it.'
+      );
+      if (!prescriptionData.patient_principal?.length && confirmClaim()) {
+        try {
+          const claimResult = await authenticatedActor.claim_prescription(prescriptionData.id, prescriptionData.prescription_code);
+          if (claimResult && 'Ok' in claimResult) {
+            prescriptionData = claimResult.Ok;
+          } else if (claimResult && 'Err' in claimResult) {
+            showAlert('warning', claimResult.Err);
+          }
+        } catch (claimError) {
+          console.log('LOG: Error claiming prescription:', claimError);
+        }
+      }
+
       // Unwrap optional accessed_at if array
       prescriptionData.accessed_at = unwrapOptNat64(prescriptionData.accessed_at);
       console.log('LOG: Prescription fetched successfully:', prescriptionData);
@@ -251,6 +272,17 @@ export const usePrescription = (showAlert) => {
       // Set prescription first
       setPrescription(normalizedPrescription);
       
+      // Medicines exactly as the doctor approved them when the prescription was issued
+      let issuedRevisions = [];
+      try {
+        const revisionsResult = await authenticatedActor.get_prescription_medicine_revisions(prescriptionData.prescription_code);
+        if (revisionsResult && 'Ok' in revisionsResult) {
+          issuedRevisions = revisionsResult.Ok;
+        }
+      } catch (revisionError) {
+        console.log('LOG: Could not load issued medicine revisions:', revisionError);
+      }
+      
       // Get medicine details for each medicine in the prescription
       console.log('LOG: Starting to fetch medicine details for', prescriptionData.medicines.length, 'medicines');
       const medicinesWithDetails = await Promise.all(
@@ -258,22 +290,26 @@ export const usePrescription = (showAlert) => {
           try {
             console.log(`LOG: Fetching medicine ${index + 1}/${prescriptionData.medicines.length} for ID:`, prescriptionMedicine.medicine_id);
             
-            let medicineResult;
-            try {
-              // Try get_all_medicines first as it's more reliable
-              const allMedicines = await authenticatedActor.get_all_medicines();
-              console.log('LOG: Got all medicines, count:', allMedicines.length);
-              medicineResult = allMedicines.find(m => m.id === prescriptionMedicine.medicine_id);
-              console.log('LOG: Found medicine in all medicines:', !!medicineResult);
+            let medicineResult = issuedRevisions.find(r => r.medicine_id === prescriptionMedicine.medicine_id)?.medicine;
+            if (!medicineResult) {
+              try {
+                // Try get_all_medicines first as it's more reliable
+                const allMedicines = await authenticatedActor.get_all_medicines();
+                console.log('LOG: Got all medicines, count:', allMedicines.length);
+                medicineResult = allMedicines
+                  .map(listing => listing.medicine)
+                  .find(m => m.id === prescriptionMedicine.medicine_id);
+                console.log('LOG: Found medicine in all medicines:', !!medicineResult);
               
-              // If not found in all medicines, try direct lookup
-              if (!medicineResult) {
-                console.log('LOG: Medicine not found in all medicines, trying direct get_medicine');
-                medicineResult = await authenticatedActor.get_medicine?.(prescriptionMedicine.medicine_id);
+                // If not found in all medicines, try direct lookup
+                if (!medicineResult) {
+                  console.log('LOG: Medicine not found in all medicines, trying direct get_medicine');
+                  medicineResult = await authenticatedActor.get_medicine?.(prescriptionMedicine.medicine_id);
+                }
+              } catch (medicineError) {
+                console.log('LOG: Error fetching medicine:', medicineError);
+                medicineResult = null;
               }
-            } catch (medicineError) {
-              console.log('LOG: Error fetching medicine:', medicineError);
-              medicineResult = null;
             }
             
             console.log('LOG: Final medicine result for ID', prescriptionMedicine.medicine_id, ':', medicineResult);
@@ -295,7 +331,7 @@ export const usePrescription = (showAlert) => {
               
               return {
                 medicine_id: prescriptionMedicine.medicine_id,
-                custom_dosage: prescriptionMedicine.custom_dosage || null,
+                custom_dosage: formatCustomDosage(prescriptionMedicine.custom_dosage),
                 custom_instructions: prescriptionMedicine.custom_instructions || '',
                 medicine: {
                   id: normalizedMedicine.id,
@@ -313,7 +349,7 @@ export const usePrescription = (showAlert) => {
               console.warn('LOG: Medicine not found for ID:', prescriptionMedicine.medicine_id);
               return {
                 medicine_id: prescriptionMedicine.medicine_id,
-                custom_dosage: prescriptionMedicine.custom_dosage || null,
+                custom_dosage: formatCustomDosage(prescriptionMedicine.custom_dosage),
                 custom_instructions: prescriptionMedicine.custom_instructions || '',
                 medicine: null
               };
@@ -322,7 +358,7 @@ export const usePrescription = (showAlert) => {
             console.error('LOG: Error fetching medicine for ID:', prescriptionMedicine.medicine_id, error);
             return {
               medicine_id: prescriptionMedicine.medicine_id,
-              custom_dosage: prescriptionMedicine.custom_dosage || null,
+              custom_dosage: formatCustomDosage(prescriptionMedicine.custom_dosage),
               custom_instructions: prescriptionMedicine.custom_instructions || '',
               medicine: null
             };
@@ -327,15 +327,14 @@ export const AuthProvider = ({ children }) => {
       else if (userData.role === 'Admin') roleString = 'Admin';
       else if (userData.role === 'NGO') roleString = 'NGO';
       
-      // Use register_user_with_principal for explicit principal handling
+      // register_user binds the account to the authenticated caller's principal
       try {
-        console.log('LOG: Attempting registration with principal...');
-        const result = await authenticatedActor.register_user_with_principal({
+        console.log('LOG: Attempting registration for caller principal...');
+        const result = await authenticatedActor.register_user({
           name: userData.name,
           email: userData.email,
           role: { [roleString]: null }, // Use variant format including Admin support
-          license_number: userData.license_number || "",
-          user_principal: user_principal.trim().toLowerCase()
+          license_number: userData.license_number || ""
         });
         
         console.log('LOG: Registration with principal result:', result);
@@ -3,12 +3,11 @@ import { MedSeal_backend } from 'declarations/MedSeal_backend';
 export const addMedicine = async (medicineData) => {
     const payload = {
         name: medicineData.name,
-        dosage: medicineData.dosage,
-        frequency: medicineData.frequency,
-        duration: medicineData.duration,
+        dosage: medicineData.dosage, // Structured DosageSpec, see utils/dosage.js
         side_effects: medicineData.side_effects,
         guide_text: medicineData.guide_text || "No guide available", // Required field with fallback
         guide_source: medicineData.guide_source || "Manual entry", // Required field with fallback
+        active_ingredients: medicineData.active_ingredients || [],
     };
 
     try {
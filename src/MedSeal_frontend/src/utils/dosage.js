@@ -0,0 +1,71 @@
+// Helpers for the backend's structured DosageSpec (see shared/dosage.rs)
+
+export const STRENGTH_UNITS = [
+  { value: 'Mg', label: 'mg' },
+  { value: 'Mcg', label: 'mcg' },
+  { value: 'G', label: 'g' },
+  { value: 'Ml', label: 'ml' },
+  { value: 'MgPerMl', label: 'mg/ml' },
+  { value: 'IU', label: 'IU' },
+  { value: 'Percent', label: '%' }
+];
+
+export const DOSE_FORMS = [
+  'Tablet', 'Capsule', 'Syrup', 'Suspension', 'Injection', 'Cream',
+  'Ointment', 'Drops', 'Inhaler', 'Patch', 'Suppository'
+];
+
+export const DOSE_ROUTES = [
+  'Oral', 'Sublingual', 'Topical', 'Intravenous', 'Intramuscular', 'Subcutaneous',
+  'Inhalation', 'Ophthalmic', 'Otic', 'Nasal', 'Rectal'
+];
+
+export const FREQUENCY_PERIODS = ['Day', 'Week', 'Month'];
+
+const variantKey = (variant) => Object.keys(variant || {})[0];
+
+// Builds the candid DosageSpec record from plain form values
+export const toDosageSpec = ({ strength, unit, form, route, times, period, as_needed, duration_days }) => ({
+  strength: Number(strength),
+  unit: { [unit]: null },
+  form: { [form]: null },
+  route: { [route]: null },
+  frequency: {
+    times: Number(times),
+    period: { [period]: null },
+    as_needed: Boolean(as_needed)
+  },
+  duration_days: Number(duration_days)
+});
+
+// Human-readable summary in the same shape as dosage::describe_dosage on the backend
+export const formatDosageSpec = (spec) => {
+  if (!spec) return '';
+
+  const unitKey = variantKey(spec.unit);
+  const unit = STRENGTH_UNITS.find(u => u.value === unitKey)?.label || unitKey;
+  const formKey = variantKey(spec.form);
+  const form = formKey === 'Other' ? spec.form.Other : formKey;
+  const routeKey = variantKey(spec.route);
+  const route = routeKey === 'Other' ? spec.route.Other : routeKey;
+
+  const times = Number(spec.frequency.times);
+  const timesText = times === 1 ? 'once' : times === 2 ? 'twice' : `${times} times`;
+  const period = variantKey(spec.frequency.period).toLowerCase();
+  const frequency = spec.frequency.as_needed
+    ? `up to ${timesText} a ${period}, as needed`
+    : `${timesText} a ${period}`;
+
+  const days = Number(spec.duration_days);
+  const separator = unitKey === 'Percent' ? '' : ' ';
+
+  return `${spec.strength}${separator}${unit} ${form.toLowerCase()} (${route.toLowerCase()}), ${frequency} for ${days} day${days === 1 ? '' : 's'}`;
+};
+
+// custom_dosage arrives as a candid opt: [] or [DosageSpec]
+export const formatCustomDosage = (customDosage) => {
+  if (Array.isArray(customDosage)) {
+    return customDosage.length > 0 ? formatDosageSpec(customDosage[0]) : null;
+  }
+  return customDosage ? formatDosageSpec(customDosage) : null;
+};